@@ -0,0 +1,399 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+
+use tracing_subscriber::{
+    fmt::{self},
+    prelude::*,
+    filter::LevelFilter,
+};
+
+use crate::factory::*;
+use crate::json::*;
+use crate::replications::*;
+use crate::scheduler::*;
+use crate::snapshot::*;
+
+// Settings for a headless run, parsed from the command line
+pub struct BatchOptions
+{
+    pub config: String, // Config file name, looked up in the data directory
+    pub horizonUs: u128, // Simulated time to run for, in microseconds
+    pub seed: Option<u64>, // Overrides the config's seed when set
+    pub output: String, // Results file name, written to the data directory
+    pub snapshot: Option<String>, // Snapshot to resume from instead of starting the config at t=0
+    pub saveSnapshot: Option<String>, // Snapshot to write when the run ends
+    pub replications: Option<usize>, // Independent runs to summarize, with consecutive seeds
+    pub trace: Option<String>, // CSV of every named sensor reading, labelled with faults on their way
+}
+
+// Expects arguments in the form:
+// --batch factory.json --minutes 480 [--seed 42] [--output results.json]
+//     [--snapshot resume.json] [--save-snapshot end.json] [--replications 20] [--trace trace.csv]
+pub fn parseBatchArgs(args: &[String]) -> Result<BatchOptions>
+{
+    let mut config: Option<String> = None;
+    let mut minutes: Option<f64> = None;
+    let mut seed: Option<u64> = None;
+    let mut output: Option<String> = None;
+    let mut snapshot: Option<String> = None;
+    let mut saveSnapshot: Option<String> = None;
+    let mut replications: Option<usize> = None;
+    let mut trace: Option<String> = None;
+
+    let mut i = 0;
+    while i < args.len()
+    {
+        let flag = args[i].as_str();
+        let value = args.get(i + 1).ok_or_else(|| anyhow!("Missing value for {flag}."))?;
+        match flag
+        {
+            "--batch" => config = Some(value.clone()),
+            "--minutes" => minutes = Some(value.parse()?),
+            "--seed" => seed = Some(value.parse()?),
+            "--output" => output = Some(value.clone()),
+            "--snapshot" => snapshot = Some(value.clone()),
+            "--save-snapshot" => saveSnapshot = Some(value.clone()),
+            "--replications" => replications = Some(value.parse()?),
+            "--trace" => trace = Some(value.clone()),
+            _ => return Err(anyhow!("Unknown argument {flag}.")),
+        }
+        i += 2;
+    }
+
+    let config = config.ok_or_else(|| anyhow!("--batch needs a config file name."))?;
+    if snapshot.is_some() && seed.is_some()
+    {
+        return Err(anyhow!("--seed cannot be used with --snapshot, the snapshot carries its own RNG state."));
+    }
+    if replications.is_some() && (snapshot.is_some() || saveSnapshot.is_some())
+    {
+        return Err(anyhow!("--replications cannot be used with snapshots."));
+    }
+    if replications.is_some() && trace.is_some()
+    {
+        return Err(anyhow!("--trace cannot be used with --replications."));
+    }
+    if replications.is_some_and(|count| count < 2)
+    {
+        return Err(anyhow!("--replications needs at least 2 runs."));
+    }
+    let minutes = minutes.ok_or_else(|| anyhow!("--minutes is required in batch mode."))?;
+    if minutes <= 0.0
+    {
+        return Err(anyhow!("--minutes must be greater than 0."));
+    }
+
+    // Default to results named after the config, e.g. factory.json -> factory-results.json,
+    // or factory-replications.json for a replication summary
+    let output = output.unwrap_or_else(|| {
+        let stem = config.strip_suffix(".json").unwrap_or(&config);
+        match replications
+        {
+            Some(_) => format!("{stem}-replications.json"),
+            None => format!("{stem}-results.json"),
+        }
+    });
+
+    Ok(BatchOptions {
+        config,
+        horizonUs: (minutes * 60.0 * 1000000.0) as u128,
+        seed,
+        output,
+        snapshot,
+        saveSnapshot,
+        replications,
+        trace,
+    })
+}
+
+// Checks and loads the config, applying the seed override if there is one
+fn loadBatchConfig(options: &BatchOptions) -> Result<JSONData>
+{
+    let mut data = loadFactoryData(&options.config)?;
+    if options.seed.is_some()
+    {
+        data.factory.seed = options.seed;
+    }
+
+    Ok(data)
+}
+
+// Runs a config from t=0 to the horizon, jumping from event to event. Nothing here reads the
+// wall clock, so the same config and seed always produce the same results.
+// When resuming from a snapshot, the horizon is counted from the snapshot's time instead.
+pub fn runBatch(options: &BatchOptions) -> Result<RunStatistics>
+{
+    let mut factory = match &options.snapshot
+    {
+        Some(snapshot) => loadSnapshot(snapshot)?,
+        None => factorySetup(loadBatchConfig(options)?),
+    };
+
+    // Polls are scheduled events like everything else, so states debounce the same way
+    // they would on the server
+    let targetUs = factory.simTimeUs + options.horizonUs;
+    match &options.trace
+    {
+        Some(traceName) => {
+            let mut trace = BufWriter::new(File::create(dataPath(traceName))?);
+            writeln!(trace, "timeSec,machine,state,sensor,value,precursorFault,timeToFaultSec")?;
+            let mut traceResult = Ok(());
+            factory.runUntil(targetUs, |factory, event| {
+                if *event == EventKind::Poll && traceResult.is_ok()
+                {
+                    traceResult = writeTrace(&mut trace, factory);
+                }
+                false
+            });
+            traceResult?;
+            trace.flush()?;
+        },
+        None => {
+            factory.runUntil(targetUs, |_, _| false);
+        },
+    }
+
+    if let Some(saveName) = &options.saveSnapshot
+    {
+        saveSnapshot(&factory, saveName)?;
+    }
+
+    Ok(factory.statistics())
+}
+
+// One row per named sensor per poll, with the machine's state and the fault whose precursor shows
+// in the reading, if any, so models can be checked against what was really coming
+fn writeTrace(trace: &mut impl Write, factory: &Factory) -> std::io::Result<()>
+{
+    let timeSec = factory.simTimeUs as f64 / 1000000.0;
+    for id in factory.machineIDs.iter()
+    {
+        let machine = factory.machines.get(id).expect("Machine ceased to exist.").borrow();
+        let showing = machine.showingPrecursors();
+        for sensor in machine.sensors.iter()
+        {
+            // The fault closest to arriving, if more than one shows in this sensor
+            let label = showing.iter()
+                .filter(|(index, _, _)| machine.faults[*index].precursor.as_ref().is_some_and(|precursor| precursor.sensor == sensor.name))
+                .min_by_key(|(_, _, timeUs)| *timeUs);
+            let (fault, timeToFaultSec) = match label
+            {
+                Some((index, _, timeUs)) => (format!("\"{}\"", machine.faults[*index].faultMessage.replace('"', "\"\"")),
+                        (*timeUs as f64 / 1000000.0).to_string()),
+                None => (String::new(), String::new()),
+            };
+            writeln!(trace, "{},{},{},{},{},{},{}", timeSec, id, machine.state, sensor.name, sensor.value, fault, timeToFaultSec)?;
+        }
+    }
+    Ok(())
+}
+
+// Entry point for `--batch`, runs the simulation and writes the statistics as JSON
+pub fn batchMain(args: &[String]) -> Result<()>
+{
+    let options = parseBatchArgs(args)?;
+
+    // Per-item logging would dominate the run time, so only warnings and errors are shown
+    let stdoutLogger = fmt::layer()
+        .with_line_number(false)
+        .with_file(false)
+        .with_filter(LevelFilter::WARN);
+    let _subscriber = tracing_subscriber::registry()
+        .with(stdoutLogger)
+        .try_init();
+
+    if let Some(count) = options.replications
+    {
+        return replicationMain(&options, count);
+    }
+
+    let statistics = runBatch(&options)?;
+
+    let outputPath = dataPath(&options.output);
+    serde_json::to_writer_pretty(File::create(&outputPath)?, &statistics)?;
+
+    println!("Simulated {} seconds of {} (seed {})", statistics.simulatedTimeSec, options.config, statistics.seed);
+    if statistics.warmupSec > 0.0
+    {
+        println!("Counts cover the last {} seconds, after {} seconds of warm-up", statistics.measuredTimeSec, statistics.warmupSec);
+    }
+    for machine in statistics.machines.iter()
+    {
+        println!("{}: consumed {}, produced {}, state changes {}, faults {}, maintenance {}, availability {:.3}, quality {:.3}",
+                machine.id, machine.consumedCount, machine.producedCount, machine.stateChangeCount, machine.faultedCount, machine.maintenanceCount, machine.availability, machine.quality);
+    }
+    println!("Results written to {}", outputPath);
+
+    Ok(())
+}
+
+// Runs the config count times with consecutive seeds and writes the summary as JSON
+fn replicationMain(options: &BatchOptions, count: usize) -> Result<()>
+{
+    let data = loadBatchConfig(options)?;
+
+    // Without a seed anywhere, pick one so the whole set of replications can be repeated
+    let baseSeed = data.factory.seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+    let runs = runReplications(&data, options.horizonUs, count, baseSeed);
+    let report = summarizeReplications(&runs);
+
+    let outputPath = dataPath(&options.output);
+    serde_json::to_writer_pretty(File::create(&outputPath)?, &report)?;
+
+    println!("Ran {} replications of {} for {} seconds each (seeds {} to {})", report.replications, options.config,
+            report.simulatedTimeSec, baseSeed, baseSeed.wrapping_add(count as u64 - 1));
+    if report.warmupSec > 0.0
+    {
+        println!("Figures cover the last {} seconds, after {} seconds of warm-up", report.measuredTimeSec, report.warmupSec);
+    }
+    println!("Line: produced {}, faults {}", formatSummary(&report.line.producedCount), formatSummary(&report.line.faultedCount));
+    for machine in report.machines.iter()
+    {
+        println!("{}: produced {}, faults {}, faulted {} s", machine.id, formatSummary(&machine.producedCount),
+                formatSummary(&machine.faultedCount), formatSummary(&machine.faultedSec));
+    }
+    println!("Results written to {}", outputPath);
+
+    Ok(())
+}
+
+// eg. "986.2 ± 4.1 (95% CI 983.3 to 989.1)"
+fn formatSummary(summary: &Summary) -> String
+{
+    format!("{:.1} ± {:.1} (95% CI {:.1} to {:.1})", summary.mean, summary.stdDev, summary.ci95Low, summary.ci95High)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn args(line: &str) -> Vec<String>
+    {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn options(line: &str) -> BatchOptions
+    {
+        parseBatchArgs(&args(line)).expect("Arguments do not parse.")
+    }
+
+    // Statistics as JSON, so every figure is compared at once
+    fn runJSON(line: &str) -> String
+    {
+        let statistics = runBatch(&options(line)).expect("Batch run failed.");
+        serde_json::to_string(&statistics).expect("Statistics do not serialize.")
+    }
+
+    #[test]
+    fn parsesEveryOption()
+    {
+        let options = options("--batch line.json --minutes 2.5 --seed 7 --output out.json");
+        assert_eq!(options.config, "line.json");
+        assert_eq!(options.horizonUs, 150000000);
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.output, "out.json");
+    }
+
+    #[test]
+    fn defaultsSeedAndOutput()
+    {
+        let options = options("--batch line.json --minutes 1");
+        assert_eq!(options.seed, None);
+        assert_eq!(options.output, "line-results.json");
+    }
+
+    #[test]
+    fn rejectsBadArguments()
+    {
+        for line in [
+            "--minutes 1",
+            "--batch line.json",
+            "--batch line.json --minutes",
+            "--batch line.json --minutes ten",
+            "--batch line.json --minutes 0",
+            "--batch line.json --minutes -1",
+            "--batch line.json --minutes 1 --seed -1",
+            "--batch line.json --minutes 1 --speed 2",
+            "--batch line.json --minutes 1 --replications 1",
+            "--batch line.json --minutes 1 --replications 5 --snapshot resume.json",
+        ]
+        {
+            assert!(parseBatchArgs(&args(line)).is_err(), "accepted {line}");
+        }
+    }
+
+    #[test]
+    fn traceLabelsLineUpWithTheFault()
+    {
+        let data: JSONData = serde_json::from_value(serde_json::json!({ "factory": {
+            "name": "test", "description": "", "simSpeed": 1.0, "pollRateMs": 100, "debounceRateInPolls": 1, "seed": 1,
+            "conveyors": [],
+            "machines": [{
+                "id": "a", "cost": 1, "throughput": 1, "state": "PRODUCING",
+                "faults": [{ "faultChance": 1.0, "faultMessage": "Bearing \"B\" failed.", "faultTimeHighSec": 10.0, "faultTimeLowSec": 10.0,
+                    "failureClock": "CALENDAR",
+                    "precursor": { "sensor": "vibration", "leadTimeSec": 2.0, "pattern": "drift", "magnitude": 4.0 } }],
+                "inputIDs": [], "inputBehavior": "SPAWNER", "inputSpeedMs": 0, "inputCapacity": 1,
+                "processingBehavior": "DEFAULT", "processingSpeedMs": 1000,
+                "outputIDs": [], "outputBehavior": "CONSUMER", "outputSpeedMs": 0, "outputCapacity": 1,
+                "sensor": false, "sensorBaseline": 0.0, "sensorVariance": 0.0,
+                "sensors": [{ "name": "vibration", "model": "uniform", "baseline": 1.0 }]
+            }]
+        }})).expect("Test config does not parse.");
+        let mut factory = factorySetup(data);
+
+        let mut trace = Vec::<u8>::new();
+        factory.runUntil(120000000, |factory, event| {
+            if *event == EventKind::Poll
+            {
+                writeTrace(&mut trace, factory).expect("Trace does not write.");
+            }
+            factory.machines["a"].borrow().state == crate::machine::OPCState::FAULTED
+        });
+        let faultSec = factory.simTimeUs as f64 / 1000000.0;
+
+        let trace = String::from_utf8(trace).expect("Trace is not UTF-8.");
+        let labelled: Vec<Vec<&str>> = trace.lines().map(|row| row.splitn(6, ',').collect::<Vec<&str>>())
+            .filter(|row| !row[5].starts_with(','))
+            .collect();
+        // Two seconds of polls, less the one the fault was rolled on
+        assert!(labelled.len() >= 19 && labelled.len() <= 20, "{trace}");
+        for row in labelled
+        {
+            let (label, timeToFaultSec) = row[5].rsplit_once(',').expect("Row has no time to the fault.");
+            assert_eq!(label, "\"Bearing \"\"B\"\" failed.\"");
+            let timeSec: f64 = row[0].parse().unwrap();
+            let timeToFaultSec: f64 = timeToFaultSec.parse().unwrap();
+            assert!((timeSec + timeToFaultSec - faultSec).abs() < 1e-9, "{timeSec} + {timeToFaultSec} is not {faultSec}");
+            // The drift has grown in step with the time left
+            let value: f64 = row[4].parse().unwrap();
+            assert!((value - (1.0 + 4.0 * (1.0 - timeToFaultSec / 2.0))).abs() < 1e-9, "{value} at {timeToFaultSec}");
+        }
+    }
+
+    #[test]
+    fn runsExactlyToTheHorizon()
+    {
+        // Events rarely land on the horizon itself, so the clock must stop there rather than at the last event
+        let statistics = runBatch(&options("--batch factory.json --minutes 0.123 --seed 1")).expect("Batch run failed.");
+        assert_eq!(statistics.simulatedTimeSec, 7.38);
+    }
+
+    #[test]
+    fn sameSeedGivesTheSameRun()
+    {
+        let line = "--batch factory.json --minutes 10 --seed 42";
+        assert_eq!(runJSON(line), runJSON(line));
+    }
+
+    #[test]
+    fn differentSeedsGiveDifferentRuns()
+    {
+        assert_ne!(runJSON("--batch factory.json --minutes 10 --seed 1"), runJSON("--batch factory.json --minutes 10 --seed 2"));
+    }
+}
@@ -0,0 +1,241 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+use crate::json::*;
+
+// Formats accepted for dates and times in the config
+const DATETIME_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+pub const TIME_FORMAT: &str = "%H:%M";
+
+pub fn parseDateTime(text: &str) -> Option<NaiveDateTime>
+{
+    DATETIME_FORMATS.iter().find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+// Days of the week from the config, every day if left out
+pub fn parseDays(days: &Option<Vec<String>>) -> Vec<Weekday>
+{
+    match days
+    {
+        Some(days) => days.iter().map(|day| day.parse::<Weekday>().expect("Calendar day is not a day of the week.")).collect(),
+        None => vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun],
+    }
+}
+
+// A window of time that repeats on some days of the week, eg. a shift or a break.
+// A window that ends at or before its start runs past midnight into the next day.
+struct RecurringWindow
+{
+    name: String,
+    days: Vec<Weekday>, // Days the window starts on
+    start: NaiveTime,
+    end: NaiveTime,
+}
+impl RecurringWindow
+{
+    fn new(window: &JSONTimeWindow, defaultName: &str) -> RecurringWindow
+    {
+        RecurringWindow {
+            name: window.name.clone().unwrap_or(String::from(defaultName)),
+            days: parseDays(&window.days),
+            start: NaiveTime::parse_from_str(&window.start, TIME_FORMAT).expect("Calendar start is not a valid time."),
+            end: NaiveTime::parse_from_str(&window.end, TIME_FORMAT).expect("Calendar end is not a valid time."),
+        }
+    }
+
+    // Start and end of the window that starts on date, if it starts on that day at all
+    fn span(&self, date: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)>
+    {
+        if !self.days.contains(&date.weekday())
+        {
+            return None;
+        }
+
+        let start = date.and_time(self.start);
+        let mut end = date.and_time(self.end);
+        if end <= start
+        {
+            end += Duration::days(1);
+        }
+
+        Some((start, end))
+    }
+
+    fn contains(&self, time: NaiveDateTime) -> bool
+    {
+        // Only a window that started today or overnight from yesterday can cover this time
+        [time.date(), time.date() - Duration::days(1)].iter()
+            .filter_map(|date| self.span(*date))
+            .any(|(start, end)| start <= time && time < end)
+    }
+}
+
+// A one-off stop between two dates, for the whole line or only some machines
+struct PlannedStop
+{
+    reason: String,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    machineIDs: Option<Vec<String>>, // None stops every machine
+}
+
+// When the line is planned to run. Outside of every shift, during breaks, and during planned
+// stops, machines are put into planned stop. With no shifts at all the line runs around the clock.
+pub struct Calendar
+{
+    shifts: Vec<RecurringWindow>,
+    breaks: Vec<RecurringWindow>,
+    plannedStops: Vec<PlannedStop>,
+}
+impl Calendar
+{
+    pub fn new(calendar: &Option<JSONCalendar>) -> Calendar
+    {
+        let calendar = match calendar
+        {
+            Some(calendar) => calendar,
+            None => return Calendar { shifts: Vec::new(), breaks: Vec::new(), plannedStops: Vec::new() },
+        };
+
+        let mut plannedStops = Vec::<PlannedStop>::new();
+        for stop in calendar.plannedStops.iter()
+        {
+            plannedStops.push(PlannedStop {
+                reason: stop.reason.clone().unwrap_or(String::from("planned stop")),
+                // checkFactoryData turns away impossible dates
+                start: parseDateTime(&stop.start).unwrap(),
+                end: parseDateTime(&stop.end).unwrap(),
+                machineIDs: stop.machines.clone(),
+            });
+        }
+
+        Calendar {
+            shifts: calendar.shifts.iter().map(|shift| RecurringWindow::new(shift, "shift")).collect(),
+            breaks: calendar.breaks.iter().map(|window| RecurringWindow::new(window, "break")).collect(),
+            plannedStops,
+        }
+    }
+
+    // Why a machine is planned to be stopped at this time, None if it is planned to run
+    pub fn plannedStopReason(&self, machineID: &str, time: NaiveDateTime) -> Option<String>
+    {
+        if !self.shifts.is_empty() && !self.shifts.iter().any(|shift| shift.contains(time))
+        {
+            return Some(String::from("off shift"));
+        }
+
+        if let Some(window) = self.breaks.iter().find(|window| window.contains(time))
+        {
+            return Some(window.name.clone());
+        }
+
+        let stop = self.plannedStops.iter().find(|stop| {
+            let appliesToMachine = match &stop.machineIDs
+            {
+                Some(ids) => ids.iter().any(|id| id == machineID),
+                None => true,
+            };
+            appliesToMachine && stop.start <= time && time < stop.end
+        });
+
+        stop.map(|stop| stop.reason.clone())
+    }
+
+    // The first time after this one that any shift, break or planned stop starts or ends,
+    // None if the calendar never changes again
+    pub fn nextChange(&self, time: NaiveDateTime) -> Option<NaiveDateTime>
+    {
+        let mut boundaries = Vec::<NaiveDateTime>::new();
+
+        // Windows repeat every week, so one always starts or ends within the next 8 days
+        for window in self.shifts.iter().chain(self.breaks.iter())
+        {
+            for offset in -1..=7
+            {
+                if let Some((start, end)) = window.span(time.date() + Duration::days(offset))
+                {
+                    boundaries.push(start);
+                    boundaries.push(end);
+                }
+            }
+        }
+
+        for stop in self.plannedStops.iter()
+        {
+            boundaries.push(stop.start);
+            boundaries.push(stop.end);
+        }
+
+        boundaries.into_iter().filter(|boundary| *boundary > time).min()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn calendar(json: &str) -> Calendar
+    {
+        Calendar::new(&Some(serde_json::from_str(json).expect("Test calendar does not parse.")))
+    }
+
+    // 2024-01-01 is a Monday
+    fn at(text: &str) -> NaiveDateTime
+    {
+        parseDateTime(text).expect("Test time does not parse.")
+    }
+
+    #[test]
+    fn noCalendarNeverChanges()
+    {
+        let calendar = Calendar::new(&None);
+
+        assert_eq!(calendar.nextChange(at("2024-01-01T00:00")), None);
+        assert_eq!(calendar.plannedStopReason("machine-0", at("2024-01-01T03:00")), None);
+    }
+
+    #[test]
+    fn nextChangeIsStrictlyAfterNow()
+    {
+        let calendar = calendar(r#"{ "shifts": [{ "start": "06:00", "end": "14:00", "days": ["Mon", "Tue", "Wed", "Thu", "Fri"] }] }"#);
+
+        assert_eq!(calendar.nextChange(at("2024-01-01T05:00")), Some(at("2024-01-01T06:00")));
+        assert_eq!(calendar.nextChange(at("2024-01-01T06:00")), Some(at("2024-01-01T14:00")));
+        assert_eq!(calendar.plannedStopReason("machine-0", at("2024-01-01T06:00")), None);
+        assert_eq!(calendar.plannedStopReason("machine-0", at("2024-01-01T14:00")), Some(String::from("off shift")));
+    }
+
+    #[test]
+    fn nextChangeSkipsDaysWithoutShifts()
+    {
+        let calendar = calendar(r#"{ "shifts": [{ "start": "06:00", "end": "14:00", "days": ["Mon", "Tue", "Wed", "Thu", "Fri"] }] }"#);
+
+        // Friday afternoon to Monday morning
+        assert_eq!(calendar.nextChange(at("2024-01-05T15:00")), Some(at("2024-01-08T06:00")));
+    }
+
+    #[test]
+    fn overnightShiftEndsTheNextDay()
+    {
+        let calendar = calendar(r#"{ "shifts": [{ "start": "22:00", "end": "06:00", "days": ["Tue"] }] }"#);
+
+        assert_eq!(calendar.nextChange(at("2024-01-02T23:00")), Some(at("2024-01-03T06:00")));
+        assert_eq!(calendar.plannedStopReason("machine-0", at("2024-01-03T02:00")), None);
+        assert_eq!(calendar.plannedStopReason("machine-0", at("2024-01-03T07:00")), Some(String::from("off shift")));
+    }
+
+    #[test]
+    fn breaksAndPlannedStopsAreBoundaries()
+    {
+        let calendar = calendar(r#"{
+            "breaks": [{ "name": "lunch", "start": "12:00", "end": "12:30" }],
+            "plannedStops": [{ "reason": "changeover", "start": "2024-01-01T09:00", "end": "2024-01-01T10:00", "machines": ["machine-1"] }]
+        }"#);
+
+        assert_eq!(calendar.nextChange(at("2024-01-01T08:00")), Some(at("2024-01-01T09:00")));
+        assert_eq!(calendar.nextChange(at("2024-01-01T10:00")), Some(at("2024-01-01T12:00")));
+        assert_eq!(calendar.plannedStopReason("machine-0", at("2024-01-01T09:30")), None);
+        assert_eq!(calendar.plannedStopReason("machine-1", at("2024-01-01T09:30")), Some(String::from("changeover")));
+        assert_eq!(calendar.plannedStopReason("machine-0", at("2024-01-01T12:15")), Some(String::from("lunch")));
+    }
+}
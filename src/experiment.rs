@@ -0,0 +1,455 @@
+use std::fs::{File, metadata};
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
+use serde_json::Value;
+
+use tracing_subscriber::{
+    fmt::{self},
+    prelude::*,
+    filter::LevelFilter,
+};
+
+use crate::json::*;
+use crate::replications::*;
+use crate::factory::checkFactoryData;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Design
+{
+    #[default]
+    Grid, // Every combination of every parameter's values
+    LatinHypercube, // samples scenarios, spread evenly over each parameter's range
+}
+
+// One config field to vary, either over a list of values or over a min to max range
+#[derive(Deserialize)]
+pub struct Parameter
+{
+    pub path: String, // eg. machines[machine-1].processingSpeedMs or conveyors[conveyor-0].capacity
+    pub values: Option<Vec<Value>>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub steps: Option<usize>, // Evenly spaced values from min to max for a grid, 2 if left out
+}
+
+// An experiment file, read from the data directory
+#[derive(Deserialize)]
+pub struct Experiment
+{
+    pub config: String, // Base config that every scenario is a copy of
+    pub minutes: f64,
+    #[serde(default)]
+    pub design: Design,
+    pub samples: Option<usize>, // Scenarios to draw for a Latin hypercube
+    pub replications: Option<usize>, // Runs per scenario, 1 if left out
+    pub seed: Option<u64>, // Every scenario uses the same seeds, so they differ only by their parameters
+    pub parameters: Vec<Parameter>,
+}
+
+// Finds the field a path points to inside a config. Paths start inside "factory", and array
+// elements are picked by id or by index, eg. machines[machine-0].faults[1].faultChance
+fn fieldAt<'a>(config: &'a mut Value, path: &str) -> Result<&'a mut Value>
+{
+    let mut field = config.get_mut("factory").ok_or_else(|| anyhow!("Config has no factory."))?;
+
+    for segment in path.split('.')
+    {
+        let (name, key) = match segment.split_once('[')
+        {
+            Some((name, rest)) => (name, Some(rest.strip_suffix(']').ok_or_else(|| anyhow!("Unclosed [ in {path}."))?)),
+            None => (segment, None),
+        };
+
+        field = field.get_mut(name).ok_or_else(|| anyhow!("{path}: no field named {name}."))?;
+
+        if let Some(key) = key
+        {
+            let elements = field.as_array_mut().ok_or_else(|| anyhow!("{path}: {name} is not a list."))?;
+            let byID = elements.iter().position(|element| element.get("id").and_then(Value::as_str) == Some(key));
+            let index = match byID
+            {
+                Some(index) => index,
+                None => key.parse::<usize>().map_err(|_e| anyhow!("{path}: nothing in {name} has id {key}."))?,
+            };
+            field = elements.get_mut(index).ok_or_else(|| anyhow!("{path}: {name} has no element {key}."))?;
+        }
+    }
+
+    Ok(field)
+}
+
+// Numbers drawn from a range keep the type of the field they replace, so whole-number fields stay whole
+fn numberLike(original: &Value, number: f64) -> Value
+{
+    if original.is_u64() || original.is_i64()
+    {
+        return Value::from(number.round() as i64);
+    }
+
+    Value::from(number)
+}
+
+// Every value a grid uses for one parameter
+fn gridValues(parameter: &Parameter) -> Result<Vec<Value>>
+{
+    if let Some(values) = &parameter.values
+    {
+        return Ok(values.clone());
+    }
+
+    let (min, max) = parameterRange(parameter)?;
+    let steps = parameter.steps.unwrap_or(2).max(1);
+    if steps == 1
+    {
+        return Ok(vec![Value::from(min)]);
+    }
+
+    Ok((0..steps).map(|step| Value::from(min + (max - min) * step as f64 / (steps - 1) as f64)).collect())
+}
+
+fn parameterRange(parameter: &Parameter) -> Result<(f64, f64)>
+{
+    match (parameter.min, parameter.max)
+    {
+        (Some(min), Some(max)) if min <= max => Ok((min, max)),
+        (Some(_), Some(_)) => Err(anyhow!("{}: min is greater than max.", parameter.path)),
+        _ => Err(anyhow!("{}: needs either values or both min and max.", parameter.path)),
+    }
+}
+
+// Expands an experiment into its scenarios, each a list of one value per parameter
+pub fn expandDesign(experiment: &Experiment, rng: &mut ChaCha8Rng) -> Result<Vec<Vec<Value>>>
+{
+    // An empty list of values would leave no scenarios to run
+    if let Some(parameter) = experiment.parameters.iter().find(|parameter| parameter.values.as_ref().is_some_and(|values| values.is_empty()))
+    {
+        return Err(anyhow!("{}: values is empty.", parameter.path));
+    }
+
+    let mut scenarios: Vec<Vec<Value>> = vec![Vec::new()];
+
+    match experiment.design
+    {
+        Design::Grid => {
+            for parameter in experiment.parameters.iter()
+            {
+                let values = gridValues(parameter)?;
+                scenarios = scenarios.iter()
+                    .flat_map(|scenario| values.iter().map(move |value| {
+                        let mut scenario = scenario.clone();
+                        scenario.push(value.clone());
+                        scenario
+                    }))
+                    .collect();
+            }
+        },
+        Design::LatinHypercube => {
+            let samples = experiment.samples.ok_or_else(|| anyhow!("A Latin hypercube needs a number of samples."))?;
+            if samples == 0
+            {
+                return Err(anyhow!("A Latin hypercube needs at least 1 sample."));
+            }
+            scenarios = vec![Vec::new(); samples];
+
+            // Each parameter's range is cut into one slice per sample, every slice is used exactly
+            // once, and the order of the slices is shuffled independently for each parameter
+            for parameter in experiment.parameters.iter()
+            {
+                let mut strata: Vec<usize> = (0..samples).collect();
+                strata.shuffle(rng);
+
+                for (scenario, stratum) in scenarios.iter_mut().zip(strata)
+                {
+                    let position = (stratum as f64 + rng.gen::<f64>()) / samples as f64;
+                    let value = match &parameter.values
+                    {
+                        Some(values) => values[((position * values.len() as f64) as usize).min(values.len() - 1)].clone(),
+                        None => {
+                            let (min, max) = parameterRange(parameter)?;
+                            Value::from(min + (max - min) * position)
+                        },
+                    };
+                    scenario.push(value);
+                }
+            }
+        },
+    }
+
+    Ok(scenarios)
+}
+
+// Builds the config for one scenario from the base config, checked against the schema.
+// Also returns the values as they were written into the config, after any rounding.
+fn scenarioConfig(baseConfig: &Value, parameters: &[Parameter], values: &[Value]) -> Result<(JSONData, Vec<Value>)>
+{
+    let mut config = baseConfig.clone();
+    let mut applied = Vec::<Value>::new();
+    for (parameter, value) in parameters.iter().zip(values)
+    {
+        let field = fieldAt(&mut config, &parameter.path)?;
+        *field = match value.as_f64()
+        {
+            Some(number) => numberLike(field, number),
+            None => value.clone(),
+        };
+        applied.push(field.clone());
+    }
+
+    if !validateFactoryJSON(&config.to_string())
+    {
+        return Err(anyhow!("Scenario {:?} gives a config with invalid structure.", values));
+    }
+
+    let data: JSONData = serde_json::from_value(config)?;
+    checkFactoryData(&data).map_err(|error| anyhow!("Scenario {:?} gives a config where {}", values, error))?;
+
+    Ok((data, applied))
+}
+
+// Text for one value in the results table
+fn csvValue(value: &Value) -> String
+{
+    match value
+    {
+        Value::String(text) => format!("\"{}\"", text.replace('"', "\"\"")),
+        _ => value.to_string(),
+    }
+}
+
+// Entry point for `--experiment`, runs every scenario headless and writes a CSV results table
+pub fn experimentMain(args: &[String]) -> Result<()>
+{
+    let experimentFile = args.iter().skip_while(|arg| *arg != "--experiment").nth(1)
+        .ok_or_else(|| anyhow!("--experiment needs an experiment file name."))?;
+    let output = match args.iter().skip_while(|arg| *arg != "--output").nth(1)
+    {
+        Some(output) => output.clone(),
+        None => format!("{}-results.csv", experimentFile.strip_suffix(".json").unwrap_or(experimentFile)),
+    };
+
+    // Per-item logging would dominate the run time, so only warnings and errors are shown
+    let stdoutLogger = fmt::layer()
+        .with_line_number(false)
+        .with_file(false)
+        .with_filter(LevelFilter::WARN);
+    let _subscriber = tracing_subscriber::registry()
+        .with(stdoutLogger)
+        .try_init();
+
+    let experimentPath = dataPath(experimentFile);
+    if let Err(_e) = metadata(&experimentPath)
+    {
+        return Err(anyhow!("{} does not exist.", experimentPath));
+    }
+    let experiment: Experiment = serde_json::from_str(&read_json_file(&experimentPath))?;
+    if experiment.minutes <= 0.0
+    {
+        return Err(anyhow!("minutes must be greater than 0."));
+    }
+
+    let configPath = dataPath(&experiment.config);
+    if let Err(_e) = metadata(&configPath)
+    {
+        return Err(anyhow!("{} does not exist.", configPath));
+    }
+    let baseConfig: Value = serde_json::from_str(&read_json_file(&configPath))?;
+
+    // Without a seed, pick one so the whole experiment can be repeated
+    let seed = experiment.seed
+        .or_else(|| baseConfig["factory"]["seed"].as_u64())
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    let replications = experiment.replications.unwrap_or(1).max(1);
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let scenarios = expandDesign(&experiment, &mut rng)?;
+
+    // Every run of every scenario goes into one pool, so small scenarios still fill every core
+    let mut runs = Vec::<JSONData>::new();
+    let mut appliedValues = Vec::<Vec<Value>>::new();
+    for values in scenarios.iter()
+    {
+        let (data, applied) = scenarioConfig(&baseConfig, &experiment.parameters, values)?;
+        appliedValues.push(applied);
+        for replication in 0..replications
+        {
+            let mut runData = data.clone();
+            runData.factory.seed = Some(seed.wrapping_add(replication as u64));
+            runs.push(runData);
+        }
+    }
+
+    println!("Running {} scenarios of {}, {} replications each (seed {})", scenarios.len(), experiment.config, replications, seed);
+    let horizonUs = (experiment.minutes * 60.0 * 1000000.0) as u128;
+    let statistics = runParallel(&runs, horizonUs);
+
+    let outputPath = dataPath(&output);
+    let mut table = File::create(&outputPath)?;
+
+    let mut header = vec![String::from("scenario")];
+    header.extend(experiment.parameters.iter().map(|parameter| format!("\"{}\"", parameter.path)));
+    header.extend(["lineProducedMean", "lineProducedStdDev", "lineProducedCi95Low", "lineProducedCi95High",
+            "lineFaultsMean", "lineFaultsStdDev"].map(String::from));
+    for machine in statistics[0].machines.iter()
+    {
+        for figure in ["producedMean", "faultsMean", "maintenanceMean", "producingSecMean", "faultedSecMean", "maintenanceSecMean", "blockedSecMean", "starvedSecMean", "availabilityMean", "qualityMean"]
+        {
+            header.push(format!("{}.{}", machine.id, figure));
+        }
+    }
+    writeln!(table, "{}", header.join(","))?;
+
+    for (scenario, values) in appliedValues.iter().enumerate()
+    {
+        let report = summarizeReplications(&statistics[scenario * replications..(scenario + 1) * replications]);
+
+        let mut row = vec![scenario.to_string()];
+        row.extend(values.iter().map(csvValue));
+        let produced = &report.line.producedCount;
+        row.extend([produced.mean, produced.stdDev, produced.ci95Low, produced.ci95High,
+                report.line.faultedCount.mean, report.line.faultedCount.stdDev].map(|number| number.to_string()));
+        for machine in report.machines.iter()
+        {
+            row.extend([machine.producedCount.mean, machine.faultedCount.mean, machine.maintenanceCount.mean, machine.producingSec.mean,
+                    machine.faultedSec.mean, machine.maintenanceSec.mean, machine.blockedSec.mean, machine.starvedSec.mean, machine.availability.mean, machine.quality.mean].map(|number| number.to_string()));
+        }
+        writeln!(table, "{}", row.join(","))?;
+
+        println!("Scenario {}: {} -> line produced {:.1}", scenario,
+                values.iter().map(csvValue).collect::<Vec<String>>().join(", "), report.line.producedCount.mean);
+    }
+    println!("Results written to {}", outputPath);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use serde_json::json;
+
+    fn baseConfig() -> Value
+    {
+        json!({ "factory": {
+            "simSpeed": 1.0,
+            "machines": [
+                { "id": "machine-0", "processingSpeedMs": 1000, "faults": [{ "faultChance": 0.1 }, { "faultChance": 0.2 }] },
+                { "id": "machine-1", "processingSpeedMs": 2000, "faults": [] }
+            ],
+            "conveyors": [{ "id": "belt-0", "capacity": 5 }]
+        }})
+    }
+
+    fn experiment(fields: Value) -> Experiment
+    {
+        let mut experiment = json!({ "config": "factory.json", "minutes": 1.0, "parameters": [] });
+        for (key, value) in fields.as_object().expect("Experiment fields must be an object.")
+        {
+            experiment[key] = value.clone();
+        }
+        serde_json::from_value(experiment).expect("Test experiment does not parse.")
+    }
+
+    fn expand(experiment: &Experiment) -> Result<Vec<Vec<Value>>>
+    {
+        expandDesign(experiment, &mut ChaCha8Rng::seed_from_u64(1))
+    }
+
+    #[test]
+    fn fieldAtFindsElementsByIDOrIndex()
+    {
+        let mut config = baseConfig();
+        assert_eq!(*fieldAt(&mut config, "simSpeed").unwrap(), json!(1.0));
+        assert_eq!(*fieldAt(&mut config, "machines[machine-1].processingSpeedMs").unwrap(), json!(2000));
+        assert_eq!(*fieldAt(&mut config, "machines[0].faults[1].faultChance").unwrap(), json!(0.2));
+        assert_eq!(*fieldAt(&mut config, "conveyors[belt-0].capacity").unwrap(), json!(5));
+
+        *fieldAt(&mut config, "machines[machine-0].processingSpeedMs").unwrap() = json!(1500);
+        assert_eq!(config["factory"]["machines"][0]["processingSpeedMs"], json!(1500));
+    }
+
+    #[test]
+    fn fieldAtRejectsPathsThatLeadNowhere()
+    {
+        let mut config = baseConfig();
+        for path in ["speed", "machines[machine-9].processingSpeedMs", "machines[5].processingSpeedMs",
+                "simSpeed[0]", "machines[machine-0.processingSpeedMs"]
+        {
+            assert!(fieldAt(&mut config, path).is_err(), "found {path}");
+        }
+    }
+
+    #[test]
+    fn numberLikeKeepsWholeNumbersWhole()
+    {
+        assert_eq!(numberLike(&json!(1000), 1234.6), json!(1235));
+        assert_eq!(numberLike(&json!(0.5), 1234.6), json!(1234.6));
+    }
+
+    #[test]
+    fn gridCoversEveryCombination()
+    {
+        let experiment = experiment(json!({ "parameters": [
+            { "path": "a", "values": ["x", "y"] },
+            { "path": "b", "min": 0.0, "max": 10.0, "steps": 3 }
+        ]}));
+
+        assert_eq!(expand(&experiment).unwrap(), vec![
+            vec![json!("x"), json!(0.0)], vec![json!("x"), json!(5.0)], vec![json!("x"), json!(10.0)],
+            vec![json!("y"), json!(0.0)], vec![json!("y"), json!(5.0)], vec![json!("y"), json!(10.0)],
+        ]);
+    }
+
+    #[test]
+    fn gridRejectsBadRanges()
+    {
+        assert!(expand(&experiment(json!({ "parameters": [{ "path": "a", "min": 2.0, "max": 1.0 }] }))).is_err());
+        assert!(expand(&experiment(json!({ "parameters": [{ "path": "a", "min": 2.0 }] }))).is_err());
+    }
+
+    #[test]
+    fn emptyDesignsAreRejected()
+    {
+        assert!(expand(&experiment(json!({ "parameters": [{ "path": "a", "values": [] }] }))).is_err());
+        assert!(expand(&experiment(json!({ "design": "latinHypercube", "samples": 3, "parameters": [{ "path": "a", "values": [] }] }))).is_err());
+        assert!(expand(&experiment(json!({ "design": "latinHypercube", "samples": 0, "parameters": [{ "path": "a", "min": 0.0, "max": 1.0 }] }))).is_err());
+    }
+
+    #[test]
+    fn latinHypercubeUsesEveryStratumOnce()
+    {
+        let samples = 10;
+        let experiment = experiment(json!({ "design": "latinHypercube", "samples": samples, "parameters": [
+            { "path": "a", "min": 0.0, "max": 100.0 },
+            { "path": "b", "min": -1.0, "max": 1.0 }
+        ]}));
+        let scenarios = expand(&experiment).unwrap();
+        assert_eq!(scenarios.len(), samples);
+
+        for (parameter, (min, max)) in [(0.0, 100.0), (-1.0, 1.0)].into_iter().enumerate()
+        {
+            let mut strata: Vec<usize> = scenarios.iter()
+                .map(|scenario| ((scenario[parameter].as_f64().unwrap() - min) / (max - min) * samples as f64) as usize)
+                .collect();
+            strata.sort();
+            assert_eq!(strata, (0..samples).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn latinHypercubeUsesEveryListedValueOnce()
+    {
+        let experiment = experiment(json!({ "design": "latinHypercube", "samples": 3, "parameters": [
+            { "path": "a", "values": [1, 2, 3] }
+        ]}));
+        let mut values: Vec<i64> = expand(&experiment).unwrap().iter().map(|scenario| scenario[0].as_i64().unwrap()).collect();
+        values.sort();
+        assert_eq!(values, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,1711 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::cell::{RefCell, RefMut};
+use std::fs::metadata;
+
+use anyhow::{anyhow, Result};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::calendar::*;
+use crate::maintenance::*;
+use crate::machine::*;
+use crate::sensor::*;
+use crate::json::*;
+use crate::scheduler::*;
+use crate::servers::RunCondition;
+
+// Upper limit on zero-time update passes at one instant, see Factory::settle
+const MAX_SETTLE_PASSES: usize = 1000;
+
+// Where a seeded run without a startDateTime starts, a Monday, so its calendar is the same every run
+const SEEDED_START_DATETIME: &str = "2024-01-01T00:00:00";
+
+// One loaded line: every machine and conveyor, plus the factory-level settings
+// they were loaded with. Drivers (the real-time OPC loop, batch mode) own one of
+// these and advance it through time with runUntil().
+pub struct Factory
+{
+    pub name: String,
+    pub config: JSONData, // The config the line was built from, kept so snapshots can rebuild it
+    pub machines: HashMap<String, RefCell<Machine>>,
+    pub machineIDs: Vec<String>, // Track all IDs, this makes iterating over the hashmap easier
+    pub conveyors: HashMap<String, RefCell<ConveyorBelt>>,
+    pub conveyorIDs: Vec<String>,
+    pub simSpeed: f64,
+    pub pollRateUs: u128, // Server poll rate in microseconds
+    pub seed: u64,
+    pub simTimeUs: u128, // Simulated time since the start of the run, in microseconds
+    pub warmupUs: u128, // Statistics are reset once the line has run this long
+    pub statsStartUs: u128, // Simulated time the statistics have been counting from
+    pub startDateTime: NaiveDateTime, // Calendar date and time at the start of the run
+    pub simulatedTimestamps: bool, // OPC values are stamped with calendarTime() instead of the real time
+    pub calendar: Calendar,
+    pub technicians: Vec<TechnicianGroup>, // Empty if repairs start without waiting for anyone
+    pub maintenanceTimes: Vec<MaintenanceTime>, // Times of day that make calendar maintenance due
+    pub events: EventQueue,
+    pub zeroTimeLoopWarned: bool,
+}
+impl Factory
+{
+    // Handles every event up to and including targetUs, then moves the clock to targetUs.
+    // Time jumps straight from one event to the next, so nothing is computed while the
+    // line is waiting. onEvent is called after each event, which lets a driver react to
+    // polls (eg. pushing values to the OPC server). If onEvent returns true the run stops
+    // right there, leaving the clock at that event, and this returns true.
+    pub fn runUntil(&mut self, targetUs: u128, mut onEvent: impl FnMut(&mut Factory, &EventKind) -> bool) -> bool
+    {
+        while let Some(timeUs) = self.events.peekTime()
+        {
+            if timeUs > targetUs
+            {
+                break;
+            }
+
+            let event = self.processNextEvent();
+            if let Some(event) = event
+            {
+                if onEvent(self, &event)
+                {
+                    return true;
+                }
+            }
+        }
+
+        self.advanceTo(targetUs);
+        false
+    }
+
+    // Handles events until count machine or conveyor events have happened. Polls and the end
+    // of warm-up or calendar changes along the way are handled and passed to onEvent, but do not count as a step.
+    pub fn stepEvents(&mut self, count: u64, mut onEvent: impl FnMut(&mut Factory, &EventKind))
+    {
+        let mut stepped = 0;
+        while stepped < count
+        {
+            let event = self.processNextEvent();
+            match event
+            {
+                Some(EventKind::Poll) | Some(EventKind::WarmupEnd) | Some(EventKind::Calendar) => (),
+                Some(_) => stepped += 1,
+                None => return, // Nothing left that can ever happen
+            }
+            onEvent(self, event.as_ref().unwrap());
+        }
+    }
+
+    // Checks a run-until condition from the control panel against the line
+    pub fn isConditionMet(&self, condition: &RunCondition) -> bool
+    {
+        match condition
+        {
+            RunCondition::MachineState { machineID, state } => {
+                match self.machines.get(machineID)
+                {
+                    Some(machine) => machine.borrow().state == *state,
+                    None => false,
+                }
+            },
+            RunCondition::ProducedCount { machineID, count } => {
+                match self.machines.get(machineID)
+                {
+                    Some(machine) => machine.borrow().producedCount >= *count,
+                    None => false,
+                }
+            },
+        }
+    }
+
+    // Jumps to the earliest scheduled event and handles it, returning the event
+    pub fn processNextEvent(&mut self) -> Option<EventKind>
+    {
+        let (timeUs, event) = self.events.pop()?;
+        self.advanceTo(timeUs);
+
+        match event
+        {
+            // Polls are ordered after any machine or conveyor events at the same instant,
+            // so the line is already settled and only the states need checking
+            EventKind::Poll => {
+                self.pollStates();
+                self.events.schedule(EventKind::Poll, timeUs + self.pollRateUs.max(1));
+            },
+            // Also ordered after machine and conveyor events, so work finishing right at the
+            // end of warm-up is counted as part of warm-up
+            EventKind::WarmupEnd => self.resetStatistics(),
+            // Machines going into or out of planned stop can start or stop work at this instant.
+            // Maintenance is only marked due here, so a restart or reload cannot mark it twice.
+            EventKind::Calendar => {
+                self.markMaintenanceDue();
+                self.applyCalendar();
+                self.settle();
+            },
+            // Anything due at this instant completes here, no matter which event woke us up
+            _ => self.settle(),
+        }
+
+        Some(event)
+    }
+
+    // Moves every running clock forward to timeUs. Nothing completes here, since by
+    // construction no event falls between the current time and timeUs.
+    fn advanceTo(&mut self, timeUs: u128)
+    {
+        if timeUs <= self.simTimeUs
+        {
+            return;
+        }
+
+        let deltaTime = timeUs - self.simTimeUs;
+        for id in self.machineIDs.iter()
+        {
+            self.machines.get(id).expect("Machine ceased to exist.").borrow_mut().advanceClocks(deltaTime);
+        }
+        for id in self.conveyorIDs.iter()
+        {
+            self.conveyors.get(id).expect("Conveyor ceased to exist.").borrow_mut().advanceClocks(deltaTime);
+        }
+
+        self.simTimeUs = timeUs;
+    }
+
+    // Lets every machine and conveyor act at the current instant, repeating until none of
+    // them can do anything else (a machine outputting can free up a belt, which lets the
+    // machine upstream of it output, and so on), then schedules when each one next finishes.
+    pub fn settle(&mut self)
+    {
+        let mut passes = 0;
+        let mut changed = true;
+        while changed && passes < MAX_SETTLE_PASSES
+        {
+            changed = self.updatePass();
+            passes += 1;
+        }
+
+        // A loop of zero-time machines never settles, so give it a microsecond per lap
+        // rather than stalling the clock forever
+        let minimumDelay = if changed { 1 } else { 0 };
+        if changed && !self.zeroTimeLoopWarned
+        {
+            tracing::warn!("Line did not settle after {} passes, check for a loop of machines with 0ms speeds.", MAX_SETTLE_PASSES);
+            self.zeroTimeLoopWarned = true;
+        }
+
+        for id in self.machineIDs.iter()
+        {
+            let event = EventKind::Machine(id.clone());
+            match self.machines.get(id).expect("Machine ceased to exist.").borrow().nextEventUs()
+            {
+                Some(remainingUs) => self.events.schedule(event, self.simTimeUs + remainingUs.max(minimumDelay)),
+                None => self.events.cancel(&event),
+            }
+        }
+        for id in self.conveyorIDs.iter()
+        {
+            let event = EventKind::Conveyor(id.clone());
+            match self.conveyors.get(id).expect("Conveyor ceased to exist.").borrow().nextEventUs()
+            {
+                Some(remainingUs) => self.events.schedule(event, self.simTimeUs + remainingUs.max(minimumDelay)),
+                None => self.events.cancel(&event),
+            }
+        }
+    }
+
+    // One zero-time update of every machine, then every conveyor, returning true if anything changed
+    fn updatePass(&mut self) -> bool
+    {
+        let mut changed = false;
+
+        for id in self.machineIDs.iter()
+        {
+            changed |= self.machines.get(id)
+                    .unwrap_or_else(|| panic!("Machine {id} does not exist."))
+                    .borrow_mut()
+                    .update(&mut self.conveyors, 0);
+        }
+
+        for id in self.conveyorIDs.iter()
+        {
+            // Get reference to current conveyor
+            let mut conveyor = self.conveyors.get(id).unwrap_or_else(|| panic!("Conveyor {id} does not exist.")).borrow_mut();
+            // Initialize input conveyor to None by default, as most belts will NOT take input from other belts
+            let mut inputConveyor: Option<RefMut<ConveyorBelt>> = None;
+            // If the current conveyor has some value for inputID (conveyor to take from),
+            // get that conveyor as a reference and make it an option
+            if conveyor.isInputIDSome
+            {
+                let inputID = conveyor.inputID.as_ref().unwrap();
+                inputConveyor = Some(self.conveyors.get(inputID).unwrap_or_else(|| panic!("Conveyor {inputID} does not exist.")).borrow_mut());
+            }
+            changed |= conveyor.update(inputConveyor, 0);
+        }
+
+        changed |= self.assignTechnicians();
+
+        changed
+    }
+
+    // Sends free technicians to faulted machines, the machine that has waited longest first,
+    // returning true if any repair started. Busy technicians are counted from the machines
+    // they are repairing, so the pool never needs saving or restoring.
+    fn assignTechnicians(&mut self) -> bool
+    {
+        if self.technicians.is_empty()
+        {
+            return false;
+        }
+
+        let mut busy = HashMap::<String, usize>::new();
+        let mut waiting = Vec::<(u128, usize)>::new();
+        for (position, id) in self.machineIDs.iter().enumerate()
+        {
+            let machine = self.machines.get(id).expect("Machine ceased to exist.").borrow();
+            if let Some(technician) = &machine.technician
+            {
+                *busy.entry(technician.clone()).or_insert(0) += 1;
+            }
+            if machine.waitingForTechnician()
+            {
+                waiting.push((machine.technicianWaitClockUs, position));
+            }
+        }
+        // Longest wait first, ties go in config order
+        waiting.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        let mut assigned = false;
+        for (_, position) in waiting
+        {
+            let mut machine = self.machines.get(&self.machineIDs[position]).expect("Machine ceased to exist.").borrow_mut();
+            let skill = machine.jobSkill();
+            let group = self.technicians.iter()
+                .find(|group| group.canRepair(&skill) && *busy.get(&group.name).unwrap_or(&0) < group.count);
+            if let Some(group) = group
+            {
+                *busy.entry(group.name.clone()).or_insert(0) += 1;
+                let job = if machine.state == OPCState::MAINTENANCE { "maintenance" } else { "repairing" };
+                tracing::info!("ID {}: {} started {} after waiting {:.1} seconds.", machine.id, group.name, job, machine.technicianWaitClockUs as f64 / 1000000.0);
+                machine.technician = Some(group.name.clone());
+                assigned = true;
+            }
+        }
+
+        assigned
+    }
+
+    // Checks every machine for state updates, and reads its sensors. This happens once per poll rather
+    // than once per update, so that state debouncing is measured in poll cycles and not in loop iterations.
+    pub fn pollStates(&mut self)
+    {
+        for id in self.machineIDs.iter()
+        {
+            let mut machine = self.machines.get(id).expect("Machine ceased to exist.").borrow_mut();
+            machine.updateState();
+            machine.sampleSensors(self.simTimeUs);
+        }
+    }
+
+    // Calendar date and time at the current simulated time
+    pub fn calendarTime(&self) -> NaiveDateTime
+    {
+        self.startDateTime + Duration::microseconds(self.simTimeUs as i64)
+    }
+
+    // Tells every machine whether the calendar has it stopped right now, and schedules
+    // the next time that could change. Machines act on it the next time they update.
+    fn applyCalendar(&mut self)
+    {
+        let now = self.calendarTime();
+        for id in self.machineIDs.iter()
+        {
+            self.machines.get(id).expect("Machine ceased to exist.").borrow_mut().plannedStop = self.calendar.plannedStopReason(id, now);
+        }
+
+        let nextMaintenance = self.maintenanceTimes.iter().filter_map(|time| time.next(now)).min();
+        match self.calendar.nextChange(now).into_iter().chain(nextMaintenance).min()
+        {
+            Some(next) => {
+                let nextUs = (next - self.startDateTime).num_microseconds().expect("Calendar change is too far away.") as u128;
+                self.events.schedule(EventKind::Calendar, nextUs);
+            },
+            None => self.events.cancel(&EventKind::Calendar),
+        }
+    }
+
+    // Marks calendar maintenance that is scheduled for right now as due on its machine
+    fn markMaintenanceDue(&mut self)
+    {
+        let now = self.calendarTime();
+        for time in self.maintenanceTimes.iter().filter(|time| time.isAt(now))
+        {
+            let mut machine = self.machines.get(&time.machineID).expect("Machine ceased to exist.").borrow_mut();
+            machine.maintenancePlans[time.plan].calendarDue = true;
+        }
+    }
+
+    // Throws away every scheduled event and schedules them again from the line as it is now,
+    // used after the line's state has been replaced (a snapshot restore or a config reload)
+    pub fn restartEvents(&mut self, nextPollUs: u128)
+    {
+        self.events = EventQueue::new();
+        self.applyCalendar();
+        self.settle();
+        self.events.schedule(EventKind::Poll, nextPollUs);
+
+        // Warm-up is only pending if the statistics have not been reset yet
+        if self.warmupUs > 0 && self.statsStartUs < self.warmupUs
+        {
+            self.events.schedule(EventKind::WarmupEnd, self.warmupUs.max(self.simTimeUs));
+        }
+    }
+
+    // Applies a new config to the running line. Machines and conveyors are matched by ID: ones
+    // that are still in the config keep their counters, inventories, work in progress, faults
+    // and belt contents with the new settings applied, new ones start empty, and missing ones
+    // are dropped. The seed and the simulated clock carry on as they were.
+    pub fn reload(&mut self, mut data: JSONData) -> ReloadSummary
+    {
+        data.factory.seed = Some(self.seed);
+        data.factory.startDateTime = self.config.factory.startDateTime.clone();
+        let mut reloaded = factorySetup(data);
+        let mut summary = ReloadSummary::default();
+
+        for id in reloaded.machineIDs.iter()
+        {
+            match self.machines.get(id)
+            {
+                Some(old) => {
+                    reloaded.machines.get(id).expect("Machine ceased to exist.").borrow_mut().carryOver(&old.borrow());
+                    let oldConfig = self.config.factory.machines.iter().find(|machine| machine.id == *id);
+                    let newConfig = reloaded.config.factory.machines.iter().find(|machine| machine.id == *id);
+                    if oldConfig != newConfig
+                    {
+                        summary.changedMachines.push(id.clone());
+                    }
+                },
+                None => summary.addedMachines.push(id.clone()),
+            }
+        }
+        summary.removedMachines = self.machineIDs.iter().filter(|id| !reloaded.machines.contains_key(*id)).cloned().collect();
+
+        for id in reloaded.conveyorIDs.iter()
+        {
+            match self.conveyors.get(id)
+            {
+                Some(old) => {
+                    reloaded.conveyors.get(id).expect("Conveyor ceased to exist.").borrow_mut().carryOver(&old.borrow());
+                    let oldConfig = self.config.factory.conveyors.iter().find(|conveyor| conveyor.id == *id);
+                    let newConfig = reloaded.config.factory.conveyors.iter().find(|conveyor| conveyor.id == *id);
+                    if oldConfig != newConfig
+                    {
+                        summary.changedConveyors.push(id.clone());
+                    }
+                },
+                None => summary.addedConveyors.push(id.clone()),
+            }
+        }
+        summary.removedConveyors = self.conveyorIDs.iter().filter(|id| !reloaded.conveyors.contains_key(*id)).cloned().collect();
+
+        reloaded.simTimeUs = self.simTimeUs;
+        reloaded.statsStartUs = self.statsStartUs;
+        reloaded.restartEvents(self.simTimeUs + reloaded.pollRateUs.max(1));
+        *self = reloaded;
+
+        tracing::info!("Reloaded config at {} us: {:?}", self.simTimeUs, summary);
+
+        summary
+    }
+
+    // Zeroes every machine's counters, keeping the line's physical state (belt contents,
+    // inventories, work in progress, faults) so counting restarts from a filled line
+    pub fn resetStatistics(&mut self)
+    {
+        for id in self.machineIDs.iter()
+        {
+            self.machines.get(id).expect("Machine ceased to exist.").borrow_mut().resetStatistics();
+        }
+        self.statsStartUs = self.simTimeUs;
+
+        tracing::info!("Warm-up ended at {} us, statistics reset.", self.simTimeUs);
+    }
+
+    pub fn statistics(&self) -> RunStatistics
+    {
+        // Items leave the line through consumer machines, so what they produce is the line's output
+        let consumerIDs: Vec<&String> = self.config.factory.machines.iter()
+            .filter(|machine| machine.outputBehavior.to_lowercase() == "consumer")
+            .map(|machine| &machine.id)
+            .collect();
+
+        let mut machines = Vec::<MachineStatistics>::new();
+        let mut lineProducedCount = 0;
+        let mut lineProducedByType = BTreeMap::<String, usize>::new();
+        let mut lineGoodCount = 0;
+        let mut lineScrapCount = 0;
+        let mut lineFaultedCount = 0;
+        for id in self.machineIDs.iter()
+        {
+            let machine = self.machines.get(id).expect("Machine ceased to exist.").borrow();
+            if consumerIDs.contains(&id)
+            {
+                lineProducedCount += machine.producedCount;
+                for (productType, count) in machine.producedByType.iter()
+                {
+                    *lineProducedByType.entry(productType.clone()).or_insert(0) += count;
+                }
+                lineGoodCount += machine.goodCount;
+                lineScrapCount += machine.scrapCount;
+            }
+            lineFaultedCount += machine.faultedCount;
+
+            let times = &machine.stateTimes;
+            machines.push(MachineStatistics {
+                id: machine.id.clone(),
+                state: machine.state.to_string(),
+                consumedCount: machine.consumedCount,
+                producedCount: machine.producedCount,
+                producedByType: machine.producedByType.clone(),
+                goodCount: machine.goodCount,
+                scrapCount: machine.scrapCount,
+                quality: machine.quality(),
+                reworkedCount: machine.reworkedCount,
+                waitingForTechnicianSec: machine.waitingForTechnicianUs as f64 / 1000000.0,
+                maintenanceCount: machine.maintenanceCount,
+                wear: machine.wear.as_ref().map(|_| machine.wearLevel),
+                sensors: machine.sensors.iter().map(|sensor| (sensor.name.clone(), sensor.value)).collect(),
+                firstPassYield: machine.firstPass.iter()
+                    .map(|(productType, count)| (productType.clone(), count.passed as f64 / count.inspected as f64))
+                    .collect(),
+                stateChangeCount: machine.stateChangeCount,
+                faultedCount: machine.faultedCount,
+                producingSec: times.producingUs as f64 / 1000000.0,
+                faultedSec: times.faultedUs as f64 / 1000000.0,
+                blockedSec: times.blockedUs as f64 / 1000000.0,
+                starvedSec: times.starvedUs as f64 / 1000000.0,
+                starvedBlockedSec: times.starvedBlockedUs as f64 / 1000000.0,
+                plannedStopSec: times.plannedStopUs as f64 / 1000000.0,
+                maintenanceSec: times.maintenanceUs as f64 / 1000000.0,
+                availability: times.availability(),
+                laneStarvedSec: machine.billOfMaterials.iter().map(|lane| (lane.inputID.clone(), lane.starvedUs as f64 / 1000000.0)).collect(),
+            });
+        }
+
+        RunStatistics {
+            factory: self.name.clone(),
+            seed: self.seed,
+            lineProducedCount,
+            lineProducedByType,
+            lineGoodCount,
+            lineScrapCount,
+            lineFaultedCount,
+            simulatedTimeSec: self.simTimeUs as f64 / 1000000.0,
+            warmupSec: self.statsStartUs as f64 / 1000000.0,
+            measuredTimeSec: (self.simTimeUs - self.statsStartUs) as f64 / 1000000.0,
+            machines,
+        }
+    }
+
+    pub fn logStatistics(&self)
+    {
+        for id in self.machineIDs.iter()
+        {
+            let machine = self.machines.get(id).expect("Machine ceased to exist.").borrow();
+            let times = &machine.stateTimes;
+            tracing::info!("\nMachine: {}\nConsumed: {}\nProduced: {}\nState Changes: {}\nFaults: {}\nSeconds Producing/Faulted/Blocked/Starved/Starved and Blocked/Planned Stop/Maintenance: {:.1}/{:.1}/{:.1}/{:.1}/{:.1}/{:.1}/{:.1}\nWaiting for Technician: {:.1}\nMaintenance Done: {}\nAvailability: {:.3}\nGood/Scrap: {}/{}",
+                    machine.id, machine.consumedCount, machine.producedCount, machine.stateChangeCount, machine.faultedCount,
+                    times.producingUs as f64 / 1000000.0, times.faultedUs as f64 / 1000000.0, times.blockedUs as f64 / 1000000.0,
+                    times.starvedUs as f64 / 1000000.0, times.starvedBlockedUs as f64 / 1000000.0, times.plannedStopUs as f64 / 1000000.0,
+                    times.maintenanceUs as f64 / 1000000.0, machine.waitingForTechnicianUs as f64 / 1000000.0, machine.maintenanceCount, times.availability(), machine.goodCount, machine.scrapCount);
+        }
+    }
+}
+
+// What a config reload changed, by ID
+#[derive(Debug, Default)]
+pub struct ReloadSummary
+{
+    pub addedMachines: Vec<String>,
+    pub removedMachines: Vec<String>,
+    pub changedMachines: Vec<String>,
+    pub addedConveyors: Vec<String>,
+    pub removedConveyors: Vec<String>,
+    pub changedConveyors: Vec<String>,
+}
+
+// End-of-run counters for one machine
+#[derive(Serialize)]
+pub struct MachineStatistics
+{
+    pub id: String,
+    pub state: String,
+    pub consumedCount: usize,
+    pub producedCount: usize,
+    pub producedByType: BTreeMap<String, usize>,
+    pub goodCount: usize,
+    pub scrapCount: usize, // Defective products made, including ones made from defective parts
+    pub quality: f64, // Share of the products made that were good
+    pub reworkedCount: usize, // Products an inspection sent back for rework
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub firstPassYield: BTreeMap<String, f64>, // Share of each product type that passed inspection the first time
+    pub stateChangeCount: usize,
+    pub faultedCount: usize,
+    pub maintenanceCount: usize, // Preventive maintenance finished
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wear: Option<f64>, // Units of wear built up by the end of the run, only for machines that wear
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub sensors: BTreeMap<String, f64>, // Last reading of each named sensor
+    pub producingSec: f64, // Time spent in each state, in simulated seconds
+    pub faultedSec: f64,
+    pub blockedSec: f64,
+    pub starvedSec: f64,
+    pub starvedBlockedSec: f64,
+    pub plannedStopSec: f64,
+    pub maintenanceSec: f64,
+    pub waitingForTechnicianSec: f64, // Part of faultedSec and maintenanceSec spent waiting for a technician before the work started
+    pub availability: f64, // Share of planned run time not lost to faults or maintenance, planned stops are left out
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub laneStarvedSec: BTreeMap<String, f64>, // Time each assembly lane was short of parts while the machine was starved
+}
+
+// End-of-run counters for a whole line, written out by batch mode
+#[derive(Serialize)]
+pub struct RunStatistics
+{
+    pub factory: String,
+    pub seed: u64,
+    pub lineProducedCount: usize, // Produced by the consumer machines at the end of the line
+    pub lineProducedByType: BTreeMap<String, usize>,
+    pub lineGoodCount: usize, // Produced by the consumer machines and not defective
+    pub lineScrapCount: usize,
+    pub lineFaultedCount: usize, // Faults across every machine
+    pub simulatedTimeSec: f64,
+    pub warmupSec: f64, // Time excluded from the counters at the start of the run
+    pub measuredTimeSec: f64, // Time the counters cover, simulatedTimeSec - warmupSec
+    pub machines: Vec<MachineStatistics>,
+}
+
+// Reads, checks against the schema and parses a config file from the data directory
+pub fn loadFactoryData(configFile: &str) -> Result<JSONData>
+{
+    let configPath = dataPath(configFile);
+    if let Err(_e) = metadata(&configPath)
+    {
+        return Err(anyhow!("{} does not exist.", configFile));
+    }
+
+    let json_data = read_json_file(configPath.as_str());
+    if !validateFactoryJSON(&json_data)
+    {
+        return Err(anyhow!("{} has invalid structure.", configFile));
+    }
+
+    let data: JSONData = serde_json::from_str(&json_data).map_err(|error| anyhow!("{} could not be read: {}", configFile, error))?;
+    checkFactoryData(&data).map_err(|error| anyhow!("{}: {}", configFile, error))?;
+    Ok(data)
+}
+
+// Checks the values the schema cannot, so a config that would stop the line part way through
+// a run is turned away when it is loaded
+pub fn checkFactoryData(data: &JSONData) -> Result<()>
+{
+    if let Some(startDateTime) = &data.factory.startDateTime
+    {
+        if parseDateTime(startDateTime).is_none()
+        {
+            return Err(anyhow!("startDateTime {} is not a valid date and time.", startDateTime));
+        }
+    }
+
+    // A group nobody is in would never repair anything
+    if let Some(group) = data.factory.technicians.iter().flatten().find(|group| group.count == 0)
+    {
+        return Err(anyhow!("technician group {} has a count of 0.", group.name));
+    }
+
+    for stop in data.factory.calendar.iter().flat_map(|calendar| calendar.plannedStops.iter())
+    {
+        let name = match &stop.reason
+        {
+            Some(reason) => format!("planned stop \"{}\"", reason),
+            None => String::from("planned stop"),
+        };
+        match (parseDateTime(&stop.start), parseDateTime(&stop.end))
+        {
+            (None, _) => return Err(anyhow!("{} start {} is not a valid date and time.", name, stop.start)),
+            (_, None) => return Err(anyhow!("{} end {} is not a valid date and time.", name, stop.end)),
+            (Some(start), Some(end)) if end <= start => return Err(anyhow!("{} ends at {}, which is not after its start.", name, stop.end)),
+            _ => (),
+        }
+    }
+
+    for machine in data.factory.machines.iter()
+    {
+        if machine.processingBehavior.to_lowercase() == "batch" && machine.batch.is_none()
+        {
+            return Err(anyhow!("machine {} has BATCH processing but no batch sizes.", machine.id));
+        }
+
+        for fault in machine.faults.iter()
+        {
+            for distribution in [&fault.timeBetweenFailures, &fault.timeToRepair].into_iter().flatten()
+            {
+                checkDistribution(distribution)
+                    .map_err(|problem| anyhow!("machine {} fault \"{}\" {}", machine.id, fault.faultMessage, problem))?;
+            }
+        }
+
+        if let Some(sensor) = machine.quality.as_ref().and_then(|quality| quality.sensor.as_ref())
+        {
+            if !machine.sensors.iter().flatten().any(|named| named.name == *sensor)
+            {
+                return Err(anyhow!("machine {} quality follows sensor {}, which is not one of its sensors.", machine.id, sensor));
+            }
+            if machine.quality.as_ref().is_some_and(|quality| quality.sensorTolerance.is_none())
+            {
+                return Err(anyhow!("machine {} quality needs a sensorTolerance to follow sensor {}.", machine.id, sensor));
+            }
+        }
+
+        for sensor in machine.sensors.iter().flatten()
+        {
+            if let (Some(min), Some(max)) = (sensor.min, sensor.max)
+            {
+                if min > max
+                {
+                    return Err(anyhow!("machine {} sensor {} has a min of {} above its max of {}.", machine.id, sensor.name, min, max));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Every parameter a distribution is drawn with has to be there and in range
+fn checkDistribution(distribution: &JSONDistribution) -> Result<()>
+{
+    let positive = |name: &str, value: Option<f64>| match value
+    {
+        Some(value) if value > 0.0 && value.is_finite() => Ok(()),
+        _ => Err(anyhow!("{} distribution needs {} greater than 0.", distribution.distribution, name)),
+    };
+
+    match distribution.distribution.to_lowercase().as_str()
+    {
+        "weibull" => positive("shape", distribution.shape).and(positive("scaleSec", distribution.scaleSec)),
+        "lognormal" => match distribution.sigma
+        {
+            Some(sigma) if sigma >= 0.0 && sigma.is_finite() => positive("meanSec", distribution.meanSec),
+            _ => Err(anyhow!("lognormal distribution needs sigma of 0 or more.")),
+        },
+        _ => positive("meanSec", distribution.meanSec),
+    }
+}
+
+fn timeDistribution(distribution: JSONDistribution) -> TimeDistribution
+{
+    // Only a distribution with its own fields gets past checkDistribution
+    match distribution.distribution.to_lowercase().as_str()
+    {
+        "weibull" => TimeDistribution::Weibull { shape: distribution.shape.unwrap(), scaleSec: distribution.scaleSec.unwrap() },
+        "lognormal" => TimeDistribution::Lognormal { meanSec: distribution.meanSec.unwrap(), sigma: distribution.sigma.unwrap() },
+        _ => TimeDistribution::Exponential { meanSec: distribution.meanSec.unwrap() },
+    }
+}
+
+pub fn factorySetup(mut data: JSONData) -> Factory
+{
+    // Without a start date and time, a seeded run starts from a fixed date and any other run from
+    // now. It is written into the stored config so snapshots and reloads carry on from the same calendar.
+    if data.factory.startDateTime.is_none()
+    {
+        data.factory.startDateTime = Some(match data.factory.seed
+        {
+            Some(_) => String::from(SEEDED_START_DATETIME),
+            None => Utc::now().naive_utc().format("%Y-%m-%dT%H:%M:%S").to_string(),
+        });
+    }
+    let startDateTime = parseDateTime(data.factory.startDateTime.as_ref().unwrap()).unwrap(); // checkFactoryData turns away impossible dates
+    let calendar = Calendar::new(&data.factory.calendar);
+    let technicians: Vec<TechnicianGroup> = data.factory.technicians.iter().flatten().map(TechnicianGroup::new).collect();
+    let mut maintenanceTimes = Vec::<MaintenanceTime>::new();
+
+    let config = data.clone();
+
+    tracing::info!("Factory Name: {}", data.factory.name);
+    tracing::info!("Description: {}", data.factory.description);
+    tracing::info!("Simulation Speed: {} ", data.factory.simSpeed);
+    tracing::info!("Poll Rate: {} milliseconds", data.factory.pollRateMs);
+    tracing::info!("Start Date and Time: {}", startDateTime);
+
+    // Without a seed in the config, pick one and log it so the run can still be reproduced
+    let factorySeed = data.factory.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    tracing::info!("Seed: {}", factorySeed);
+
+    //Setting data to variables to be passed into the return
+    let factorySpeed = data.factory.simSpeed;
+    let factoryPollRateUs = data.factory.pollRateMs * 1000; // milliseconds to microseconds
+    let factoryWarmupUs = (data.factory.warmupSec.unwrap_or(0.0) * 1000000.0) as u128; // seconds to microseconds
+
+    let mut machines = HashMap::<String, RefCell<Machine>>::new();
+    let mut conveyors = HashMap::<String, RefCell<ConveyorBelt>>::new();
+    let mut machineIDs = Vec::<String>::new(); // Track all IDs, this makes iterating over the hashmap easier in the future
+    let mut conveyorIDs = Vec::<String>::new();
+
+    for machine in data.factory.machines
+    {
+        let mut state = OPCState::PRODUCING;
+
+        match machine.state.to_lowercase().as_str()
+        {
+            "producing" => state = OPCState::PRODUCING,
+            "faulted" => state = OPCState::FAULTED,
+            "blocked" => state = OPCState::BLOCKED,
+            "starved" => state = OPCState::STARVED,
+            _ => (),
+        }
+
+        let id = machine.id;
+
+        let mut machineFaults = Vec::<Fault>::new();
+        for fault in machine.faults
+        {
+            machineFaults.push(Fault { faultChance: fault.faultChance, faultMessage: fault.faultMessage,
+                    faultTimeHighSec: fault.faultTimeHighSec, faultTimeLowSec: fault.faultTimeLowSec,
+                    timeBetweenFailures: fault.timeBetweenFailures.map(timeDistribution),
+                    timeToRepair: fault.timeToRepair.map(timeDistribution),
+                    operatingTime: fault.failureClock.unwrap_or(String::from("operating")).to_lowercase() != "calendar",
+                    skill: fault.skill,
+                    precursor: fault.precursor.as_ref().map(Precursor::new) });
+        }
+
+        // A fault no technician can repair would leave the machine down for good
+        if !technicians.is_empty()
+        {
+            for fault in machineFaults.iter().filter(|fault| !technicians.iter().any(|group| group.canRepair(&fault.skill)))
+            {
+                tracing::warn!("ID {}: No technician has the {} skill needed to repair \"{}\".", id, fault.skill.as_ref().unwrap(), fault.faultMessage);
+            }
+        }
+
+        let mut maintenancePlans = Vec::<MaintenancePlan>::new();
+        for (index, maintenance) in machine.maintenance.iter().flatten().enumerate()
+        {
+            maintenancePlans.push(MaintenancePlan::new(maintenance, format!("maintenance {index}").as_str()));
+            for time in maintenance.atTimes.iter().flatten()
+            {
+                maintenanceTimes.push(MaintenanceTime::new(&id, index, time));
+            }
+        }
+
+        // Maintenance no technician can do would leave the machine down for good
+        if !technicians.is_empty()
+        {
+            for plan in maintenancePlans.iter().filter(|plan| !technicians.iter().any(|group| group.canRepair(&plan.skill)))
+            {
+                tracing::warn!("ID {}: No technician has the {} skill needed for \"{}\".", id, plan.skill.as_ref().unwrap(), plan.name);
+            }
+        }
+
+        let mut newMachine = Machine::new(
+            id.clone(),
+            machine.cost,
+            machine.throughput,
+            state,
+            machineFaults,
+            data.factory.debounceRateInPolls,
+            machine.processingSpeedMs * 1000, // milliseconds to microseconds
+            machine.inputSpeedMs * 1000, // milliseconds to microseconds
+            machine.inputCapacity,
+            machine.outputSpeedMs * 1000, // milliseconds to microseconds
+            machine.outputCapacity,
+            machine.sensor,
+            machine.sensorBaseline,
+            machine.sensorVariance,
+        );
+        newMachine.inputIDs = machine.inputIDs;
+        newMachine.outputIDs = machine.outputIDs;
+        newMachine.rejectIDs = machine.rejectIDs.unwrap_or_default();
+        newMachine.maintenancePlans = maintenancePlans;
+        newMachine.sensors = machine.sensors.iter().flatten().map(Sensor::new).collect();
+        for fault in newMachine.faults.iter().filter(|fault| fault.precursor.is_some())
+        {
+            let sensor = &fault.precursor.as_ref().unwrap().sensor;
+            if !newMachine.sensors.iter().any(|named| named.name == *sensor)
+            {
+                tracing::warn!("ID {}: \"{}\" has a precursor in {}, which is not one of its sensors.", id, fault.faultMessage, sensor);
+            }
+        }
+        newMachine.wear = machine.wear.map(|wear| Wear {
+            clock: match wear.perCycles
+            {
+                Some(cycles) => WearClock::Cycles(cycles),
+                None => WearClock::OperatingUs((wear.perOperatingHours.unwrap() * 3600.0 * 1000000.0) as u128), // hours to microseconds
+            },
+            processingSlowdown: wear.processingSlowdown.unwrap_or(0.0),
+            faultChanceGrowth: wear.faultChanceGrowth.unwrap_or(0.0),
+            sensorDrift: wear.sensorDrift.unwrap_or(0.0),
+            maxWear: wear.maxWear,
+            resetOnRepair: wear.resetOnRepair.unwrap_or(false),
+        });
+        if let Some(quality) = machine.quality
+        {
+            newMachine.defectChance = quality.defectChance;
+            newMachine.sensorDefectChance = quality.sensorDefectChance.unwrap_or(0.0);
+            newMachine.sensorTolerance = quality.sensorTolerance.unwrap_or(newMachine.variance / 2.0);
+            newMachine.qualitySensor = quality.sensor;
+        }
+        for share in machine.productMix.unwrap_or_default()
+        {
+            newMachine.productMix.push(ProductShare { productType: share.productType, weight: share.weight });
+        }
+        for transform in machine.transforms.unwrap_or_default()
+        {
+            newMachine.transforms.insert(transform.input, transform.output);
+        }
+        for line in machine.billOfMaterials.unwrap_or_default()
+        {
+            newMachine.billOfMaterials.push(BOMLine { inputID: line.inputID, quantity: line.quantity,
+                    capacity: line.capacity.unwrap_or(line.quantity).max(line.quantity), items: VecDeque::new(), starvedUs: 0 });
+        }
+        newMachine.seedRng(factorySeed);
+        newMachine.startFailureClocks();
+        newMachine.needsTechnician = !technicians.is_empty();
+
+        let mut inputBehavior: fn(&mut Machine, &mut HashMap<String, RefCell<ConveyorBelt>>, u128) -> bool = Machine::singleInput;
+        let mut processingBehavior: fn(&mut Machine, u128) -> bool = Machine::defaultProcessing;
+        let mut outputBehavior: fn(&mut Machine, &mut HashMap<String, RefCell<ConveyorBelt>>, u128) -> bool = Machine::singleOutput;
+        match machine.inputBehavior.to_lowercase().as_str()
+        {
+            "spawner" => inputBehavior = Machine::spawnerInput,
+            "single" => inputBehavior = Machine::singleInput,
+            "flow" => {
+                inputBehavior = Machine::flowInput;
+                newMachine.continuousInput = true;
+            },
+            _ => (),
+        }
+        match machine.processingBehavior.to_lowercase().as_str()
+        {
+            "default" => processingBehavior = Machine::defaultProcessing,
+            // Assembly takes its input lane by lane, whatever the input behavior, and uses
+            // the bill of materials instead of cost
+            "assembly" => {
+                processingBehavior = Machine::assemblyProcessing;
+                inputBehavior = Machine::assemblyInput;
+                newMachine.inputIDs = newMachine.billOfMaterials.iter().map(|line| line.inputID.clone()).collect();
+                newMachine.cost = newMachine.billOfMaterials.iter().map(|line| line.quantity).sum();
+            },
+            "flow" => {
+                processingBehavior = Machine::flowProcessing;
+                newMachine.continuousProcessing = true;
+            },
+            // Inspection checks one product at a time and makes nothing, so cost and throughput are 1
+            "inspection" => {
+                processingBehavior = Machine::inspectionProcessing;
+                newMachine.inspection = true;
+                newMachine.cost = 1;
+                newMachine.throughput = 1;
+                if let Some(inspection) = machine.inspection.clone()
+                {
+                    newMachine.reworkIDs = inspection.reworkIDs;
+                    newMachine.maxReworks = inspection.maxReworks.unwrap_or(1);
+                }
+            },
+            "batch" => {
+                processingBehavior = Machine::batchProcessing;
+                let batch = machine.batch.clone().unwrap(); // checkFactoryData turns away BATCH processing without batch sizes
+                newMachine.batchMaxSize = batch.maxSize;
+                newMachine.batchMinSize = batch.minSize.unwrap_or(1).min(batch.maxSize);
+                newMachine.batchMaxWaitUs = batch.maxWaitSec.map(|seconds| (seconds * 1000000.0) as u128); // seconds to microseconds
+            },
+            _ => (),
+        }
+        match machine.outputBehavior.to_lowercase().as_str()
+        {
+            "consumer" => outputBehavior = Machine::consumerOutput,
+            "default" => outputBehavior = Machine::singleOutput,
+            _ => (),
+        }
+
+        // Piped-in input has no items to process in cycles, so it only works with flow processing
+        if newMachine.continuousInput && !newMachine.continuousProcessing
+        {
+            tracing::warn!("ID {}: FLOW input needs FLOW processing, using FLOW processing.", id);
+            processingBehavior = Machine::flowProcessing;
+            newMachine.continuousProcessing = true;
+        }
+
+        newMachine.inputBehavior = Some(inputBehavior);
+        newMachine.processingBehavior = Some(processingBehavior);
+        newMachine.outputBehavior = Some(outputBehavior);
+
+        machineIDs.push(id.clone());
+        machines.insert(id.clone(), RefCell::new(newMachine));
+    }
+
+    for conveyor in data.factory.conveyors
+    {
+        let id = conveyor.id;
+        conveyors.insert(id.clone(), RefCell::new(ConveyorBelt::new(id.clone(), conveyor.capacity, conveyor.beltSpeedMs * 1000, conveyor.inputID)));
+        conveyorIDs.push(id.clone());
+    }
+
+    let mut factory = Factory {
+        name: data.factory.name,
+        config,
+        machines,
+        machineIDs,
+        conveyors,
+        conveyorIDs,
+        simSpeed: factorySpeed,
+        pollRateUs: factoryPollRateUs,
+        seed: factorySeed,
+        simTimeUs: 0,
+        warmupUs: factoryWarmupUs,
+        statsStartUs: 0,
+        startDateTime,
+        simulatedTimestamps: data.factory.simulatedTimestamps.unwrap_or(false),
+        calendar,
+        technicians,
+        maintenanceTimes,
+        events: EventQueue::new(),
+        zeroTimeLoopWarned: false,
+    };
+
+    // Start everything that can start at t=0, and schedule the first poll
+    factory.applyCalendar();
+    factory.settle();
+    factory.events.schedule(EventKind::Poll, factory.pollRateUs.max(1));
+    if factory.warmupUs > 0
+    {
+        tracing::info!("Warm-up: {} seconds", factory.warmupUs as f64 / 1000000.0);
+        factory.events.schedule(EventKind::WarmupEnd, factory.warmupUs);
+    }
+
+    factory
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use serde_json::{json, Value};
+
+    // A machine that spawns an item, processes it for a second and consumes it, with any of
+    // these fields replaced by those in `fields`
+    fn machine(id: &str, fields: Value) -> Value
+    {
+        let mut machine = json!({
+            "id": id, "cost": 1, "throughput": 1, "state": "PRODUCING", "faults": [],
+            "inputIDs": [], "inputBehavior": "SPAWNER", "inputSpeedMs": 0, "inputCapacity": 1,
+            "processingBehavior": "DEFAULT", "processingSpeedMs": 1000,
+            "outputIDs": [], "outputBehavior": "CONSUMER", "outputSpeedMs": 0, "outputCapacity": 1,
+            "sensor": false, "sensorBaseline": 0.0, "sensorVariance": 0.0
+        });
+        for (key, value) in fields.as_object().expect("Machine fields must be an object.")
+        {
+            machine[key] = value.clone();
+        }
+        machine
+    }
+
+    fn belt(id: &str, capacity: usize) -> Value
+    {
+        json!({ "id": id, "capacity": capacity, "beltSpeedMs": 500, "inputID": null })
+    }
+
+    fn config(machines: Vec<Value>, conveyors: Vec<Value>) -> JSONData
+    {
+        let data = json!({ "factory": {
+            "name": "test", "description": "", "simSpeed": 1.0, "pollRateMs": 100, "debounceRateInPolls": 1, "seed": 1,
+            "machines": machines, "conveyors": conveyors
+        }});
+        serde_json::from_value(data).expect("Test config does not parse.")
+    }
+
+    fn line(machines: Vec<Value>, conveyors: Vec<Value>) -> Factory
+    {
+        factorySetup(config(machines, conveyors))
+    }
+
+    fn producedAt(factory: &mut Factory, seconds: u128) -> usize
+    {
+        factory.runUntil(seconds * 1000000, |_, _| false);
+        factory.statistics().machines[0].producedCount
+    }
+
+    fn itemsOn(factory: &Factory, conveyorID: &str) -> usize
+    {
+        factory.conveyors[conveyorID].borrow().belt.iter().filter(|item| item.is_some()).count()
+    }
+
+    // A spawner feeding a slower consumer over a belt, next to an unconnected machine c
+    fn feedLine(consumerSpeedMs: u128, withC: bool) -> JSONData
+    {
+        let mut machines = vec![
+            machine("a", json!({ "outputIDs": ["ab"], "outputBehavior": "DEFAULT" })),
+            machine("b", json!({ "inputIDs": ["ab"], "inputBehavior": "SINGLE", "processingSpeedMs": consumerSpeedMs })),
+        ];
+        if withC
+        {
+            machines.push(machine("c", json!({})));
+        }
+        config(machines, vec![belt("ab", 5)])
+    }
+
+    fn produced(machineID: &str, count: usize) -> RunCondition
+    {
+        RunCondition::ProducedCount { machineID: String::from(machineID), count }
+    }
+
+    fn inState(machineID: &str, state: OPCState) -> RunCondition
+    {
+        RunCondition::MachineState { machineID: String::from(machineID), state }
+    }
+
+    #[test]
+    fn producedCountConditionIsMetOnceReached()
+    {
+        let mut factory = line(vec![machine("a", json!({}))], vec![]);
+        assert!(!factory.isConditionMet(&produced("a", 5)));
+
+        factory.runUntil(10 * 1000000, |_, _| false);
+        assert!(factory.isConditionMet(&produced("a", 5)));
+        assert!(!factory.isConditionMet(&produced("a", 50)));
+        assert!(!factory.isConditionMet(&produced("missing", 0)));
+    }
+
+    #[test]
+    fn stateConditionMatchesTheCurrentState()
+    {
+        let jam = json!([{ "faultChance": 1.0, "faultMessage": "Jammed.", "faultTimeHighSec": 100.0, "faultTimeLowSec": 100.0 }]);
+        let mut factory = line(vec![machine("a", json!({ "faults": jam }))], vec![]);
+
+        factory.runUntil(10 * 1000000, |_, _| false);
+        assert!(factory.isConditionMet(&inState("a", OPCState::FAULTED)));
+        assert!(!factory.isConditionMet(&inState("a", OPCState::PRODUCING)));
+        assert!(!factory.isConditionMet(&inState("missing", OPCState::FAULTED)));
+    }
+
+    #[test]
+    fn runUntilStopsAtTheEventThatMeetsTheCondition()
+    {
+        let mut factory = line(vec![machine("a", json!({}))], vec![]);
+        let condition = produced("a", 3);
+        assert!(factory.runUntil(60 * 1000000, |factory, _| factory.isConditionMet(&condition)));
+
+        let stoppedAtUs = factory.simTimeUs;
+        assert_eq!(factory.machines["a"].borrow().producedCount, 3);
+        assert!(stoppedAtUs < 60 * 1000000);
+
+        // An instant earlier the condition did not hold yet
+        let mut earlier = line(vec![machine("a", json!({}))], vec![]);
+        earlier.runUntil(stoppedAtUs - 1, |_, _| false);
+        assert_eq!(earlier.machines["a"].borrow().producedCount, 2);
+    }
+
+    #[test]
+    fn statisticsExcludeTheWarmup()
+    {
+        let mut unwarmed = line(vec![machine("a", json!({}))], vec![]);
+        let duringWarmup = producedAt(&mut unwarmed, 10);
+        let total = producedAt(&mut unwarmed, 30);
+
+        let mut data = config(vec![machine("a", json!({}))], vec![]);
+        data.factory.warmupSec = Some(10.0);
+        let mut warmed = factorySetup(data);
+        assert_eq!(producedAt(&mut warmed, 30), total - duringWarmup);
+
+        let statistics = warmed.statistics();
+        assert_eq!(statistics.warmupSec, 10.0);
+        assert_eq!(statistics.measuredTimeSec, 20.0);
+    }
+
+    #[test]
+    fn reloadCarriesOverUnchangedMachinesAndDropsRemovedOnes()
+    {
+        let mut factory = factorySetup(feedLine(3000, true));
+        factory.runUntil(20 * 1000000, |_, _| false);
+
+        let counts = |factory: &Factory, id: &str| {
+            let machine = factory.machines[id].borrow();
+            (machine.producedCount, machine.consumedCount, machine.inputInventory, machine.outputInventory)
+        };
+        let before = (counts(&factory, "a"), counts(&factory, "b"), itemsOn(&factory, "ab"));
+        assert!(before.2 > 0, "the belt should have backed up");
+
+        let summary = factory.reload(feedLine(3000, false));
+        assert_eq!(summary.removedMachines, vec![String::from("c")]);
+        assert!(summary.changedMachines.is_empty());
+        assert!(summary.addedMachines.is_empty());
+
+        assert_eq!(factory.simTimeUs, 20 * 1000000);
+        assert_eq!((counts(&factory, "a"), counts(&factory, "b"), itemsOn(&factory, "ab")), before);
+        assert!(!factory.machines.contains_key("c"));
+        assert_eq!(factory.machineIDs, vec![String::from("a"), String::from("b")]);
+        assert_eq!(factory.statistics().machines.len(), 2);
+
+        // The line carries on from where it was
+        factory.runUntil(40 * 1000000, |_, _| false);
+        assert!(factory.machines["b"].borrow().consumedCount > before.1.1);
+    }
+
+    #[test]
+    fn serialsAreUniqueAndKeepTheirTypeThroughTransforms()
+    {
+        let mix = json!([{ "productType": "a", "weight": 1.0 }, { "productType": "b", "weight": 1.0 }]);
+        let mut factory = line(vec![
+            machine("spawner", json!({ "outputIDs": ["belt"], "outputBehavior": "DEFAULT", "productMix": mix })),
+            machine("paint", json!({ "inputIDs": ["belt"], "inputBehavior": "SINGLE", "transforms": [{ "input": "a", "output": "painted" }] })),
+        ], vec![belt("belt", 3)]);
+
+        // Every product the spawner makes, by serial, and every one the painter finishes, in order
+        let mut spawned = HashMap::<String, String>::new();
+        let mut painted = Vec::<Product>::new();
+        factory.runUntil(600 * 1000000, |factory, _| {
+            if let Some(product) = factory.machines["spawner"].borrow().lastProduct.clone()
+            {
+                let previous = spawned.insert(product.serial.clone(), product.productType.clone());
+                assert!(previous.is_none() || previous == Some(product.productType));
+            }
+            if let Some(product) = factory.machines["paint"].borrow().lastProduct.clone()
+            {
+                if painted.last() != Some(&product)
+                {
+                    painted.push(product);
+                }
+            }
+            false
+        });
+
+        assert!(painted.len() > 100);
+        let mut serials: Vec<&String> = painted.iter().map(|product| &product.serial).collect();
+        serials.sort();
+        serials.dedup();
+        assert_eq!(serials.len(), painted.len(), "a serial was finished twice");
+
+        for product in painted.iter()
+        {
+            let expected = match spawned[&product.serial].as_str()
+            {
+                "a" => "painted",
+                other => other,
+            };
+            assert_eq!(product.productType, expected, "{} changed type", product.serial);
+        }
+        assert_eq!(factory.statistics().machines[1].producedByType.keys().collect::<Vec<&String>>(), vec!["b", "painted"]);
+    }
+
+    // Bodies and screws from their own spawners, assembled with one body and four screws per product
+    fn assemblyLine(bodySpeedMs: u128, screwSpeedMs: u128) -> Factory
+    {
+        let bom = json!([{ "inputID": "bodies", "quantity": 1 }, { "inputID": "screws", "quantity": 4, "capacity": 8 }]);
+        line(vec![
+            machine("body", json!({ "outputIDs": ["bodies"], "outputBehavior": "DEFAULT", "processingSpeedMs": bodySpeedMs,
+                    "productMix": [{ "productType": "body", "weight": 1.0 }] })),
+            machine("screw", json!({ "outputIDs": ["screws"], "outputBehavior": "DEFAULT", "processingSpeedMs": screwSpeedMs,
+                    "productMix": [{ "productType": "screw", "weight": 1.0 }] })),
+            machine("assembly", json!({ "processingBehavior": "ASSEMBLY", "billOfMaterials": bom, "processingSpeedMs": 2000 })),
+        ], vec![belt("bodies", 3), belt("screws", 3)])
+    }
+
+    #[test]
+    fn assemblyConsumesTheBillOfMaterialsPerProduct()
+    {
+        let mut factory = assemblyLine(1000, 250);
+        factory.runUntil(300 * 1000000, |_, _| false);
+
+        let assembly = factory.machines["assembly"].borrow();
+        assert!(assembly.producedCount > 50, "assembled {}", assembly.producedCount);
+        assert_eq!(assembly.consumedCount, 5 * assembly.producedCount);
+        assert_eq!(assembly.producedByType.keys().collect::<Vec<&String>>(), vec!["body"]);
+
+        // Every part a spawner made is still on its way, or went into a product in the lane's quantity
+        for (lane, (spawnerID, quantity)) in [("body", 1), ("screw", 4)].into_iter().enumerate()
+        {
+            let spawner = factory.machines[spawnerID].borrow();
+            let incoming = (assembly.inputInProgress && assembly.incomingLane == lane) as usize;
+            let onTheWay = spawner.outputItems.len() + itemsOn(&factory, &assembly.billOfMaterials[lane].inputID)
+                    + incoming + assembly.billOfMaterials[lane].items.len();
+            assert_eq!(spawner.producedCount, onTheWay + quantity * assembly.producedCount, "{spawnerID} parts went missing");
+        }
+    }
+
+    #[test]
+    fn starvedTimeIsPutOnTheLaneShortOfParts()
+    {
+        let mut factory = assemblyLine(5000, 250);
+        factory.runUntil(300 * 1000000, |_, _| false);
+
+        let statistics = factory.statistics();
+        let lanes = &statistics.machines[2].laneStarvedSec;
+        assert!(lanes["bodies"] > 100.0, "bodies lane was short for {} s", lanes["bodies"]);
+        assert!(lanes["screws"] * 10.0 < lanes["bodies"], "screws lane was short for {} s", lanes["screws"]);
+    }
+
+    #[test]
+    fn flowThroughputMatchesTheConfiguredRate()
+    {
+        // Two units of input piped in a second, two used per unit of output, one unit made a second
+        let mut factory = line(vec![machine("mixer", json!({ "inputBehavior": "FLOW", "inputSpeedMs": 500, "inputCapacity": 10,
+                "processingBehavior": "FLOW", "cost": 2 }))], vec![]);
+        factory.runUntil(600 * 1000000, |_, _| false);
+
+        let mixer = factory.machines["mixer"].borrow();
+        assert!(mixer.producedCount.abs_diff(600) <= 1, "made {} in 600 s", mixer.producedCount);
+        assert!(mixer.consumedCount.abs_diff(1200) <= 2, "used {} in 600 s", mixer.consumedCount);
+    }
+
+    #[test]
+    fn flowThroughputIsLimitedBySupply()
+    {
+        // One unit piped in every 4 seconds can only make one unit every 4 seconds
+        let mut factory = line(vec![machine("mixer", json!({ "inputBehavior": "FLOW", "inputSpeedMs": 4000, "inputCapacity": 10,
+                "processingBehavior": "FLOW" }))], vec![]);
+        factory.runUntil(600 * 1000000, |_, _| false);
+
+        let mixer = factory.machines["mixer"].borrow();
+        assert!(mixer.producedCount.abs_diff(150) <= 1, "made {} in 600 s", mixer.producedCount);
+    }
+
+    // Sizes of the batches an oven fed one item every feedSpeedMs has finished within 10 minutes
+    fn batchSizes(feedSpeedMs: u128, batch: Value) -> Vec<usize>
+    {
+        let mut factory = line(vec![
+            machine("feeder", json!({ "outputIDs": ["belt"], "outputBehavior": "DEFAULT", "processingSpeedMs": feedSpeedMs })),
+            machine("oven", json!({ "inputIDs": ["belt"], "inputBehavior": "SINGLE", "inputCapacity": 10, "outputCapacity": 10,
+                    "processingBehavior": "BATCH", "processingSpeedMs": 5000, "batch": batch })),
+        ], vec![belt("belt", 3)]);
+
+        let mut sizes = Vec::<usize>::new();
+        let mut produced = 0;
+        factory.runUntil(600 * 1000000, |factory, _| {
+            let producedNow = factory.machines["oven"].borrow().producedCount;
+            if producedNow > produced
+            {
+                sizes.push(producedNow - produced);
+                produced = producedNow;
+            }
+            false
+        });
+        sizes
+    }
+
+    #[test]
+    fn batchesRunFullWithoutATimeout()
+    {
+        let sizes = batchSizes(1000, json!({ "maxSize": 4 }));
+        assert!(sizes.len() > 10);
+        assert!(sizes.iter().all(|size| *size == 4), "{sizes:?}");
+    }
+
+    #[test]
+    fn timeoutReleasesAPartialBatch()
+    {
+        // Items arrive every 10 seconds, so a batch of 4 would take 30 seconds to gather
+        let sizes = batchSizes(10000, json!({ "minSize": 2, "maxSize": 4, "maxWaitSec": 15.0 }));
+        assert!(sizes.len() > 10);
+        assert!(sizes.iter().all(|size| *size >= 2 && *size < 4), "{sizes:?}");
+    }
+
+    #[test]
+    fn partialBatchesWaitForTheMinimumSize()
+    {
+        let sizes = batchSizes(10000, json!({ "minSize": 3, "maxSize": 4, "maxWaitSec": 1.0 }));
+        assert!(sizes.len() > 10);
+        assert!(sizes.iter().all(|size| *size == 3), "{sizes:?}");
+    }
+
+    #[test]
+    fn firstPassYieldMatchesTheDefectChance()
+    {
+        let mut factory = line(vec![
+            machine("maker", json!({ "outputIDs": ["belt"], "outputBehavior": "DEFAULT", "quality": { "defectChance": 0.2 },
+                    "productMix": [{ "productType": "a", "weight": 1.0 }, { "productType": "b", "weight": 1.0 }] })),
+            machine("inspection", json!({ "inputIDs": ["belt"], "inputBehavior": "SINGLE", "processingBehavior": "INSPECTION" })),
+        ], vec![belt("belt", 3)]);
+        factory.runUntil(2000 * 1000000, |_, _| false);
+
+        let statistics = factory.statistics();
+        let firstPassYield = &statistics.machines[1].firstPassYield;
+        assert_eq!(firstPassYield.keys().collect::<Vec<&String>>(), vec!["a", "b"]);
+        for (productType, share) in firstPassYield.iter()
+        {
+            assert!((share - 0.8).abs() < 0.05, "{productType} passed {share} of the time");
+        }
+    }
+
+    // A machine that faults at the end of nearly every cycle, needing a ten second repair with the given skill
+    fn breakingMachine(id: &str, skill: Option<&str>) -> Value
+    {
+        machine(id, json!({ "faults": [{ "faultChance": 1.0, "faultMessage": "Broke.", "faultTimeHighSec": 10.0, "faultTimeLowSec": 10.0, "skill": skill }] }))
+    }
+
+    fn repairLine(machines: Vec<Value>, technicians: Value) -> Factory
+    {
+        let mut data = config(machines, vec![]);
+        data.factory.technicians = Some(serde_json::from_value(technicians).expect("Test technicians do not parse."));
+        factorySetup(data)
+    }
+
+    // Which machines have a technician and which are waiting for one
+    fn repairs(factory: &Factory) -> (Vec<String>, Vec<String>)
+    {
+        let repairing = factory.machineIDs.iter().filter(|id| factory.machines[*id].borrow().technician.is_some()).cloned().collect();
+        let waiting = factory.machineIDs.iter().filter(|id| factory.machines[*id].borrow().waitingForTechnician()).cloned().collect();
+        (repairing, waiting)
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String>
+    {
+        ids.iter().map(|id| String::from(*id)).collect()
+    }
+
+    #[test]
+    fn faultsQueueWhileTechniciansAreBusy()
+    {
+        let machines = || vec![breakingMachine("a", None), breakingMachine("b", None), breakingMachine("c", None)];
+
+        let mut short = repairLine(machines(), json!([{ "name": "fitter", "count": 1 }]));
+        short.runUntil(1500000, |_, _| false);
+        assert_eq!(repairs(&short), (ids(&["a"]), ids(&["b", "c"])));
+
+        let mut staffed = repairLine(machines(), json!([{ "name": "fitter", "count": 3 }]));
+        staffed.runUntil(1500000, |_, _| false);
+        assert_eq!(repairs(&staffed), (ids(&["a", "b", "c"]), ids(&[])));
+    }
+
+    #[test]
+    fn techniciansOnlyRepairFaultsTheyHaveTheSkillFor()
+    {
+        let machines = || vec![breakingMachine("a", Some("mechanical")), breakingMachine("b", Some("electrical"))];
+
+        let mut factory = repairLine(machines(), json!([{ "name": "electrician", "count": 2, "skills": ["electrical"] }]));
+        factory.runUntil(1500000, |_, _| false);
+        assert_eq!(repairs(&factory), (ids(&["b"]), ids(&["a"])));
+
+        let mut factory = repairLine(machines(), json!([{ "name": "electrician", "count": 1, "skills": ["electrical"] }, { "name": "anyone", "count": 1 }]));
+        factory.runUntil(1500000, |_, _| false);
+        assert_eq!(repairs(&factory), (ids(&["a", "b"]), ids(&[])));
+        assert_eq!(factory.machines["a"].borrow().technician.as_deref(), Some("anyone"));
+    }
+
+    #[test]
+    fn waitingForATechnicianIsCounted()
+    {
+        // Both fault at 1 s, b waits for a's ten second repair to finish
+        let mut factory = repairLine(vec![breakingMachine("a", None), breakingMachine("b", None)], json!([{ "name": "fitter", "count": 1 }]));
+        factory.runUntil(11500000, |_, _| false);
+        assert_eq!(repairs(&factory), (ids(&["b"]), ids(&[])));
+
+        let statistics = factory.statistics();
+        assert_eq!(statistics.machines[0].waitingForTechnicianSec, 0.0);
+        assert!((statistics.machines[1].waitingForTechnicianSec - 10.0).abs() < 0.2, "b waited {} s", statistics.machines[1].waitingForTechnicianSec);
+    }
+
+    // Produced counts and times, in seconds, at which machine "a" went into maintenance within the given time
+    fn maintenanceStarts(data: JSONData, seconds: u128) -> Vec<(usize, f64)>
+    {
+        let mut factory = factorySetup(data);
+        let mut starts = Vec::<(usize, f64)>::new();
+        let mut inMaintenance = false;
+        factory.runUntil(seconds * 1000000, |factory, _| {
+            let machine = factory.machines["a"].borrow();
+            if machine.state == OPCState::MAINTENANCE && !inMaintenance
+            {
+                starts.push((machine.producedCount, factory.simTimeUs as f64 / 1000000.0));
+            }
+            inMaintenance = machine.state == OPCState::MAINTENANCE;
+            false
+        });
+        starts
+    }
+
+    fn maintainedLine(maintenance: Value) -> JSONData
+    {
+        config(vec![machine("a", json!({ "maintenance": [maintenance] }))], vec![])
+    }
+
+    #[test]
+    fn maintenanceIsDueEveryCycles()
+    {
+        let starts = maintenanceStarts(maintainedLine(json!({ "everyCycles": 10, "durationSec": 5.0 })), 100);
+        assert_eq!(starts.iter().map(|start| start.0).collect::<Vec<usize>>(), vec![10, 20, 30, 40, 50, 60, 70]);
+    }
+
+    #[test]
+    fn maintenanceIsDueEveryOperatingHours()
+    {
+        // 36 seconds of processing, which starts over after each maintenance
+        let starts = maintenanceStarts(maintainedLine(json!({ "everyOperatingHours": 0.01, "durationSec": 4.0 })), 200);
+        assert_eq!(starts, vec![(36, 36.0), (72, 76.0), (108, 116.0), (144, 156.0), (180, 196.0)]);
+    }
+
+    #[test]
+    fn maintenanceIsDueAtItsTimesOfDay()
+    {
+        // The run starts a minute before maintenance is due, on a Monday
+        let maintenance = json!({ "atTimes": [{ "days": ["mon"], "time": "08:00" }], "durationSec": 30.0 });
+        let mut data = maintainedLine(maintenance.clone());
+        data.factory.startDateTime = Some(String::from("2026-01-05T07:59:00"));
+        assert_eq!(maintenanceStarts(data, 600), vec![(60, 60.0)]);
+
+        // Not on a Tuesday
+        let mut data = maintainedLine(maintenance);
+        data.factory.startDateTime = Some(String::from("2026-01-06T07:59:00"));
+        assert!(maintenanceStarts(data, 600).is_empty());
+    }
+
+    #[test]
+    fn seededRunWithoutAStartFollowsTheCalendarFromAFixedDate()
+    {
+        // Only the first minute of each Monday is a shift
+        let mut data = config(vec![machine("a", json!({}))], vec![]);
+        data.factory.calendar = Some(serde_json::from_value(json!({ "shifts": [{ "days": ["mon"], "start": "00:00", "end": "00:01" }] }))
+            .expect("Test calendar does not parse."));
+        let mut factory = factorySetup(data);
+        assert_eq!(factory.config.factory.startDateTime.as_deref(), Some(SEEDED_START_DATETIME));
+        // The 60th cycle would end as the shift does, so it is held until the next one
+        assert_eq!(producedAt(&mut factory, 300), 59);
+    }
+
+    #[test]
+    fn maintenanceRestartsTimeBasedFaults()
+    {
+        // Fails after exactly 30 seconds of processing, unless maintenance every 20 cycles restores it first
+        let line = |restoration: f64| {
+            let fault = json!({ "faultMessage": "Worn out.", "timeBetweenFailures": { "distribution": "lognormal", "meanSec": 30.0, "sigma": 0.0 },
+                    "faultTimeHighSec": 5.0, "faultTimeLowSec": 5.0 });
+            let maintenance = json!({ "everyCycles": 20, "durationSec": 2.0, "restoration": restoration });
+            let mut factory = line(vec![machine("a", json!({ "faults": [fault], "maintenance": [maintenance] }))], vec![]);
+            factory.runUntil(300 * 1000000, |_, _| false);
+            factory.statistics().machines[0].faultedCount
+        };
+
+        assert_eq!(line(1.0), 0);
+        assert!(line(0.0) > 0);
+    }
+
+    #[test]
+    fn maintenanceLowersRolledFaultsUntilTheyRecover()
+    {
+        let maintenance = json!({ "everyCycles": 20, "durationSec": 2.0, "restoration": 0.8, "recoveryCycles": 10 });
+        let mut factory = factorySetup(maintainedLine(maintenance));
+        let mut scales = Vec::<(usize, f64)>::new();
+        factory.runUntil(40 * 1000000, |factory, _| {
+            let machine = factory.machines["a"].borrow();
+            if machine.state != OPCState::MAINTENANCE && scales.last().is_none_or(|last| last.0 != machine.producedCount)
+            {
+                scales.push((machine.producedCount, machine.faultChanceScale));
+            }
+            false
+        });
+
+        let scaleAt = |cycles: usize| scales.iter().find(|scale| scale.0 == cycles).expect("No cycle count recorded.").1;
+        assert_eq!(scaleAt(19), 1.0);
+        // Cut by the restoration once done, then back evenly over the recovery cycles
+        assert!((scaleAt(20) - 0.2).abs() < 1e-9, "{scales:?}");
+        assert!((scaleAt(25) - 0.6).abs() < 1e-9, "{scales:?}");
+        assert!((scaleAt(30) - 1.0).abs() < 1e-9, "{scales:?}");
+        assert_eq!(scaleAt(35), 1.0);
+    }
+
+    #[test]
+    fn processingSlowsWithWear()
+    {
+        let wearing = json!({ "wear": { "perCycles": 10, "processingSlowdown": 0.1 } });
+        let mut worn = line(vec![machine("a", wearing)], vec![]);
+        let mut fresh = line(vec![machine("a", json!({}))], vec![]);
+        assert!(producedAt(&mut worn, 600) < producedAt(&mut fresh, 600) * 3 / 4);
+
+        // The cycle in progress runs at the speed its wear set when it started
+        let machine = worn.machines["a"].borrow();
+        assert!(machine.wearLevel > 5.0);
+        let expectedUs = 1000000.0 * (1.0 + 0.1 * machine.wearLevel);
+        assert!((machine.processingTickSpeedUs as f64 - expectedUs).abs() <= 1.0, "{} us per cycle at wear {}", machine.processingTickSpeedUs, machine.wearLevel);
+    }
+
+    #[test]
+    fn rolledFaultsGrowLikelierWithWear()
+    {
+        let faults = |wear: Value| {
+            let fault = json!({ "faultChance": 0.01, "faultMessage": "Jammed.", "faultTimeHighSec": 1.0, "faultTimeLowSec": 1.0 });
+            let mut factory = line(vec![machine("a", json!({ "faults": [fault], "wear": wear }))], vec![]);
+            factory.runUntil(2000 * 1000000, |_, _| false);
+            factory.statistics().machines[0].faultedCount
+        };
+
+        let fresh = faults(Value::Null);
+        let worn = faults(json!({ "perCycles": 10, "faultChanceGrowth": 1.0, "maxWear": 20.0 }));
+        assert!(worn > 5 * fresh, "{worn} faults worn, {fresh} fresh");
+    }
+
+    #[test]
+    fn maintenanceTakesOffItsRestorationOfTheWear()
+    {
+        let maintenance = json!({ "everyCycles": 50, "durationSec": 5.0, "restoration": 0.5 });
+        let mut factory = line(vec![machine("a", json!({ "wear": { "perCycles": 10 }, "maintenance": [maintenance] }))], vec![]);
+
+        // Wear going into each maintenance and coming out of it
+        let mut wearAround = Vec::<(f64, f64)>::new();
+        let mut inMaintenance = false;
+        factory.runUntil(600 * 1000000, |factory, _| {
+            let machine = factory.machines["a"].borrow();
+            if (machine.state == OPCState::MAINTENANCE) != inMaintenance
+            {
+                inMaintenance = !inMaintenance;
+                match inMaintenance
+                {
+                    true => wearAround.push((machine.wearLevel, 0.0)),
+                    false => wearAround.last_mut().unwrap().1 = machine.wearLevel,
+                }
+            }
+            false
+        });
+
+        assert!(wearAround.len() > 5);
+        for (before, after) in wearAround.iter().filter(|(_, after)| *after > 0.0)
+        {
+            assert!((after - before * 0.5).abs() < 1e-9, "wear went from {before} to {after}");
+        }
+        // Half the wear is left each time, so it settles at the wear of one interval
+        assert!((wearAround.last().unwrap().0 - 10.0).abs() < 0.1);
+    }
+
+    // A machine that breaks every cycle, with a vibration sensor that drifts for leadTimeSec first
+    fn warnedFaultLine(leadTimeSec: f64) -> Factory
+    {
+        line(vec![machine("a", json!({
+            "faults": [{ "faultChance": 1.0, "faultMessage": "Bearing failed.", "faultTimeHighSec": 10.0, "faultTimeLowSec": 10.0,
+                "failureClock": "CALENDAR",
+                "precursor": { "sensor": "vibration", "leadTimeSec": leadTimeSec, "pattern": "drift", "magnitude": 4.0 } }],
+            "sensors": [{ "name": "vibration", "model": "uniform", "baseline": 1.0 }]
+        }))], vec![])
+    }
+
+    #[test]
+    fn precursorStartsOneLeadTimeBeforeTheFault()
+    {
+        let mut factory = warnedFaultLine(5.0);
+        let mut onItsWayUs = None;
+        let mut firstShownUs = None;
+        factory.runUntil(120000000, |factory, event| {
+            let machine = factory.machines["a"].borrow();
+            if onItsWayUs.is_none() && machine.timeToFailureUs[0].is_some()
+            {
+                onItsWayUs = Some(factory.simTimeUs);
+            }
+            if firstShownUs.is_none() && *event == EventKind::Poll && machine.sensors[0].value > 1.0
+            {
+                firstShownUs = Some(factory.simTimeUs);
+            }
+            machine.state == OPCState::FAULTED
+        });
+
+        let onItsWayUs = onItsWayUs.expect("The fault never started on its way.");
+        assert_eq!(factory.machines["a"].borrow().state, OPCState::FAULTED);
+        assert_eq!(factory.simTimeUs - onItsWayUs, 5000000);
+        // The first poll after the fault is on its way already shows the drift
+        let firstShownUs = firstShownUs.expect("The precursor never showed.");
+        assert!(firstShownUs > onItsWayUs && firstShownUs <= onItsWayUs + 100000, "{firstShownUs} {onItsWayUs}");
+    }
+
+    #[test]
+    fn reloadKeepsCountersOfChangedMachines()
+    {
+        let mut factory = factorySetup(feedLine(3000, true));
+        factory.runUntil(20 * 1000000, |_, _| false);
+        let consumed = factory.machines["b"].borrow().consumedCount;
+
+        let summary = factory.reload(feedLine(1000, true));
+        assert_eq!(summary.changedMachines, vec![String::from("b")]);
+        assert_eq!(factory.machines["b"].borrow().processingTickSpeedUs, 1000000);
+        assert!(factory.machines["b"].borrow().consumedCount >= consumed);
+    }
+
+    #[test]
+    fn distributionsWithoutPositiveParametersAreRejected()
+    {
+        let distribution = |text: &str| -> JSONDistribution { serde_json::from_str(text).expect("Test distribution does not parse.") };
+
+        assert!(checkDistribution(&distribution(r#"{"distribution": "exponential", "meanSec": 30}"#)).is_ok());
+        assert!(checkDistribution(&distribution(r#"{"distribution": "lognormal", "meanSec": 30, "sigma": 0}"#)).is_ok());
+        assert!(checkDistribution(&distribution(r#"{"distribution": "exponential", "meanSec": 0}"#)).is_err());
+        assert!(checkDistribution(&distribution(r#"{"distribution": "weibull", "shape": 1.5, "scaleSec": -2}"#)).is_err());
+        assert!(checkDistribution(&distribution(r#"{"distribution": "weibull", "scaleSec": 60}"#)).is_err());
+        assert!(checkDistribution(&distribution(r#"{"distribution": "lognormal", "meanSec": 30, "sigma": -0.5}"#)).is_err());
+    }
+
+    #[test]
+    fn sensorWithMinAboveMaxIsRejected()
+    {
+        let mut data = config(vec![machine("a", json!({}))], vec![]);
+        data.factory.machines[0].sensors = Some(vec![serde_json::from_str(
+            r#"{"name": "temperature", "model": "uniform", "baseline": 65, "variance": 2, "min": 120, "max": 20}"#)
+            .expect("Test sensor does not parse.")]);
+        assert!(checkFactoryData(&data).is_err());
+
+        data.factory.machines[0].sensors.as_mut().unwrap()[0].min = Some(20.0);
+        assert!(checkFactoryData(&data).is_ok());
+    }
+
+    #[test]
+    fn impossibleStartDateTimeIsRejected()
+    {
+        let mut data = config(vec![machine("a", json!({}))], vec![]);
+        data.factory.startDateTime = Some(String::from("2026-02-30T06:00:00"));
+        assert!(checkFactoryData(&data).is_err());
+
+        data.factory.startDateTime = Some(String::from("2026-02-28T06:00:00"));
+        assert!(checkFactoryData(&data).is_ok());
+    }
+
+    #[test]
+    fn impossiblePlannedStopsAreRejected()
+    {
+        let stopped = |start: &str, end: &str| {
+            let mut data = config(vec![machine("a", json!({}))], vec![]);
+            data.factory.calendar = Some(serde_json::from_value(json!({ "plannedStops": [{ "start": start, "end": end }] }))
+                .expect("Test calendar does not parse."));
+            checkFactoryData(&data)
+        };
+
+        assert!(stopped("2026-01-05T08:00", "2026-01-05T12:00").is_ok());
+        assert!(stopped("2026-02-30T08:00", "2026-03-01T12:00").is_err());
+        assert!(stopped("2026-01-05T08:00", "2026-01-05T25:00").is_err());
+        assert!(stopped("2026-01-05T12:00", "2026-01-05T08:00").is_err());
+    }
+
+    #[test]
+    fn qualityFollowingAMissingSensorIsRejected()
+    {
+        let quality = |quality: Value| config(vec![machine("a", json!({ "quality": quality,
+            "sensors": [{ "name": "temperature", "model": "uniform", "baseline": 20.0 }] }))], vec![]);
+
+        assert!(checkFactoryData(&quality(json!({ "defectChance": 0.0, "sensorDefectChance": 0.1, "sensor": "temperature", "sensorTolerance": 5.0 }))).is_ok());
+        assert!(checkFactoryData(&quality(json!({ "defectChance": 0.0, "sensorDefectChance": 0.1, "sensor": "pressure", "sensorTolerance": 5.0 }))).is_err());
+        assert!(checkFactoryData(&quality(json!({ "defectChance": 0.0, "sensorDefectChance": 0.1, "sensor": "temperature" }))).is_err());
+    }
+
+    #[test]
+    fn emptyTechnicianGroupIsRejected()
+    {
+        let mut data = config(vec![machine("a", json!({}))], vec![]);
+        data.factory.technicians = Some(serde_json::from_value(json!([{ "name": "mechanics", "count": 0 }])).expect("Test technicians do not parse."));
+        assert!(checkFactoryData(&data).is_err());
+
+        data.factory.technicians.as_mut().unwrap()[0].count = 1;
+        assert!(checkFactoryData(&data).is_ok());
+    }
+
+    #[test]
+    fn batchProcessingWithoutBatchSizesIsRejected()
+    {
+        let mut data = config(vec![machine("a", json!({ "processingBehavior": "BATCH" }))], vec![]);
+        assert!(checkFactoryData(&data).is_err());
+
+        data.factory.machines[0].batch = Some(serde_json::from_value(json!({ "maxSize": 4 })).expect("Test batch does not parse."));
+        assert!(checkFactoryData(&data).is_ok());
+    }
+}
@@ -1,10 +1,12 @@
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
+
+use jsonschema::JSONSchema;
 
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct JSONMachine {
     pub id: String,
     pub cost: usize,
@@ -24,18 +26,179 @@ pub struct JSONMachine {
     pub sensor: bool,
     pub sensorBaseline: f64, 
     pub sensorVariance: f64,    
+    pub productMix: Option<Vec<JSONProductShare>>, // Product types a spawner emits, all "product" if left out
+    pub transforms: Option<Vec<JSONTransform>>, // Product types this machine turns into other types
+    pub billOfMaterials: Option<Vec<JSONBOMLine>>, // Parts an ASSEMBLY machine needs from each input lane
+    pub batch: Option<JSONBatch>, // Batch sizes of a BATCH machine
+    pub quality: Option<JSONQuality>, // Chance of making defective products, none if left out
+    pub rejectIDs: Option<Vec<String>>, // Conveyors products found defective here go out on, they go out with the rest if left out
+    pub inspection: Option<JSONInspection>, // Rework settings of an INSPECTION machine
+    pub maintenance: Option<Vec<JSONMaintenance>>, // Preventive maintenance, the machine runs to failure if left out
+    pub wear: Option<JSONWear>, // How the machine degrades with use, it never does if left out
+    pub sensors: Option<Vec<JSONSensor>>, // Named sensors, each its own OPC variable, none if left out
+}
+
+// A named sensor and its signal model. The schema checks each model has the fields it needs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONSensor
+{
+    pub name: String,
+    pub units: Option<String>,
+    pub model: String, // "uniform", "gaussian", "randomWalk", "sine" or "step"
+    pub baseline: f64,
+    pub variance: Option<f64>, // Full range of uniform noise around the baseline
+    pub stdDev: Option<f64>, // Gaussian noise, optional on sine and step
+    pub stepStdDev: Option<f64>, // Random walk step size
+    pub reversion: Option<f64>, // Share of a random walk's distance from the baseline taken back every reading, 0 if left out
+    pub amplitude: Option<f64>, // Sine
+    pub periodSec: Option<f64>,
+    pub steps: Option<Vec<JSONSensorStep>>, // Step
+    pub repeatSec: Option<f64>, // Steps start over this often, they happen once if left out
+    pub min: Option<f64>, // Readings are kept at or above this
+    pub max: Option<f64>,
+    pub wearDrift: Option<f64>, // Amount the reading moves per unit of wear, 0 if left out
+    pub stateTargets: Option<Vec<JSONStateTarget>>, // Where the readings settle in some machine states, the baseline in the rest
+    pub timeConstantSec: Option<f64>, // How fast the readings settle, at once if left out
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONStateTarget
+{
+    pub state: String, // "PRODUCING", "FAULTED", "BLOCKED", "STARVED", "STARVEDBLOCKED", "PLANNEDSTOP" or "MAINTENANCE"
+    pub target: Option<f64>, // The sensor's baseline if left out
+    pub timeConstantSec: Option<f64>, // The sensor's timeConstantSec if left out
+    pub spike: Option<f64>, // Jump as the machine goes into the state, 0 if left out
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONSensorStep
+{
+    pub atSec: f64, // Time into the run, or into each repeat
+    pub value: f64,
+}
+
+// One unit of wear builds up every perCycles cycles or every perOperatingHours of processing,
+// the schema makes sure there is exactly one of them
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONWear
+{
+    pub perCycles: Option<u64>,
+    pub perOperatingHours: Option<f64>,
+    pub processingSlowdown: Option<f64>, // Share longer processing takes per unit of wear, 0 if left out
+    pub faultChanceGrowth: Option<f64>, // Share every faultChance grows by per unit of wear, 0 if left out
+    pub sensorDrift: Option<f64>, // Amount the sensor moves from its baseline per unit of wear, 0 if left out
+    pub maxWear: Option<f64>, // Wear stops building up here, no limit if left out
+    pub resetOnRepair: Option<bool>, // A repair makes the machine as good as new, false if left out
+}
+
+// Preventive maintenance, triggered by exactly one of everyCycles, everyOperatingHours or atTimes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONMaintenance
+{
+    pub name: Option<String>,
+    pub everyCycles: Option<u64>, // Processing cycles, or units made by a FLOW machine
+    pub everyOperatingHours: Option<f64>, // Time spent processing
+    pub atTimes: Option<Vec<JSONMaintenanceTime>>,
+    pub durationSec: f64,
+    pub restoration: Option<f64>, // Chance each time-based fault starts over from a fresh draw, and share rolled faults drop by, 1 if left out
+    pub recoveryCycles: Option<u64>, // Cycles rolled faults take to climb back to faultChance, the cycles between maintenance if left out
+    pub skill: Option<String>, // Skill a technician needs to do it, any technician can if left out
+}
+
+// A time of day maintenance is due on some days of the week, "HH:MM"
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONMaintenanceTime
+{
+    pub days: Option<Vec<String>>, // "mon" through "sun", every day if left out
+    pub time: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONInspection
+{
+    pub reworkIDs: Vec<String>, // Conveyors back upstream that defective products are sent on for rework
+    pub maxReworks: Option<u32>, // Times a product is sent back before it is scrapped, 1 if left out
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONQuality
+{
+    pub defectChance: f64,
+    pub sensorDefectChance: Option<f64>, // Added to defectChance as the sensor reads further from its baseline
+    pub sensor: Option<String>, // Named sensor sensorDefectChance follows, the generic sensor if left out
+    pub sensorTolerance: Option<f64>, // Distance from the baseline that adds all of sensorDefectChance, half the generic sensor's variance if left out
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONBatch
+{
+    pub minSize: Option<usize>, // 1 if left out
+    pub maxSize: usize,
+    pub maxWaitSec: Option<f64>, // Waits for a full batch if left out
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONBOMLine
+{
+    pub inputID: String,
+    pub quantity: usize, // Parts used per cycle
+    pub capacity: Option<usize>, // Parts the lane can hold, quantity if left out
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONProductShare
+{
+    pub productType: String,
+    pub weight: f64, // Relative to the other types in the mix
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONTransform
+{
+    pub input: String,
+    pub output: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct JSONFault
 {
+    #[serde(default)]
     pub faultChance: f32, // percent chance for a fault
     pub faultMessage: String, // string for fault message
+    #[serde(default)]
     pub faultTimeHighSec: f32,
+    #[serde(default)]
     pub faultTimeLowSec: f32,
+    pub timeBetweenFailures: Option<JSONDistribution>, // Fails after a drawn time instead of rolling faultChance every cycle
+    pub timeToRepair: Option<JSONDistribution>, // Used instead of faultTimeLowSec to faultTimeHighSec
+    pub failureClock: Option<String>, // "OPERATING" or "CALENDAR", what timeBetweenFailures counts, OPERATING if left out
+    pub skill: Option<String>, // Skill a technician needs to repair it, any technician can if left out
+    pub precursor: Option<JSONPrecursor>, // Warning the fault gives in one of the machine's sensors before it arrives
+}
+
+// How a fault shows in a sensor before it fails the machine. The schema checks the pattern.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONPrecursor
+{
+    pub sensor: String, // Name of one of the machine's sensors
+    pub leadTimeSec: f64, // How long before the fault the pattern starts
+    pub pattern: String, // "drift", "noise" or "spikes"
+    pub magnitude: f64, // Drift or spike size, or the standard deviation of the extra noise, reached just before the fault
+    pub spikeChance: Option<f64>, // Chance a reading spikes just before the fault, 0.2 if left out
+}
+
+// A random length of time, in seconds. The schema checks each distribution has the fields it needs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONDistribution
+{
+    pub distribution: String, // "exponential" with meanSec, "weibull" with shape and scaleSec, or "lognormal" with meanSec and sigma
+    pub meanSec: Option<f64>,
+    pub shape: Option<f64>,
+    pub scaleSec: Option<f64>,
+    pub sigma: Option<f64>, // Standard deviation of the log of the time
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct JSONConveyor {
     pub id: String,
     pub capacity: usize,
@@ -43,18 +206,63 @@ pub struct JSONConveyor {
     pub inputID: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+// A window that repeats on some days of the week, times are "HH:MM"
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JSONTimeWindow
+{
+    pub name: Option<String>,
+    pub days: Option<Vec<String>>, // "mon" through "sun", every day if left out
+    pub start: String,
+    pub end: String, // At or before start to run past midnight
+}
+
+// A one-off stop, dates are "YYYY-MM-DDTHH:MM:SS"
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JSONPlannedStop
+{
+    pub reason: Option<String>,
+    pub start: String,
+    pub end: String,
+    pub machines: Option<Vec<String>>, // Machine IDs to stop, the whole line if left out
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JSONCalendar
+{
+    #[serde(default)]
+    pub shifts: Vec<JSONTimeWindow>,
+    #[serde(default)]
+    pub breaks: Vec<JSONTimeWindow>,
+    #[serde(default)]
+    pub plannedStops: Vec<JSONPlannedStop>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JSONFactory {
     pub name: String,
     pub description: String,
     pub simSpeed: f64,
     pub pollRateMs: u128,
     pub debounceRateInPolls: i32,
+    pub seed: Option<u64>, // Fixed seed for reproducible runs, random if left out
+    pub warmupSec: Option<f64>, // Simulated time before statistics start counting, 0 if left out
+    pub startDateTime: Option<String>, // Date and time the simulated clock starts at, the time of setup if left out
+    pub simulatedTimestamps: Option<bool>, // Stamp OPC values with the simulated date and time, false if left out
+    pub calendar: Option<JSONCalendar>, // Shifts, breaks and planned stops, runs around the clock if left out
+    pub technicians: Option<Vec<JSONTechnicianGroup>>, // Repairs wait for a free technician, they start straight away if left out
     pub machines: Vec<JSONMachine>,
     pub conveyors: Vec<JSONConveyor>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JSONTechnicianGroup
+{
+    pub name: String,
+    pub count: usize,
+    pub skills: Option<Vec<String>>, // Fault skills the group can repair, every fault if left out
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JSONData {
     pub factory: JSONFactory,
 }
@@ -65,3 +273,47 @@ pub fn read_json_file(file_path: &str) -> String {
     file.read_to_string(&mut file_content).expect("Failed to read file content");
     file_content
 }
+
+// Config files, logs and results all live in the data directory, which is mounted
+// at /home/data when running in the docker container
+pub fn dataPath(fileName: &str) -> String
+{
+    if in_container::in_container()
+    {
+        return format!("/home/data/{}", fileName);
+    }
+
+    format!("./data/{}", fileName)
+}
+
+// Checks a config against data/schema.json, printing every validation error found.
+// Returns false if the config is not valid JSON or does not match the schema.
+pub fn validateFactoryJSON(jsonData: &str) -> bool
+{
+    let data_as_value: serde_json::Value = match serde_json::from_str(jsonData)
+    {
+        Ok(value) => value,
+        Err(error) => {
+            println!("Failed to parse JSON: {}", error);
+            return false;
+        }
+    };
+
+    // JSON file validation using JSON schema
+    let schema_string = read_json_file("./data/schema.json");
+    let schema_data = serde_json::from_str(&schema_string).expect("Failed to parse Schema");
+
+    let compiled_schema = JSONSchema::compile(&schema_data).expect("Could not compile schema");
+
+    let result = compiled_schema.validate(&data_as_value);
+    if let Err(errors) = result {
+        for error in errors {
+            println!("Validation error: {}", error);
+            println!("Instance path: {}", error.instance_path);
+        }
+
+        return false;
+    }
+
+    true
+}
@@ -1,11 +1,19 @@
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::cell::RefCell;
 use std::cell::RefMut;
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Exp1, OpenClosed01, StandardNormal};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use serde::{Serialize, Deserialize};
+
+use crate::maintenance::*;
+use crate::sensor::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OPCState
 {
     PRODUCING,
@@ -13,7 +21,31 @@ pub enum OPCState
     BLOCKED,
     STARVED,
     STARVEDBLOCKED,
+    #[allow(clippy::upper_case_acronyms)] // named like the other states, which the config and OPC clients use
+    PLANNEDSTOP,
+    #[allow(clippy::upper_case_acronyms)]
+    MAINTENANCE,
+}
+impl OPCState
+{
+    // Reads a state as configs and requests name it, in any case, eg. "FAULTED", "plannedStop"
+    // or "planned stop" as it is shown on the OPC server. None if it is not a state.
+    pub fn parse(name: &str) -> Option<OPCState>
+    {
+        match name.to_uppercase().replace([' ', '_'], "").as_str()
+        {
+            "PRODUCING" => Some(OPCState::PRODUCING),
+            "FAULTED" => Some(OPCState::FAULTED),
+            "BLOCKED" => Some(OPCState::BLOCKED),
+            "STARVED" => Some(OPCState::STARVED),
+            "STARVEDBLOCKED" | "STARVEDANDBLOCKED" => Some(OPCState::STARVEDBLOCKED),
+            "PLANNEDSTOP" => Some(OPCState::PLANNEDSTOP),
+            "MAINTENANCE" => Some(OPCState::MAINTENANCE),
+            _ => None,
+        }
+    }
 }
+
 impl fmt::Display for OPCState
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -24,18 +56,120 @@ impl fmt::Display for OPCState
             OPCState::BLOCKED => write!(f, "blocked"),
             OPCState::STARVED => write!(f, "starved"),
             OPCState::STARVEDBLOCKED => write!(f, "starved and blocked"),
+            OPCState::PLANNEDSTOP => write!(f, "planned stop"),
+            OPCState::MAINTENANCE => write!(f, "maintenance"),
+        }
+    }
+}
+
+// Simulated time a machine has spent in each state, in microseconds
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StateTimes
+{
+    pub producingUs: u128,
+    pub faultedUs: u128,
+    pub blockedUs: u128,
+    pub starvedUs: u128,
+    pub starvedBlockedUs: u128,
+    pub plannedStopUs: u128,
+    #[serde(default)]
+    pub maintenanceUs: u128,
+}
+impl StateTimes
+{
+    pub fn add(&mut self, state: OPCState, deltaTime: u128)
+    {
+        match state
+        {
+            OPCState::PRODUCING => self.producingUs += deltaTime,
+            OPCState::FAULTED => self.faultedUs += deltaTime,
+            OPCState::BLOCKED => self.blockedUs += deltaTime,
+            OPCState::STARVED => self.starvedUs += deltaTime,
+            OPCState::STARVEDBLOCKED => self.starvedBlockedUs += deltaTime,
+            OPCState::PLANNEDSTOP => self.plannedStopUs += deltaTime,
+            OPCState::MAINTENANCE => self.maintenanceUs += deltaTime,
+        }
+    }
+
+    // Share of the time the machine was planned to run that it was not faulted or in maintenance.
+    // Planned stops are not counted as lost time, and a machine that was never planned to run lost nothing.
+    pub fn availability(&self) -> f64
+    {
+        let plannedUs = self.producingUs + self.faultedUs + self.blockedUs + self.starvedUs + self.starvedBlockedUs + self.maintenanceUs;
+        if plannedUs == 0
+        {
+            return 1.0;
         }
+
+        (plannedUs - self.faultedUs - self.maintenanceUs) as f64 / plannedUs as f64
+    }
+}
+
+// Product type given to items when a spawner has no product mix
+pub const DEFAULT_PRODUCT_TYPE: &str = "product";
+
+// One physical part moving through the line
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Product
+{
+    pub productType: String,
+    pub serial: String, // Unique on the line, the ID of the machine that made it then a count, eg. machine-0-17
+    #[serde(default)]
+    pub defective: bool, // Made out of spec here or by any machine before, whether or not it has been noticed
+    #[serde(default)]
+    pub reworkCount: u32, // Times an inspection has sent it back for rework
+    #[serde(default)]
+    pub awaitingRework: bool, // Sent back by an inspection, the next machine to process it reworks it
+    #[serde(default)]
+    pub rejected: bool, // Found defective by the machine it is leaving, which puts it on its reject lanes
+}
+impl Product
+{
+    // Whether a product made from this one inherits its defect. A product waiting for rework
+    // has its defect fixed by the machine that reworks it, which then rolls for a defect of its own.
+    fn passesOnDefect(&self) -> bool
+    {
+        self.defective && !self.awaitingRework
     }
 }
 
-#[derive(Clone)]
+// First inspections of one product type, for first-pass yield
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct FirstPassCount
+{
+    pub inspected: usize,
+    pub passed: usize,
+}
+
+// One product type a spawner can emit, and how often relative to the others
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProductShare
+{
+    pub productType: String,
+    pub weight: f64,
+}
+
+// One input lane of an assembly machine's bill of materials, with its own inventory
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BOMLine
+{
+    pub inputID: String, // conveyor the parts come in on
+    pub quantity: usize, // parts used per cycle
+    pub capacity: usize, // parts the lane can hold
+    pub items: VecDeque<Product>,
+    pub starvedUs: u128, // time this lane was short while the machine was starved
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BeltItem
 {
     pub moveClockUs: u128, // clock for current movement, in microseconds
     pub tickSpeedUs: u128, // time it takes to perform a movement, microseconds
     pub isMoving: bool,
+    pub product: Product,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ConveyorBelt
 {
     pub id: String,
@@ -55,12 +189,15 @@ impl ConveyorBelt
         return ConveyorBelt { id, capacity, belt, beltSpeedUs, isInputIDSome, inputID };
     }
 
-    pub fn update(&mut self, inputConveyor: Option<RefMut<ConveyorBelt>>, deltaTime: u128)
+    // Returns true if any item was taken, started moving, or finished moving
+    pub fn update(&mut self, inputConveyor: Option<RefMut<ConveyorBelt>>, deltaTime: u128) -> bool
     {
+        let mut changed = false;
+
         if self.isInputIDSome
         {
             let id = &self.id;
-            self.takeInput(&mut inputConveyor.expect(format!("Conveyor {id}'s input conveyor does not exist.").as_str()));
+            changed |= self.takeInput(&mut inputConveyor.unwrap_or_else(|| panic!("Conveyor {id}'s input conveyor does not exist.")));
         }
         
         let len = self.belt.len();
@@ -81,6 +218,7 @@ impl ConveyorBelt
                 {
                     item.isMoving = true;
                     item.moveClockUs = 0;
+                    changed = true;
                 }
                 else if !item.isMoving { continue; }
 
@@ -93,17 +231,43 @@ impl ConveyorBelt
                 item.isMoving = false;
                 self.belt[i + 1] = Some(item.to_owned());
                 self.belt[i] = None;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    // Moves every item's clock forward without moving anything, used by the scheduler
+    // to jump to the next event before letting update() act on it
+    pub fn advanceClocks(&mut self, deltaTime: u128)
+    {
+        for item in self.belt.iter_mut().flatten()
+        {
+            if item.isMoving
+            {
+                item.moveClockUs += deltaTime;
             }
         }
     }
 
+    // Time until the next item finishes moving, None if nothing is moving
+    pub fn nextEventUs(&self) -> Option<u128>
+    {
+        self.belt.iter()
+            .flatten()
+            .filter(|item| item.isMoving)
+            .map(|item| item.tickSpeedUs.saturating_sub(item.moveClockUs))
+            .min()
+    }
+
     pub fn takeInput(&mut self, inputConveyor: &mut RefMut<ConveyorBelt>) -> bool
     {
         // take input off optional input conveyor belt
         if !self.isStartSome() && inputConveyor.isEndSome()
         {
-            inputConveyor.pullItem();
-            self.pushItem();
+            let product = inputConveyor.pullItem().expect("Conveyor end emptied while taking from it.");
+            self.pushItem(product);
 
             return true;
         }
@@ -116,46 +280,145 @@ impl ConveyorBelt
         return self.belt[0].is_some();
     }
 
+    // Moves the items from the previous version of this belt onto this one, after a config reload.
+    // Items keep their distance from the end of the belt, so if the belt got shorter, the items
+    // furthest from the end are lost. Every item takes on the new belt speed.
+    pub fn carryOver(&mut self, old: &ConveyorBelt)
+    {
+        let len = self.belt.len();
+        for (fromEnd, item) in old.belt.iter().rev().enumerate().take(len)
+        {
+            self.belt[len - 1 - fromEnd] = item.clone().map(|mut item| {
+                item.tickSpeedUs = self.beltSpeedUs;
+                item
+            });
+        }
+    }
+
     pub fn isEndSome(&mut self) -> bool
     {
         let len = self.belt.len();
         return self.belt[len - 1].is_some();
     }
 
-    pub fn pushItem(&mut self) -> bool
+    pub fn pushItem(&mut self, product: Product) -> bool
     {
         if !self.isStartSome()
         {
-            self.belt[0] = Some(BeltItem { moveClockUs: 0, tickSpeedUs: self.beltSpeedUs, isMoving: false });
+            self.belt[0] = Some(BeltItem { moveClockUs: 0, tickSpeedUs: self.beltSpeedUs, isMoving: false, product });
             return true;
         }
 
         return false;
     }
 
-    pub fn pullItem(&mut self) -> bool
+    // Takes the item off the end of the belt, returning what it was carrying
+    pub fn pullItem(&mut self) -> Option<Product>
+    {
+        let len = self.belt.len();
+        self.belt[len - 1].take().map(|item| item.product)
+    }
+}
+
+// Flipped into a machine's stream ID to give its sensor a stream distinct from its fault rolls
+const SENSOR_STREAM_MASK: u64 = 0x5E45_0000_0000_0000;
+// Same again for a spawner's product mix rolls
+const PRODUCT_STREAM_MASK: u64 = 0x9A0D_0000_0000_0000;
+// Same again for defect rolls
+const QUALITY_STREAM_MASK: u64 = 0xDEFE_0000_0000_0000;
+// Same again for time-based failures and repairs
+const FAILURE_STREAM_MASK: u64 = 0xFA11_0000_0000_0000;
+
+// Flow levels this close to empty, full or a whole unit count as being there, to absorb rounding
+const FLOW_EPSILON: f64 = 1e-9;
+
+// Units per microsecond for something that takes tickSpeedUs per unit, 0 is treated as 1us
+fn ratePerUs(tickSpeedUs: u128) -> f64
+{
+    1.0 / tickSpeedUs.max(1) as f64
+}
+
+// FNV-1a hash of a machine ID, used to pick that machine's RNG stream. This is spelled out
+// instead of using std's hasher because std does not promise a stable hash between releases,
+// and a seed must reproduce the same run on any build.
+fn streamFromID(id: &str) -> u64
+{
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in id.bytes()
+    {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+// A random length of time, in seconds
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TimeDistribution
+{
+    Exponential { meanSec: f64 },
+    Weibull { shape: f64, scaleSec: f64 },
+    Lognormal { meanSec: f64, sigma: f64 }, // sigma is the standard deviation of the log of the time
+}
+impl TimeDistribution
+{
+    // Draws a length of time, in microseconds, at least 1. The parameters were checked when the config
+    // was loaded, so each draw scales a unit draw rather than building a distribution that could fail.
+    pub fn sampleUs(&self, rng: &mut ChaCha8Rng) -> u128
     {
-        if self.isEndSome()
+        let seconds = match *self
         {
-            let len = self.belt.len();
-            self.belt[len - 1] = None;
-            return true;
-        }
+            TimeDistribution::Exponential { meanSec } => meanSec * rng.sample::<f64, _>(Exp1),
+            TimeDistribution::Weibull { shape, scaleSec } => scaleSec * (-rng.sample::<f64, _>(OpenClosed01).ln()).powf(1.0 / shape),
+            TimeDistribution::Lognormal { meanSec, sigma } => {
+                // The mean of a lognormal is exp(mu + sigma^2 / 2), so mu is worked back from the mean
+                let mu = meanSec.ln() - sigma * sigma / 2.0;
+                (mu + sigma * rng.sample::<f64, _>(StandardNormal)).exp()
+            },
+        };
 
-        return false;
+        ((seconds * 1000000.0) as u128).max(1)// seconds to microseconds
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Fault
 {
     pub faultChance: f32, // percent chance for a fault
     pub faultMessage: String, // string for fault message
     pub faultTimeHighSec: f32, // highest time the fault can stay, in seconds
     pub faultTimeLowSec: f32, // lowest time the fault  can stay, in seconds
+    pub timeBetweenFailures: Option<TimeDistribution>, // time-based fault instead of a roll every cycle
+    pub timeToRepair: Option<TimeDistribution>, // used instead of faultTimeLowSec to faultTimeHighSec
+    pub operatingTime: bool, // timeBetweenFailures only counts while processing, instead of all the time
+    pub skill: Option<String>, // skill a technician needs to repair it, None for any technician
+    pub precursor: Option<Precursor>, // warning in a sensor before it fails the machine, a rolled fault then comes after the lead time
+}
+
+// What wear is counted in
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WearClock
+{
+    Cycles(u64), // cycles per unit of wear
+    OperatingUs(u128), // processing time per unit of wear
+}
+
+// How a machine degrades with use. Each unit of wear makes processing slower, per-cycle faults
+// likelier and the sensor drift from its baseline, by the amounts here.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wear
+{
+    pub clock: WearClock,
+    pub processingSlowdown: f64, // share longer processing takes
+    pub faultChanceGrowth: f64, // share every faultChance grows by
+    pub sensorDrift: f64, // amount the sensor moves from its baseline
+    pub maxWear: Option<f64>, // wear stops building up here
+    pub resetOnRepair: bool, // a repair makes the machine as good as new, otherwise only maintenance does
 }
 
-#[derive(Clone)]
+// Behaviors are function pointers and cannot be saved in a snapshot, they are
+// skipped and put back from the config when a snapshot is restored
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Machine
 {
     pub id: String,
@@ -164,16 +427,31 @@ pub struct Machine
     pub state: OPCState,
     pub faults: Vec<Fault>,
     pub currentFault: Option<Fault>,
+    pub currentFaultIndex: Option<usize>, // position of currentFault in faults
+    pub timeToFailureUs: Vec<Option<u128>>, // time left before each time-based fault, or before a rolled fault already on its way, None otherwise
+    pub needsTechnician: bool, // repairs wait for a technician from the factory's pool
+    pub technician: Option<String>, // technician group repairing the current fault or doing the current maintenance
+    pub technicianWaitClockUs: u128, // time the current fault or maintenance has waited for a technician
+    pub maintenancePlans: Vec<MaintenancePlan>, // preventive maintenance, empty to run to failure
+    pub currentMaintenance: Option<usize>, // position of the maintenance in progress in maintenancePlans
+    pub maintenanceClockUs: u128, // time the current maintenance has been worked on
+    pub faultChanceScale: f64, // share of faultChance rolled faults happen at, lowered by maintenance, back to 1 as the machine runs
+    pub faultChanceRecovery: f64, // amount faultChanceScale climbs back every cycle
+    pub wear: Option<Wear>, // None if the machine never degrades
+    pub wearLevel: f64, // units of wear built up since the machine was last as good as new
     pub faultTimeCurrentUs: u128, // time that needs to pass for the fault to end, in microseconds
     pub faultClockUs: u128, // current time that has passed since the fault started, in microseconds
     pub debounceRate: i32, // amount of times a state change must be true consecutively in order to actually change states
 
+    #[serde(skip)]
     pub processingBehavior: Option<fn(&mut Machine, u128) -> bool>, 
     pub processingClockUs: u128, // change in time since the processing started, in microseconds
     pub processingTickSpeedUs: u128, // how much time processing takes, in microseconds
+    pub nominalProcessingTickSpeedUs: u128, // processingTickSpeedUs of a machine with no wear
     pub processingInProgress: bool,
     pub processingDebouncer: i32, // Debouncing mechanism, needs to count to debounceRate to change states
 
+    #[serde(skip)]
     pub inputBehavior: Option<fn(&mut Machine, &mut HashMap<String, RefCell<ConveyorBelt>>, u128) -> bool>, // Function pointer that can also be None, used to define behavior
     pub inputClockUs: u128, // change in time since input started, in microseconds
     pub inputTickSpeedUs: u128, // how much time input takes, in microseconds
@@ -184,7 +462,23 @@ pub struct Machine
     pub inputInventory: usize, // storage place in machine before process 
     pub inputInvCapacity: usize, 
     pub nextInput: usize, // the input lane to start checking from 
-
+    pub inputItems: VecDeque<Product>, // the products in inputInventory, oldest first
+    pub incomingItem: Option<Product>, // product taken off a belt while input is in progress
+    pub billOfMaterials: Vec<BOMLine>, // per-lane inputs of an assembly machine, empty for any other machine
+    pub incomingLane: usize, // bill of materials lane the incoming item goes to
+    pub continuousInput: bool, // input flows in at the input rate from an unlimited supply
+    pub continuousProcessing: bool, // processing turns input into output at a rate instead of in cycles
+    pub inputLevel: f64, // fractional input inventory of a flow machine, inputInventory is this rounded up
+    pub outputLevel: f64, // progress through the next whole unit of a flow machine's output, 0 to 1
+    pub consumedFraction: f64, // input used by a flow machine that is not yet a whole unit in consumedCount
+    pub batchMinSize: usize, // smallest batch a batch machine runs once the wait is over
+    pub batchMaxSize: usize, // full batch size, 0 if this is not a batch machine
+    pub batchMaxWaitUs: Option<u128>, // longest wait for a full batch, from the batch's first item, in microseconds
+    pub batchWaitClockUs: u128, // time since the first item of the batch being gathered arrived
+    pub batchWaiting: bool, // gathering a batch, with at least one item in
+    pub batchSize: usize, // size of the batch being processed
+
+    #[serde(skip)]
     pub outputBehavior: Option<fn(&mut Machine, &mut HashMap<String, RefCell<ConveyorBelt>>, u128) -> bool>,
     pub outputClockUs: u128, // change in time since output started, in microseconds
     pub outputTickSpeedUs: u128, // how much time output takes, in microseconds
@@ -195,25 +489,62 @@ pub struct Machine
     pub outputInventory: usize, // represents num of items in it 
     pub outputInvCapacity: usize,
     pub nextOutput: usize, // the output lane to start checkng from
+    pub outputItems: VecDeque<Product>, // the products in outputInventory, oldest first
+    pub rejectIDs: Vec<String>, // conveyor belt IDs defective products are put on, instead of outputIDs
+    pub nextReject: usize, // the reject lane to start checking from
+    pub reworkIDs: Vec<String>, // conveyor belt IDs an inspection sends defective products back upstream on
+    pub nextRework: usize, // the rework lane to start checking from
+    pub maxReworks: u32, // times an inspection sends a product back for rework before scrapping it
+    pub inspection: bool, // checks products instead of making them
+
+    pub productMix: Vec<ProductShare>, // product types a spawner emits, "product" if empty
+    pub transforms: HashMap<String, String>, // input product type to the type it is turned into, others pass through unchanged
+    pub nextSerial: u64, // count used for the serials of products this machine makes
+    pub lastProduct: Option<Product>, // last product processing finished
+    pub defectChance: f64, // 0.0 through 1.0 chance of each product made being defective
+    pub sensorDefectChance: f64, // extra defect chance when the sensor reads sensorTolerance from its baseline, scaled by the distance
+    pub qualitySensor: Option<String>, // named sensor whose reading sensorDefectChance follows, None for the generic sensor
+    pub sensorTolerance: f64, // distance of the reading from its baseline that adds all of sensorDefectChance
 
     pub producedCount: usize,
     pub consumedCount: usize,
     pub stateChangeCount: usize,
     pub faultedCount: usize,
+    pub stateTimes: StateTimes, // time spent in each reported state
+    pub producedByType: BTreeMap<String, usize>, // producedCount split by product type
+    pub goodCount: usize, // products made that are not defective
+    pub scrapCount: usize, // products made that are defective, including ones made from defective parts
+    pub reworkedCount: usize, // products an inspection sent back for rework
+    pub waitingForTechnicianUs: u128, // time faulted or in maintenance while waiting for a technician, part of that time
+    pub maintenanceCount: usize, // preventive maintenance finished
+    pub firstPass: BTreeMap<String, FirstPassCount>, // first inspections by product type, empty if not an inspection
+    pub plannedStop: Option<String>, // reason the calendar has the machine stopped, None while it is planned to run
 
     pub sensor: bool,
     pub baseline: f64,
-    pub variance: f64
+    pub variance: f64,
+    pub sensorValue: f64, // last reading of the generic sensor, taken every poll
+    pub sensors: Vec<Sensor>, // named sensors, read every poll
+
+    pub rng: ChaCha8Rng, // stream used for fault rolls, derived from the factory seed and machine ID
+    pub sensorRng: ChaCha8Rng, // separate stream for sensor noise, so polling frequency cannot shift the fault timeline
+    pub productRng: ChaCha8Rng, // separate stream for picking from the product mix
+    pub qualityRng: ChaCha8Rng, // separate stream for defect rolls
+    pub failureRng: ChaCha8Rng, // separate stream for time-based failures and repair times
 }
 impl Machine
 {
-    pub fn sensor_Sim(baseline: f64, variance: f64) -> f64
+    // The generic "sensor" from before named sensors, kept as is for configs that turn it on. It is
+    // uniform noise around the baseline plus wear drift, drawn each time it is pushed to the server
+    // rather than every poll, so it does not take part in batch results, traces or precursors.
+    pub fn sensor_Sim(&mut self) -> f64
     {
-        let mut rng = rand::thread_rng();
+        let baseline = self.baseline;
+        let variance = self.variance;
 
-        let change = rng.gen_range(-(variance/2.0)..=(variance/2.0)); //Random whole number change between the - half of variance and half of variance
+        let change = self.sensorRng.gen_range(-(variance/2.0)..=(variance/2.0)); //Random whole number change between the - half of variance and half of variance
         
-        let sensNum = baseline + change;
+        let sensNum = baseline + self.sensorDrift() + change;
         //This was used in testing to make sure this function worked, currently hovers around baseline and changes within the range
         //of half of variance so that the data isnt bouncing directly from high end to low end
         //println!("Temperature: {:.2}", sensNum);
@@ -235,6 +566,18 @@ impl Machine
             state,
             faults,
             currentFault: None,
+            currentFaultIndex: None,
+            timeToFailureUs: Vec::new(),
+            needsTechnician: false,
+            technician: None,
+            technicianWaitClockUs: 0,
+            maintenancePlans: Vec::new(),
+            currentMaintenance: None,
+            maintenanceClockUs: 0,
+            faultChanceScale: 1.0,
+            faultChanceRecovery: 0.0,
+            wear: None,
+            wearLevel: 0.0,
             faultTimeCurrentUs: 0,
             faultClockUs: 0,
             debounceRate,
@@ -242,6 +585,7 @@ impl Machine
             processingBehavior: None,
             processingClockUs: 0,
             processingTickSpeedUs,
+            nominalProcessingTickSpeedUs: processingTickSpeedUs,
             processingInProgress: false,
             processingDebouncer: 0,
             
@@ -255,6 +599,21 @@ impl Machine
             inputInventory: 0,
             inputInvCapacity,
             nextInput: 0,
+            inputItems: VecDeque::new(),
+            incomingItem: None,
+            billOfMaterials: Vec::new(),
+            incomingLane: 0,
+            continuousInput: false,
+            continuousProcessing: false,
+            inputLevel: 0.0,
+            outputLevel: 0.0,
+            consumedFraction: 0.0,
+            batchMinSize: 1,
+            batchMaxSize: 0,
+            batchMaxWaitUs: None,
+            batchWaitClockUs: 0,
+            batchWaiting: false,
+            batchSize: 0,
             
             outputBehavior: None,
             outputClockUs: 0,
@@ -266,24 +625,477 @@ impl Machine
             outputInventory: 0,
             outputInvCapacity,
             nextOutput: 0,
+            outputItems: VecDeque::new(),
+            rejectIDs: Vec::new(),
+            nextReject: 0,
+            reworkIDs: Vec::new(),
+            nextRework: 0,
+            maxReworks: 0,
+            inspection: false,
+
+            productMix: Vec::new(),
+            transforms: HashMap::new(),
+            nextSerial: 0,
+            lastProduct: None,
+            defectChance: 0.0,
+            sensorDefectChance: 0.0,
+            qualitySensor: None,
+            sensorTolerance: variance / 2.0,
 
             sensor,
             baseline,
             variance,
+            sensorValue: baseline,
+            sensors: Vec::new(),
+
+            rng: ChaCha8Rng::seed_from_u64(0),
+            sensorRng: ChaCha8Rng::seed_from_u64(0),
+            productRng: ChaCha8Rng::seed_from_u64(0),
+            qualityRng: ChaCha8Rng::seed_from_u64(0),
+            failureRng: ChaCha8Rng::seed_from_u64(0),
             
             consumedCount: 0,
             producedCount: 0,
             stateChangeCount: 0,
             faultedCount: 0,
+            stateTimes: StateTimes::default(),
+            producedByType: BTreeMap::new(),
+            goodCount: 0,
+            scrapCount: 0,
+            reworkedCount: 0,
+            waitingForTechnicianUs: 0,
+            maintenanceCount: 0,
+            firstPass: BTreeMap::new(),
+            plannedStop: None,
         };
 
         return newMachine;
     }
 
-    pub fn update(&mut self, conveyors: &mut HashMap<String, RefCell<ConveyorBelt>>, deltaTime: u128)
+    // Takes a reading from the generic sensor and every named sensor, timeUs into the run
+    pub fn sampleSensors(&mut self, timeUs: u128)
+    {
+        if self.sensor
+        {
+            self.sensorValue = self.sensor_Sim();
+        }
+
+        let showing = self.showingPrecursors();
+        for sensor in self.sensors.iter_mut()
+        {
+            let precursors: Vec<(&Precursor, f64)> = showing.iter()
+                .filter_map(|(index, progress, _)| self.faults[*index].precursor.as_ref().map(|precursor| (precursor, *progress)))
+                .filter(|(precursor, _)| precursor.sensor == sensor.name)
+                .collect();
+            sensor.sample(timeUs, self.wearLevel, &precursors);
+        }
+    }
+
+    // Faults on their way whose precursor is showing, each with how far through its lead time it is
+    // and the time left before it fails the machine. The time left counts the way the fault's failure
+    // clock does, so for operating time it only runs down while the machine is processing.
+    pub fn showingPrecursors(&self) -> Vec<(usize, f64, u128)>
+    {
+        let mut showing = Vec::<(usize, f64, u128)>::new();
+        for (index, (fault, timeToFailureUs)) in self.faults.iter().zip(self.timeToFailureUs.iter()).enumerate()
+        {
+            if self.currentFaultIndex == Some(index)
+            {
+                continue;
+            }
+            if let (Some(precursor), Some(timeUs)) = (&fault.precursor, timeToFailureUs)
+            {
+                if let Some(progress) = precursor.progress(*timeUs)
+                {
+                    showing.push((index, progress, *timeUs));
+                }
+            }
+        }
+        showing
+    }
+
+    // Derives this machine's random streams from the factory-level seed. Every machine shares
+    // the same seed but draws from its own ChaCha stream, selected by hashing the machine ID,
+    // so adding or reordering machines in the config does not change any other machine's rolls.
+    pub fn seedRng(&mut self, factorySeed: u64)
+    {
+        let stream = streamFromID(&self.id);
+
+        self.rng = ChaCha8Rng::seed_from_u64(factorySeed);
+        self.rng.set_stream(stream);
+
+        self.sensorRng = ChaCha8Rng::seed_from_u64(factorySeed);
+        self.sensorRng.set_stream(stream ^ SENSOR_STREAM_MASK);
+
+        self.productRng = ChaCha8Rng::seed_from_u64(factorySeed);
+        self.productRng.set_stream(stream ^ PRODUCT_STREAM_MASK);
+
+        self.qualityRng = ChaCha8Rng::seed_from_u64(factorySeed);
+        self.qualityRng.set_stream(stream ^ QUALITY_STREAM_MASK);
+
+        self.failureRng = ChaCha8Rng::seed_from_u64(factorySeed);
+        self.failureRng.set_stream(stream ^ FAILURE_STREAM_MASK);
+
+        for sensor in self.sensors.iter_mut()
+        {
+            sensor.rng = ChaCha8Rng::seed_from_u64(factorySeed);
+            sensor.rng.set_stream(streamFromID(&format!("{}-{}", self.id, sensor.name)) ^ SENSOR_STREAM_MASK);
+        }
+    }
+
+    // Draws the first time to failure of every time-based fault
+    pub fn startFailureClocks(&mut self)
+    {
+        let mut timeToFailureUs = Vec::<Option<u128>>::new();
+        for fault in self.faults.iter()
+        {
+            timeToFailureUs.push(fault.timeBetweenFailures.as_ref().map(|distribution| distribution.sampleUs(&mut self.failureRng)));
+        }
+        self.timeToFailureUs = timeToFailureUs;
+    }
+
+    // Picks the type of a new product from the product mix
+    fn newProductType(&mut self) -> String
+    {
+        match self.productMix.len()
+        {
+            0 => String::from(DEFAULT_PRODUCT_TYPE),
+            1 => self.productMix[0].productType.clone(),
+            _ => {
+                let weights = WeightedIndex::new(self.productMix.iter().map(|share| share.weight)).expect("Product mix weights must be positive.");
+                self.productMix[weights.sample(&mut self.productRng)].productType.clone()
+            },
+        }
+    }
+
+    fn newSerial(&mut self) -> String
+    {
+        let serial = format!("{}-{}", self.id, self.nextSerial);
+        self.nextSerial += 1;
+        serial
+    }
+
+    // A product this machine is making from nothing, with a new serial
+    fn newProduct(&mut self) -> Product
+    {
+        let productType = self.newProductType();
+        let serial = self.newSerial();
+        Product { productType, serial, defective: false, reworkCount: 0, awaitingRework: false, rejected: false }
+    }
+
+    // Rolls whether a product just made is defective. The further the last reading the machine
+    // reported is from its baseline, wear drift included, the likelier a defect.
+    fn rollDefect(&mut self) -> bool
+    {
+        let mut chance = self.defectChance;
+        if self.sensorDefectChance > 0.0 && self.sensorTolerance > 0.0
+        {
+            let reading = match &self.qualitySensor
+            {
+                Some(name) => self.sensors.iter().find(|sensor| sensor.name == *name).map(|sensor| (sensor.value, sensor.baseline)),
+                None if self.sensor => Some((self.sensorValue, self.baseline)),
+                None => None,
+            };
+            if let Some((value, baseline)) = reading
+            {
+                chance += self.sensorDefectChance * (value - baseline).abs() / self.sensorTolerance;
+            }
+        }
+
+        if chance <= 0.0
+        {
+            return false;
+        }
+
+        self.qualityRng.gen::<f64>() < chance
+    }
+
+    // Puts a product processing just finished into the output inventory, after rolling for a defect.
+    // A product made from a defective part stays defective, but only a defect made here is noticed
+    // here, so one carried in from upstream goes on to the output lanes rather than the reject lanes.
+    fn finishProduct(&mut self, mut product: Product)
+    {
+        if !product.defective
+        {
+            product.defective = self.rollDefect();
+            product.rejected = product.defective;
+        }
+
+        if product.defective
+        {
+            self.scrapCount += 1;
+        }
+        else
+        {
+            self.goodCount += 1;
+        }
+
+        *self.producedByType.entry(product.productType.clone()).or_insert(0) += 1;
+        self.lastProduct = Some(product.clone());
+        self.outputItems.push_back(product);
+    }
+
+    // Carries the running state of a machine over to its reloaded version, which already has the
+    // new settings from the config. Work in progress continues against the new speeds, and
+    // inventories over the new capacities are cut down to them.
+    pub fn carryOver(&mut self, old: &Machine)
+    {
+        self.state = old.state;
+        self.currentFault = old.currentFault.clone();
+        self.currentFaultIndex = old.currentFaultIndex.filter(|index| *index < self.faults.len());
+        self.technician = old.technician.clone();
+        self.technicianWaitClockUs = old.technicianWaitClockUs;
+        self.faultTimeCurrentUs = old.faultTimeCurrentUs;
+        self.faultClockUs = old.faultClockUs;
+        self.currentMaintenance = old.currentMaintenance.filter(|index| *index < self.maintenancePlans.len());
+        self.maintenanceClockUs = old.maintenanceClockUs;
+        self.faultChanceScale = old.faultChanceScale;
+        self.faultChanceRecovery = old.faultChanceRecovery;
+        self.wearLevel = old.wearLevel;
+
+        // Sensors are matched by name, new ones start from their baseline
+        self.sensorValue = old.sensorValue;
+        for sensor in self.sensors.iter_mut()
+        {
+            if let Some(oldSensor) = old.sensors.iter().find(|oldSensor| oldSensor.name == sensor.name)
+            {
+                sensor.value = oldSensor.value;
+                sensor.walkOffset = oldSensor.walkOffset;
+                sensor.level = oldSensor.level;
+                sensor.lastState = oldSensor.lastState;
+                sensor.rng = oldSensor.rng.clone();
+            }
+        }
+        // Maintenance that is no longer in the config ends straight away
+        if old.state == OPCState::MAINTENANCE && self.currentMaintenance.is_none()
+        {
+            self.state = OPCState::PRODUCING;
+            self.technician = None;
+        }
+
+        self.processingClockUs = old.processingClockUs;
+        self.processingInProgress = old.processingInProgress;
+        self.processingDebouncer = old.processingDebouncer;
+
+        self.inputClockUs = old.inputClockUs;
+        self.inputInProgress = old.inputInProgress;
+        self.inputDebouncer = old.inputDebouncer;
+        self.inputWaiting = old.inputWaiting;
+        self.inputItems = old.inputItems.iter().take(self.inputInvCapacity).cloned().collect();
+        self.inputInventory = self.inputItems.len();
+        self.incomingItem = old.incomingItem.clone();
+
+        self.inputLevel = old.inputLevel.min(self.inputInvCapacity as f64);
+        self.outputLevel = old.outputLevel;
+        self.consumedFraction = old.consumedFraction;
+        self.batchWaitClockUs = old.batchWaitClockUs;
+        self.batchWaiting = old.batchWaiting;
+        self.batchSize = old.batchSize.min(self.inputInventory);
+
+        // Assembly lanes are matched by their conveyor, and an item on its way into a lane
+        // that is no longer in the bill of materials is dropped
+        if !self.billOfMaterials.is_empty() || !old.billOfMaterials.is_empty()
+        {
+            for lane in self.billOfMaterials.iter_mut()
+            {
+                if let Some(oldLane) = old.billOfMaterials.iter().find(|oldLane| oldLane.inputID == lane.inputID)
+                {
+                    lane.items = oldLane.items.iter().take(lane.capacity).cloned().collect();
+                    lane.starvedUs = oldLane.starvedUs;
+                }
+            }
+            self.inputInventory = self.billOfMaterials.iter().map(|lane| lane.items.len()).sum();
+
+            let incomingLane = old.billOfMaterials.get(old.incomingLane)
+                .and_then(|oldLane| self.billOfMaterials.iter().position(|lane| lane.inputID == oldLane.inputID));
+            match incomingLane
+            {
+                Some(lane) => self.incomingLane = lane,
+                None => {
+                    self.incomingItem = None;
+                    self.inputInProgress = false;
+                },
+            }
+        }
+        self.nextInput = if old.nextInput < self.inputIDs.len() { old.nextInput } else { 0 };
+
+        self.outputClockUs = old.outputClockUs;
+        self.outputInProgress = old.outputInProgress;
+        self.outputDebouncer = old.outputDebouncer;
+        self.outputWaiting = old.outputWaiting;
+        self.outputInventory = old.outputInventory.min(self.outputInvCapacity);
+        self.outputItems = old.outputItems.iter().take(self.outputInventory).cloned().collect();
+        self.nextOutput = if old.nextOutput < self.outputIDs.len() { old.nextOutput } else { 0 };
+        self.nextReject = if old.nextReject < self.rejectIDs.len() { old.nextReject } else { 0 };
+        self.nextRework = if old.nextRework < self.reworkIDs.len() { old.nextRework } else { 0 };
+        self.nextSerial = old.nextSerial;
+        self.lastProduct = old.lastProduct.clone();
+
+        self.producedCount = old.producedCount;
+        self.consumedCount = old.consumedCount;
+        self.stateChangeCount = old.stateChangeCount;
+        self.faultedCount = old.faultedCount;
+        self.stateTimes = old.stateTimes;
+        self.producedByType = old.producedByType.clone();
+        self.goodCount = old.goodCount;
+        self.scrapCount = old.scrapCount;
+        self.reworkedCount = old.reworkedCount;
+        self.waitingForTechnicianUs = old.waitingForTechnicianUs;
+        self.maintenanceCount = old.maintenanceCount;
+        self.firstPass = old.firstPass.clone();
+
+        self.rng = old.rng.clone();
+        self.sensorRng = old.sensorRng.clone();
+        self.productRng = old.productRng.clone();
+        self.qualityRng = old.qualityRng.clone();
+        self.failureRng = old.failureRng.clone();
+
+        // Time-based faults still in the same place in the fault list keep counting down,
+        // new ones start from a fresh draw. A rolled fault on its way still comes if it
+        // still has a precursor.
+        for (index, timeToFailureUs) in self.timeToFailureUs.iter_mut().enumerate()
+        {
+            let stillComing = timeToFailureUs.is_some() || self.faults[index].precursor.is_some();
+            if let (true, Some(Some(oldTimeUs))) = (stillComing, old.timeToFailureUs.get(index))
+            {
+                *timeToFailureUs = Some(*oldTimeUs);
+            }
+        }
+
+        // Maintenance still in the same place in the list keeps counting towards being due
+        for (plan, oldPlan) in self.maintenancePlans.iter_mut().zip(old.maintenancePlans.iter())
+        {
+            plan.cyclesSince = oldPlan.cyclesSince;
+            plan.operatingSinceUs = oldPlan.operatingSinceUs;
+            plan.calendarDue = oldPlan.calendarDue;
+        }
+    }
+
+    // Zeroes the counters without touching inventories, clocks or state, used when warm-up ends
+    pub fn resetStatistics(&mut self)
+    {
+        self.producedCount = 0;
+        self.consumedCount = 0;
+        self.stateChangeCount = 0;
+        self.faultedCount = 0;
+        self.stateTimes = StateTimes::default();
+        self.producedByType.clear();
+        self.goodCount = 0;
+        self.scrapCount = 0;
+        self.reworkedCount = 0;
+        self.waitingForTechnicianUs = 0;
+        self.maintenanceCount = 0;
+        self.firstPass.clear();
+        for lane in self.billOfMaterials.iter_mut()
+        {
+            lane.starvedUs = 0;
+        }
+    }
+
+    // Share of the products made that were good, 1.0 if nothing was made
+    pub fn quality(&self) -> f64
     {
+        let madeCount = self.goodCount + self.scrapCount;
+        if madeCount == 0
         {
-            if self.state != OPCState::FAULTED
+            return 1.0;
+        }
+
+        self.goodCount as f64 / madeCount as f64
+    }
+
+    // Share of the products inspected for the first time that passed, over every product type, 1.0 if none were
+    pub fn firstPassYield(&self) -> f64
+    {
+        let inspected: usize = self.firstPass.values().map(|count| count.inspected).sum();
+        if inspected == 0
+        {
+            return 1.0;
+        }
+
+        self.firstPass.values().map(|count| count.passed).sum::<usize>() as f64 / inspected as f64
+    }
+
+    // Whether there is enough input for one processing cycle, or any input at all for a flow machine
+    pub fn hasInputForCycle(&self) -> bool
+    {
+        if self.continuousProcessing
+        {
+            return self.inputLevel > FLOW_EPSILON;
+        }
+
+        // A batch machine runs a full batch, or a smaller one once it has waited long enough
+        if self.batchMaxSize > 0
+        {
+            let waitedLongEnough = match self.batchMaxWaitUs
+            {
+                Some(maxWaitUs) => self.batchWaitClockUs >= maxWaitUs,
+                None => false,
+            };
+            return self.inputInventory >= self.batchLimit() || (waitedLongEnough && self.inputInventory >= self.batchMinSize.max(1));
+        }
+
+        if self.billOfMaterials.is_empty()
+        {
+            return self.inputInventory >= self.cost;
+        }
+
+        self.billOfMaterials.iter().all(|lane| lane.items.len() >= lane.quantity)
+    }
+
+    // Returns true if anything about the machine changed, so the scheduler knows
+    // whether other machines or conveyors may be able to act at this same instant
+    pub fn update(&mut self, conveyors: &mut HashMap<String, RefCell<ConveyorBelt>>, deltaTime: u128) -> bool
+    {
+        let before = self.progressMarker();
+
+        if self.state == OPCState::MAINTENANCE
+        {
+            self.maintained(deltaTime);
+        }
+
+        // A faulted machine is repaired first, and maintenance is finished first, before the
+        // machine goes into planned stop. Work in progress is held where it is for the length of the stop.
+        let canStop = self.state != OPCState::FAULTED && self.state != OPCState::PLANNEDSTOP && self.state != OPCState::MAINTENANCE;
+        if let (Some(reason), true) = (&self.plannedStop, canStop)
+        {
+            self.state = OPCState::PLANNEDSTOP;
+            self.stateChangeCount += 1;
+            tracing::info!("ID {}: Planned stop ({}).", self.id, reason);
+        }
+        else if self.plannedStop.is_none() && self.state == OPCState::PLANNEDSTOP
+        {
+            self.state = OPCState::PRODUCING;
+            self.stateChangeCount += 1;
+            tracing::info!("ID {}: Planned stop over: Producing Again.", self.id);
+        }
+
+        if self.state == OPCState::PLANNEDSTOP
+        {
+            return self.progressMarker() != before;
+        }
+
+        // A time-based fault whose time has run out fails the machine before it does anything else.
+        // Maintenance in progress is finished first, and may well renew the fault.
+        if self.state != OPCState::FAULTED && self.state != OPCState::MAINTENANCE
+        {
+            if let Some(index) = self.timeToFailureUs.iter().position(|timeUs| *timeUs == Some(0))
+            {
+                self.startFault(index);
+            }
+        }
+
+        // Due maintenance waits for the cycle in progress to finish, a flow machine stops where it is
+        if self.state != OPCState::FAULTED && self.state != OPCState::MAINTENANCE && (!self.processingInProgress || self.continuousProcessing)
+        {
+            if let Some(index) = self.maintenancePlans.iter().position(|plan| plan.isDue())
+            {
+                self.startMaintenance(index);
+            }
+        }
+
+        {
+            if self.state != OPCState::FAULTED && self.state != OPCState::MAINTENANCE
             {
                 // Execute input
                 // Input needs to manage: 
@@ -293,7 +1105,7 @@ impl Machine
                 if self.inputBehavior.is_none()
                 {
                     tracing::error!("ID {}: Input behavior is not defined.", self.id);
-                    return;
+                    return false;
                 }
                 let inputBehavior = self.inputBehavior.unwrap();
                 inputBehavior(self, conveyors, deltaTime);
@@ -301,7 +1113,7 @@ impl Machine
         }
 
         {
-            if self.state != OPCState::FAULTED
+            if self.state != OPCState::FAULTED && self.state != OPCState::MAINTENANCE
             {
                 // Execute processing 
                 // Processing needs to manage:
@@ -310,10 +1122,28 @@ impl Machine
                 if self.processingBehavior.is_none()
                 {
                     tracing::error!("ID {}: Processing behavior is not defined.", self.id);
-                    return;
+                    return false;
                 }
                 let processingBehavior = self.processingBehavior.unwrap();
-                processingBehavior(self, deltaTime);
+                // A cycle runs at the speed its wear allowed when it started, a flow machine's rate follows its wear
+                if !self.processingInProgress || self.continuousProcessing
+                {
+                    self.applyWear();
+                }
+                let producedBefore = self.producedCount;
+                let finishedCycle = processingBehavior(self, deltaTime);
+
+                // A flow machine has no cycles, so every unit it makes counts as one
+                let cycles = if self.continuousProcessing { (self.producedCount - producedBefore) as u64 } else { finishedCycle as u64 };
+                for plan in self.maintenancePlans.iter_mut()
+                {
+                    plan.cyclesSince += cycles;
+                }
+                self.faultChanceScale = (self.faultChanceScale + self.faultChanceRecovery * cycles as f64).min(1.0);
+                if let Some(Wear { clock: WearClock::Cycles(perCycles), .. }) = self.wear
+                {
+                    self.addWear(cycles as f64 / perCycles as f64);
+                }
             }
         }
 
@@ -333,58 +1163,435 @@ impl Machine
             if self.outputBehavior.is_none()
             {
                 tracing::error!("ID {}: Output behavior is not defined.", self.id);
-                return;
+                return false;
             }
             let outputBehavior = self.outputBehavior.unwrap();
             outputBehavior(self, conveyors, deltaTime);
         }
+
+        self.progressMarker() != before
     }
 
-    // Function for faulted state
-    fn faulted(&mut self, deltaTime: u128)
+    // Snapshot of everything update() can change that matters to the rest of the line,
+    // compared before and after an update to tell if the machine did anything
+    fn progressMarker(&self) -> (OPCState, bool, bool, bool, usize, usize, usize, usize)
     {
-        // println!("ID {}: {}", self.id, self.faultMessage); //now prints the fault message from JSON
-        self.faultClockUs += deltaTime;
-        if self.faultClockUs < self.faultTimeCurrentUs 
-        {
+        (self.state, self.inputInProgress, self.processingInProgress, self.outputInProgress,
+                self.inputInventory, self.outputInventory, self.nextInput, self.nextOutput)
+    }
+
+    // Moves every running clock forward without completing anything, used by the scheduler
+    // to jump to the next event before letting update() act on it
+    pub fn advanceClocks(&mut self, deltaTime: u128)
+    {
+        self.stateTimes.add(self.state, deltaTime);
+        for sensor in self.sensors.iter_mut()
+        {
+            sensor.advance(self.state, deltaTime);
+        }
+
+        // Calendar time always counts, operating time only while processing
+        let processing = self.processingInProgress && self.state != OPCState::FAULTED && self.state != OPCState::PLANNEDSTOP && self.state != OPCState::MAINTENANCE;
+        for (fault, timeToFailureUs) in self.faults.iter().zip(self.timeToFailureUs.iter_mut())
+        {
+            if let Some(timeUs) = timeToFailureUs
+            {
+                if !fault.operatingTime || processing
+                {
+                    *timeUs = timeUs.saturating_sub(deltaTime);
+                }
+            }
+        }
+        if processing
+        {
+            for plan in self.maintenancePlans.iter_mut()
+            {
+                plan.operatingSinceUs += deltaTime;
+            }
+            if let Some(Wear { clock: WearClock::OperatingUs(perUs), .. }) = self.wear
+            {
+                self.addWear(deltaTime as f64 / perUs as f64);
+            }
+        }
+
+        // While an assembly machine is starved, every lane short of parts is to blame
+        if self.state == OPCState::STARVED || self.state == OPCState::STARVEDBLOCKED
+        {
+            for lane in self.billOfMaterials.iter_mut().filter(|lane| lane.items.len() < lane.quantity)
+            {
+                lane.starvedUs += deltaTime;
+            }
+        }
+
+        if self.state == OPCState::PLANNEDSTOP
+        {
             return;
         }
+
+        if self.continuousProcessing
+        {
+            self.advanceFlow(deltaTime);
+        }
+
+        if self.waitingForTechnician()
+        {
+            self.technicianWaitClockUs += deltaTime;
+            self.waitingForTechnicianUs += deltaTime;
+        }
+        else if self.state == OPCState::FAULTED
+        {
+            self.faultClockUs += deltaTime;
+        }
+        else if self.state == OPCState::MAINTENANCE
+        {
+            self.maintenanceClockUs += deltaTime;
+        }
+        else
+        {
+            if self.inputInProgress { self.inputClockUs += deltaTime; }
+            if self.processingInProgress { self.processingClockUs += deltaTime; }
+            if self.batchWaiting { self.batchWaitClockUs += deltaTime; }
+        }
+
+        if self.outputInProgress { self.outputClockUs += deltaTime; }
+    }
+
+    // Time until the next input, processing, output or fault repair completes,
+    // None if the machine is idle and waiting on the rest of the line
+    pub fn nextEventUs(&self) -> Option<u128>
+    {
+        let mut remaining = Vec::<u128>::new();
+
+        if self.state == OPCState::PLANNEDSTOP
+        {
+            return None;
+        }
+
+        // A fault or maintenance waiting for a technician has no end in sight until the factory assigns one
+        if self.state == OPCState::FAULTED && !self.waitingForTechnician()
+        {
+            remaining.push(self.faultTimeCurrentUs.saturating_sub(self.faultClockUs));
+        }
+        else if self.state == OPCState::MAINTENANCE && !self.waitingForTechnician()
+        {
+            let durationUs = self.currentMaintenance.map(|index| self.maintenancePlans[index].durationUs).unwrap_or(0);
+            remaining.push(durationUs.saturating_sub(self.maintenanceClockUs));
+        }
+        else if self.state != OPCState::FAULTED && self.state != OPCState::MAINTENANCE
+        {
+            if self.inputInProgress { remaining.push(self.inputTickSpeedUs.saturating_sub(self.inputClockUs)); }
+            if self.processingInProgress && !self.continuousProcessing { remaining.push(self.processingTickSpeedUs.saturating_sub(self.processingClockUs)); }
+            if let (true, Some(maxWaitUs)) = (self.batchWaiting, self.batchMaxWaitUs)
+            {
+                if self.batchWaitClockUs < maxWaitUs { remaining.push(maxWaitUs - self.batchWaitClockUs); }
+            }
+
+            for (fault, timeToFailureUs) in self.faults.iter().zip(self.timeToFailureUs.iter())
+            {
+                if let (Some(timeUs), true) = (timeToFailureUs, !fault.operatingTime || self.processingInProgress)
+                {
+                    remaining.push(*timeUs);
+                }
+            }
+
+            // Maintenance already due waits for the cycle to finish, which is scheduled above
+            if self.processingInProgress
+            {
+                remaining.extend(self.maintenancePlans.iter().filter_map(|plan| plan.operatingLeftUs()).filter(|timeUs| *timeUs > 0));
+            }
+        }
+
+        if self.outputInProgress { remaining.push(self.outputTickSpeedUs.saturating_sub(self.outputClockUs)); }
+
+        if self.continuousProcessing
+        {
+            remaining.extend(self.nextFlowEventUs());
+        }
+
+        remaining.into_iter().min()
+    }
+
+    fn addWear(&mut self, amount: f64)
+    {
+        if let Some(wear) = &self.wear
+        {
+            self.wearLevel = (self.wearLevel + amount).min(wear.maxWear.unwrap_or(f64::INFINITY));
+        }
+    }
+
+    // Sets the processing speed from the wear built up so far
+    fn applyWear(&mut self)
+    {
+        if let Some(wear) = &self.wear
+        {
+            self.processingTickSpeedUs = (self.nominalProcessingTickSpeedUs as f64 * (1.0 + wear.processingSlowdown * self.wearLevel)) as u128;
+        }
+    }
+
+    // Distance the sensor has drifted from its baseline through wear
+    fn sensorDrift(&self) -> f64
+    {
+        self.wear.as_ref().map(|wear| wear.sensorDrift * self.wearLevel).unwrap_or(0.0)
+    }
+
+    // Input used per unit of output
+    fn flowRatio(&self) -> f64
+    {
+        self.cost as f64 / self.throughput.max(1) as f64
+    }
+
+    // How fast input is flowing in and output is being made right now, in units per microsecond.
+    // Output runs at the processing rate while there is input and room for the unit being made,
+    // and at whatever the supply allows once the input has run dry. A full input only takes in
+    // as much as processing uses.
+    fn flowRates(&self) -> (f64, f64)
+    {
+        if self.state == OPCState::FAULTED || self.state == OPCState::PLANNEDSTOP || self.state == OPCState::MAINTENANCE
+        {
+            return (0.0, 0.0);
+        }
+
+        let supply = if self.continuousInput { ratePerUs(self.inputTickSpeedUs) } else { 0.0 };
+        let ratio = self.flowRatio();
+
+        let mut production = 0.0;
+        if self.outputInventory < self.outputInvCapacity
+        {
+            production = ratePerUs(self.processingTickSpeedUs);
+            if self.inputLevel <= FLOW_EPSILON && ratio > 0.0
+            {
+                production = production.min(supply / ratio);
+            }
+        }
+
+        let inflow = if self.inputLevel < self.inputInvCapacity as f64 - FLOW_EPSILON { supply } else { supply.min(production * ratio) };
+
+        (inflow, production)
+    }
+
+    // Moves a flow machine's levels forward. The rates cannot change between events, since an
+    // event is scheduled for every point where they could.
+    fn advanceFlow(&mut self, deltaTime: u128)
+    {
+        let (inflow, production) = self.flowRates();
+        let used = production * self.flowRatio() * deltaTime as f64;
+
+        self.inputLevel = (self.inputLevel + inflow * deltaTime as f64 - used).clamp(0.0, self.inputInvCapacity as f64);
+        self.outputLevel += production * deltaTime as f64;
+        self.consumedFraction += used;
+    }
+
+    // Time until a flow machine's input runs dry, fills up, or drops by a whole unit (making room
+    // for discrete input), or until the unit being made is finished
+    fn nextFlowEventUs(&self) -> Option<u128>
+    {
+        let (inflow, production) = self.flowRates();
+        let net = inflow - production * self.flowRatio();
+        let mut times = Vec::<f64>::new();
+
+        if net < 0.0 && self.inputLevel > FLOW_EPSILON
+        {
+            let nextWholeUnit = (self.inputLevel - FLOW_EPSILON).ceil() - 1.0;
+            times.push((self.inputLevel - nextWholeUnit.max(0.0)) / -net);
+        }
+        if net > 0.0 && self.inputLevel < self.inputInvCapacity as f64 - FLOW_EPSILON
+        {
+            times.push((self.inputInvCapacity as f64 - self.inputLevel) / net);
+        }
+        if production > 0.0
+        {
+            times.push((1.0 - self.outputLevel).max(0.0) / production);
+        }
+
+        times.into_iter()
+            .min_by(|a, b| a.total_cmp(b))
+            .map(|time| (time.ceil() as u128).max(1))
+    }
+
+    // Whether the current fault or maintenance cannot be worked on yet, as no technician has been assigned to it
+    pub fn waitingForTechnician(&self) -> bool
+    {
+        (self.state == OPCState::FAULTED || self.state == OPCState::MAINTENANCE) && self.needsTechnician && self.technician.is_none()
+    }
+
+    // Skill a technician needs for the current fault or maintenance, None if anyone can do it
+    pub fn jobSkill(&self) -> Option<String>
+    {
+        if self.state == OPCState::MAINTENANCE
+        {
+            return self.currentMaintenance.and_then(|index| self.maintenancePlans[index].skill.clone());
+        }
+
+        self.currentFault.as_ref().and_then(|fault| fault.skill.clone())
+    }
+
+    fn startMaintenance(&mut self, index: usize)
+    {
+        tracing::info!("ID {}: Maintenance ({}).", self.id, self.maintenancePlans[index].name);
+        self.state = OPCState::MAINTENANCE;
+        self.stateChangeCount += 1;
+        self.currentMaintenance = Some(index);
+        self.maintenanceClockUs = 0;
+        self.technician = None;
+        self.technicianWaitClockUs = 0;
+        // A flow machine picks up where it left off once it is done
+        self.processingInProgress = false;
+    }
+
+    // Function for maintenance state. Once it is done, each time-based fault has a restoration
+    // chance of starting over from a fresh draw, as if the machine were new again, and that
+    // share of the machine's wear is taken off.
+    fn maintained(&mut self, deltaTime: u128)
+    {
+        if self.waitingForTechnician()
+        {
+            return;
+        }
+
+        let index = self.currentMaintenance.expect("Machine is in maintenance without any maintenance.");
+        self.maintenanceClockUs += deltaTime;
+        if self.maintenanceClockUs < self.maintenancePlans[index].durationUs
+        {
+            return;
+        }
+
+        let restoration = self.maintenancePlans[index].restoration;
+        self.wearLevel *= 1.0 - restoration;
+        for (fault, timeToFailureUs) in self.faults.iter().zip(self.timeToFailureUs.iter_mut())
+        {
+            if let Some(distribution) = &fault.timeBetweenFailures
+            {
+                if self.failureRng.gen::<f64>() < restoration
+                {
+                    *timeToFailureUs = Some(distribution.sampleUs(&mut self.failureRng));
+                }
+            }
+            // Maintenance can also catch a rolled fault that is on its way
+            else if timeToFailureUs.is_some() && self.failureRng.gen::<f64>() < restoration
+            {
+                *timeToFailureUs = None;
+            }
+        }
+
+        // Rolled faults get less likely, then climb back to their faultChance as the machine runs again
+        let plan = &self.maintenancePlans[index];
+        let recoveryCycles = plan.recoveryCycles.unwrap_or(plan.cyclesSince).max(1);
+        self.faultChanceScale = self.faultChanceScale.min(1.0 - restoration);
+        self.faultChanceRecovery = (1.0 - self.faultChanceScale) / recoveryCycles as f64;
+        self.maintenancePlans[index].restart();
+
+        if let Some(technician) = self.technician.take()
+        {
+            tracing::info!("ID {}: Maintained by {}.", self.id, technician);
+        }
+
+        self.state = OPCState::PRODUCING;
+        self.currentMaintenance = None;
+        self.maintenanceClockUs = 0;
+        self.maintenanceCount += 1;
+        tracing::info!("ID {}: Maintenance done: Producing Again.", self.id);
+    }
+
+    // Function for faulted state
+    fn faulted(&mut self, deltaTime: u128)
+    {
+        if self.waitingForTechnician()
+        {
+            return;
+        }
+
+        // println!("ID {}: {}", self.id, self.faultMessage); //now prints the fault message from JSON
+        self.faultClockUs += deltaTime;
+        if self.faultClockUs < self.faultTimeCurrentUs 
+        {
+            return;
+        }
+        // A time-based fault starts counting down to its next failure once it is fixed
+        if let Some(distribution) = self.currentFaultIndex.and_then(|index| self.faults[index].timeBetweenFailures.clone())
+        {
+            self.timeToFailureUs[self.currentFaultIndex.unwrap()] = Some(distribution.sampleUs(&mut self.failureRng));
+        }
+
+        if let Some(technician) = self.technician.take()
+        {
+            tracing::info!("ID {}: Repaired by {}.", self.id, technician);
+        }
+
+        if self.wear.as_ref().is_some_and(|wear| wear.resetOnRepair)
+        {
+            self.wearLevel = 0.0;
+        }
+
         self.state = OPCState::PRODUCING;
         self.currentFault = None;
+        self.currentFaultIndex = None;
         self.faultTimeCurrentUs = 0;
         self.faultClockUs = 0;
         tracing::info!("ID {} : Has been fixed: Producing Again.", self.id);
     }
 
+    // Rolls every fault that is not time-based, faulting on the first one that hits.
+    // Wear makes every fault likelier. A fault with a precursor is put on its way instead,
+    // and fails the machine once its lead time has run down.
     fn checkIfShouldFault(&mut self) -> bool
     {
-        for fault in &self.faults {
+        let growth = self.wear.as_ref().map(|wear| 1.0 + wear.faultChanceGrowth * self.wearLevel).unwrap_or(1.0) as f32;
+        for i in 0_usize..self.faults.len() {
+            if self.faults[i].timeBetweenFailures.is_some() { continue; }
+            if self.timeToFailureUs.get(i).is_some_and(|timeUs| timeUs.is_some()) { continue; } // already on its way
+
             // Generate random value between 0 and 1000, used for determining if a fault happens
-            let faultSeed = rand::thread_rng().gen_range(0..1001);
-            if faultSeed as f32 / 1000.0 < fault.faultChance
-            {
-                self.currentFault = Some(fault.clone());
-                // Debug logging to show a message when the machine faults
-                tracing::debug!("ID {}: {}", self.id, fault.faultMessage); // TODO: more than one fault type
-                self.state = OPCState::FAULTED;
-                self.stateChangeCount += 1;
-                self.processingInProgress = false;
-                self.inputInProgress = false;
-                // Generate another random value, used for determining how long the fault will stay
-                let timeSeed = rand::thread_rng().gen_range(0..101);
-                let midTimePercent = timeSeed as f32 / 100.0; //turn seed into percentage
-                self.faultTimeCurrentUs = ((fault.faultTimeHighSec - fault.faultTimeLowSec) * midTimePercent + fault.faultTimeLowSec) as u128 * 1000 * 1000; //sets fault time to the a percent of the way between the low and high values.
-                self.faultClockUs = 0;
-                self.faultedCount += 1;
+            let faultSeed = self.rng.gen_range(0..1001);
+            if faultSeed as f32 / 1000.0 < self.faults[i].faultChance * growth * self.faultChanceScale as f32
+            {
+                if let Some(precursor) = &self.faults[i].precursor
+                {
+                    tracing::debug!("ID {}: {} on its way.", self.id, self.faults[i].faultMessage);
+                    self.timeToFailureUs[i] = Some(precursor.leadTimeUs);
+                    return false;
+                }
+                self.startFault(i);
                 return true;
             }
         }
         return false;
     }
 
+    fn startFault(&mut self, index: usize)
+    {
+        let fault = self.faults[index].clone();
+        // Debug logging to show a message when the machine faults
+        tracing::debug!("ID {}: {}", self.id, fault.faultMessage);
+        self.state = OPCState::FAULTED;
+        self.stateChangeCount += 1;
+        self.processingInProgress = false;
+        self.inputInProgress = false;
+        self.faultTimeCurrentUs = match &fault.timeToRepair
+        {
+            Some(distribution) => distribution.sampleUs(&mut self.failureRng),
+            None => {
+                // Generate another random value, used for determining how long the fault will stay
+                let timeSeed = self.rng.gen_range(0..101);
+                let midTimePercent = timeSeed as f32 / 100.0; //turn seed into percentage
+                ((fault.faultTimeHighSec - fault.faultTimeLowSec) * midTimePercent + fault.faultTimeLowSec) as u128 * 1000 * 1000 //sets fault time to the a percent of the way between the low and high values.
+            },
+        };
+        // A rolled fault that was on its way has arrived
+        if fault.timeBetweenFailures.is_none()
+        {
+            self.timeToFailureUs[index] = None;
+        }
+        self.currentFault = Some(fault);
+        self.currentFaultIndex = Some(index);
+        self.faultClockUs = 0;
+        self.technician = None;
+        self.technicianWaitClockUs = 0;
+        self.faultedCount += 1;
+    }
+
     pub fn updateState(&mut self)
     {
-        if self.state == OPCState::FAULTED
+        if self.state == OPCState::FAULTED || self.state == OPCState::PLANNEDSTOP || self.state == OPCState::MAINTENANCE
         {
             return;
         }
@@ -395,7 +1602,7 @@ impl Machine
         if !self.processingInProgress
         {
             // not enough input 
-            if self.inputInventory < self.cost && !self.inputWaiting
+            if !self.hasInputForCycle() && !self.inputWaiting
             {  
                 stateNotProducing = true;
 
@@ -528,6 +1735,28 @@ impl Machine
         return false;
     }
 
+    // The lanes the next product goes out on, and the one to start checking from. Products an
+    // inspection sent back go on the rework lanes, and products this machine found defective go
+    // on the reject lanes, if the machine has any.
+    fn outputLanes(&mut self) -> (&Vec<String>, &mut usize)
+    {
+        let (reworking, rejecting) = match self.outputItems.front()
+        {
+            Some(product) => (product.awaitingRework && !self.reworkIDs.is_empty(), product.rejected && !self.rejectIDs.is_empty()),
+            None => (false, false),
+        };
+        if reworking
+        {
+            return (&self.reworkIDs, &mut self.nextRework);
+        }
+        if rejecting
+        {
+            return (&self.rejectIDs, &mut self.nextReject);
+        }
+
+        (&self.outputIDs, &mut self.nextOutput)
+    }
+
     fn findOutputSingle(&mut self, conveyors: &mut HashMap<String, RefCell<ConveyorBelt>>) -> bool
     {
         if self.outputInventory <= 0
@@ -535,17 +1764,18 @@ impl Machine
             return false;
         }
 
-        for _i in 0 as usize..self.outputIDs.len()
+        let (outputIDs, nextOutput) = self.outputLanes();
+        for _i in 0_usize..outputIDs.len()
         {
-            let currentOutputID = &self.outputIDs[self.nextOutput];
+            let currentOutputID = &outputIDs[*nextOutput];
             let mut currentConveyor = 
                 conveyors.get(currentOutputID)
                         .expect(format!("Conveyor {currentOutputID} does not exist.").as_str())
                         .borrow_mut();
             if currentConveyor.isStartSome() == false { return true; }
 
-            self.nextOutput += 1;
-            self.nextOutput = self.nextOutput % self.outputIDs.len();
+            *nextOutput += 1;
+            *nextOutput %= outputIDs.len();
         }
         
         return false;
@@ -575,6 +1805,8 @@ impl Machine
             return false;
         }
 
+        let product = self.newProduct();
+        self.inputItems.push_back(product);
         self.inputInventory += 1;
         self.inputInProgress = false;
         return true;        
@@ -598,7 +1830,7 @@ impl Machine
                         .expect(format!("Conveyor {currentInputID} does not exist.").as_str())
                         .borrow_mut();
             // Take 1 item off it (reserve so nothing else can take it, essentially)
-            currentConveyor.pullItem();
+            self.incomingItem = currentConveyor.pullItem();
             // Increment nextInput for balanced taking of items
             self.nextInput += 1;
             self.nextInput = self.nextInput % self.inputIDs.len();
@@ -628,11 +1860,311 @@ impl Machine
             return false;
         }
 
+        let product = self.incomingItem.take().expect("Input finished without an item to take in.");
+        self.inputItems.push_back(product);
         self.inputInventory += 1;
         self.inputInProgress = false;
         return true;
     }
 
+    // Finds an assembly lane with a part waiting on its belt and room for it, starting from nextInput
+    fn findInputLane(&mut self, conveyors: &mut HashMap<String, RefCell<ConveyorBelt>>) -> Option<usize>
+    {
+        let laneCount = self.billOfMaterials.len();
+        for i in 0_usize..laneCount
+        {
+            let laneIndex = (self.nextInput + i) % laneCount;
+            let lane = &self.billOfMaterials[laneIndex];
+            if lane.items.len() >= lane.capacity
+            {
+                continue;
+            }
+
+            let mut currentConveyor = 
+                conveyors.get(&lane.inputID)
+                        .unwrap_or_else(|| panic!("Conveyor {} does not exist.", lane.inputID))
+                        .borrow_mut();
+            if currentConveyor.isEndSome() { return Some(laneIndex); }
+        }
+
+        None
+    }
+
+    // Inputs one part at a time into the bill of materials lane it came in on,
+    // only taking parts a lane has room for. Like singleInput, only if output is empty.
+    pub fn assemblyInput(&mut self, conveyors: &mut HashMap<String, RefCell<ConveyorBelt>>, deltaTime: u128) -> bool
+    {
+        if !self.inputInProgress && self.outputInventory == 0
+        {
+            let laneIndex = match self.findInputLane(conveyors)
+            {
+                Some(laneIndex) => laneIndex,
+                None => {
+                    self.inputWaiting = false;
+                    return false;
+                }
+            };
+
+            let inputID = &self.billOfMaterials[laneIndex].inputID;
+            let mut currentConveyor = 
+                conveyors.get(inputID)
+                        .unwrap_or_else(|| panic!("Conveyor {inputID} does not exist."))
+                        .borrow_mut();
+            self.incomingItem = currentConveyor.pullItem();
+            self.incomingLane = laneIndex;
+            // Move on to the next lane for balanced taking of parts
+            self.nextInput = (laneIndex + 1) % self.billOfMaterials.len();
+
+            self.inputWaiting = true;
+            self.inputInProgress = true;
+            self.inputClockUs = 0;
+        }
+        else { self.inputWaiting = self.outputInventory > 0 && self.findInputLane(conveyors).is_some(); }
+
+        if !self.inputInProgress 
+        { 
+            return false; 
+        }
+
+        if self.inputClockUs < self.inputTickSpeedUs
+        {
+            self.inputClockUs += deltaTime;
+            return false;
+        }
+
+        let product = self.incomingItem.take().expect("Input finished without an item to take in.");
+        self.billOfMaterials[self.incomingLane].items.push_back(product);
+        self.inputInventory += 1;
+        self.inputInProgress = false;
+        true
+    }
+
+    // Processes once every bill of materials lane has its quantity, only if the output inventory is empty.
+    // Outputs take their product type and serials from the parts in lane order, so the main part goes first.
+    pub fn assemblyProcessing(&mut self, deltaTime: u128) -> bool
+    {
+        if !self.processingInProgress
+        {
+            if self.hasInputForCycle() && self.outputInventory == 0 && self.outputInvCapacity >= self.throughput
+            { 
+                self.processingInProgress = true;
+                self.processingClockUs = 0;
+            }
+            else
+            {
+                self.processingInProgress = false;
+            }
+        }
+
+        if !self.processingInProgress { return false; }
+
+        if self.processingClockUs < self.processingTickSpeedUs
+        {
+            self.processingClockUs += deltaTime;
+            return false;
+        }
+
+        if self.checkIfShouldFault() { return false; }
+
+        // assemble
+        let mut consumed = Vec::<Product>::new();
+        for lane in self.billOfMaterials.iter_mut()
+        {
+            consumed.extend(lane.items.drain(..lane.quantity));
+        }
+        self.inputInventory -= consumed.len();
+        self.consumedCount += consumed.len();
+
+        self.outputInventory += self.throughput;
+        self.producedCount += self.throughput;
+        self.makeProducts(&consumed);
+
+        tracing::info!("ID {}: Assembled.", self.id);
+
+        self.processingInProgress = false;
+        true
+    }
+
+    #[allow(unused_variables)]
+    // Material is piped in from an unlimited supply at the input rate. The flow itself
+    // is worked out along with processing, so this only marks input as available.
+    pub fn flowInput(&mut self, conveyors: &mut HashMap<String, RefCell<ConveyorBelt>>, deltaTime: u128) -> bool
+    {
+        self.inputWaiting = true;
+        false
+    }
+
+    #[allow(unused_variables)]
+    // Turns input into output at the processing rate, with cost input used per throughput output.
+    // Discrete input is poured in as it arrives, and each whole unit of output becomes an item,
+    // with a fault roll for every unit.
+    pub fn flowProcessing(&mut self, deltaTime: u128) -> bool
+    {
+        let mut changed = false;
+
+        while self.inputItems.pop_front().is_some()
+        {
+            self.inputLevel += 1.0;
+        }
+        self.inputInventory = (self.inputLevel - FLOW_EPSILON).ceil().max(0.0) as usize;
+
+        let usedUnits = self.consumedFraction.floor();
+        self.consumedCount += usedUnits as usize;
+        self.consumedFraction -= usedUnits;
+
+        while self.outputLevel >= 1.0 - FLOW_EPSILON && self.outputInventory < self.outputInvCapacity
+        {
+            self.outputLevel = (self.outputLevel - 1.0).max(0.0);
+            self.outputInventory += 1;
+            self.producedCount += 1;
+            let product = self.newProduct();
+            self.finishProduct(product);
+            changed = true;
+
+            tracing::info!("ID {}: Produced.", self.id);
+
+            if self.checkIfShouldFault() { return true; }
+        }
+
+        self.processingInProgress = self.flowRates().1 > 0.0;
+        changed
+    }
+
+    // Largest batch the machine can run, limited by what its inventories can hold
+    fn batchLimit(&self) -> usize
+    {
+        self.batchMaxSize.min(self.inputInvCapacity).min(self.outputInvCapacity).max(1)
+    }
+
+    // Gathers items into a batch, then processes the whole batch in one cycle and puts it all
+    // into the output inventory to be released downstream. Only starts if the output inventory
+    // is empty, so the last batch has been released. Each item comes out as one item.
+    pub fn batchProcessing(&mut self, deltaTime: u128) -> bool
+    {
+        if !self.processingInProgress
+        {
+            if self.outputInventory == 0 && self.hasInputForCycle()
+            {
+                self.batchSize = self.inputInventory.min(self.batchLimit());
+                self.batchWaiting = false;
+                self.processingInProgress = true;
+                self.processingClockUs = 0;
+            }
+            else
+            {
+                // The wait starts with the first item of the batch
+                self.batchWaiting = self.inputInventory > 0;
+                self.processingInProgress = false;
+            }
+        }
+
+        if !self.processingInProgress { return false; }
+
+        if self.processingClockUs < self.processingTickSpeedUs
+        {
+            self.processingClockUs += deltaTime;
+            return false;
+        }
+
+        if self.checkIfShouldFault() { return false; }
+
+        // process the whole batch
+        self.inputInventory -= self.batchSize;
+        self.consumedCount += self.batchSize;
+        let batch: Vec<Product> = self.inputItems.drain(..self.batchSize).collect();
+        for input in batch
+        {
+            let product = Product { productType: self.transformedType(&input.productType), defective: input.passesOnDefect(),
+                    serial: input.serial, reworkCount: input.reworkCount, awaitingRework: false, rejected: false };
+            self.finishProduct(product);
+        }
+
+        self.outputInventory += self.batchSize;
+        self.producedCount += self.batchSize;
+        self.batchWaitClockUs = 0;
+
+        tracing::info!("ID {}: Processed a batch of {}.", self.id, self.batchSize);
+
+        self.processingInProgress = false;
+        true
+    }
+
+    // Checks one product at a time, only if the output inventory is empty. Good products go on to
+    // the output lanes. Defective ones are sent back on the rework lanes until they have been
+    // reworked maxReworks times, then scrapped, onto the reject lanes if there are any.
+    pub fn inspectionProcessing(&mut self, deltaTime: u128) -> bool
+    {
+        if !self.processingInProgress
+        {
+            if self.inputInventory > 0 && self.outputInventory == 0 && self.outputInvCapacity > 0
+            { 
+                self.processingInProgress = true;
+                self.processingClockUs = 0;
+            }
+            else
+            {
+                self.processingInProgress = false;
+            }
+        }
+
+        if !self.processingInProgress { return false; }
+
+        if self.processingClockUs < self.processingTickSpeedUs
+        {
+            self.processingClockUs += deltaTime;
+            return false;
+        }
+
+        if self.checkIfShouldFault() { return false; }
+
+        // inspect
+        self.inputInventory -= 1;
+        self.consumedCount += 1;
+        let mut product = self.inputItems.pop_front().expect("Input inventory has no item to inspect.");
+
+        if product.reworkCount == 0
+        {
+            let firstPass = self.firstPass.entry(product.productType.clone()).or_default();
+            firstPass.inspected += 1;
+            if !product.defective { firstPass.passed += 1; }
+        }
+
+        if !product.defective
+        {
+            self.goodCount += 1;
+            tracing::info!("ID {}: Passed {}.", self.id, product.serial);
+        }
+        else if product.reworkCount < self.maxReworks && !self.reworkIDs.is_empty()
+        {
+            product.reworkCount += 1;
+            product.awaitingRework = true;
+            self.reworkedCount += 1;
+            tracing::info!("ID {}: Sent {} back for rework.", self.id, product.serial);
+        }
+        else
+        {
+            self.scrapCount += 1;
+            tracing::info!("ID {}: Scrapped {}.", self.id, product.serial);
+
+            // Without reject lanes, scrap leaves the line here
+            if self.rejectIDs.is_empty()
+            {
+                self.processingInProgress = false;
+                return true;
+            }
+            product.rejected = true;
+        }
+
+        self.outputInventory += 1;
+        self.producedCount += 1;
+        *self.producedByType.entry(product.productType.clone()).or_insert(0) += 1;
+        self.lastProduct = Some(product.clone());
+        self.outputItems.push_back(product);
+
+        self.processingInProgress = false;
+        true
+    }
+
     // Processess only if the output inventory is empty
     pub fn defaultProcessing(&mut self, deltaTime: u128) -> bool
     {
@@ -662,9 +2194,11 @@ impl Machine
         // process 
         self.inputInventory -= self.cost;
         self.consumedCount += self.cost;
+        let consumed: Vec<Product> = self.inputItems.drain(..self.cost).collect();
 
         self.outputInventory += self.throughput;
         self.producedCount += self.throughput;
+        self.makeProducts(&consumed);
 
         tracing::info!("ID {}: Produced.", self.id);
 
@@ -672,6 +2206,33 @@ impl Machine
         return true;
     }
 
+    // Puts the products made from the consumed ones into the output inventory. Each output takes
+    // the serial of the input in the same position, so a part keeps its serial through a 1 to 1
+    // machine, and any outputs beyond the inputs get new serials. The product type comes from the
+    // first input, through this machine's transforms, and every output is defective if any input was.
+    fn makeProducts(&mut self, consumed: &[Product])
+    {
+        let defective = consumed.iter().any(|input| input.passesOnDefect());
+        for i in 0..self.throughput
+        {
+            let product = match (consumed.first(), consumed.get(i))
+            {
+                (Some(first), Some(input)) => Product { productType: self.transformedType(&first.productType), serial: input.serial.clone(),
+                        defective, reworkCount: input.reworkCount, awaitingRework: false, rejected: false },
+                (Some(first), None) => Product { productType: self.transformedType(&first.productType), serial: self.newSerial(),
+                        defective, reworkCount: 0, awaitingRework: false, rejected: false },
+                (None, _) => self.newProduct(),
+            };
+
+            self.finishProduct(product);
+        }
+    }
+
+    fn transformedType(&self, productType: &str) -> String
+    {
+        self.transforms.get(productType).cloned().unwrap_or(String::from(productType))
+    }
+
     // Outputs one thing onto one lane at a time
     pub fn singleOutput(&mut self, conveyors: &mut HashMap<String, RefCell<ConveyorBelt>>, deltaTime: u128) -> bool
     {
@@ -701,18 +2262,21 @@ impl Machine
             return false;
         }
 
+        // The lanes are picked before the product is taken out, as it decides which lanes
+        let (outputIDs, nextOutput) = self.outputLanes();
+        let currentOutputID = outputIDs[*nextOutput].clone();
+        *nextOutput += 1;
+        *nextOutput %= outputIDs.len();
+
         self.outputInventory -= 1;
-        let currentOutputID = &self.outputIDs[self.nextOutput];
+        let product = self.outputItems.pop_front().expect("Output inventory has no item to output.");
         let mut currentConveyor = 
-            conveyors.get(currentOutputID)
+            conveyors.get(&currentOutputID)
                     .expect(format!("Conveyor {currentOutputID} does not exist.").as_str())
                     .borrow_mut();
-        currentConveyor.pushItem();
+        currentConveyor.pushItem(product);
         // self.beltInventories[nextOutput][0] = Some(BeltItem { moveClock: 0, tickSpeed: self.beltTickSpeed, isMoving: false });
 
-        self.nextOutput += 1;
-        self.nextOutput = self.nextOutput % self.outputIDs.len();
-
         self.outputInProgress = false;
         return true;
     }
@@ -740,8 +2304,310 @@ impl Machine
             return false;
         }
 
+        // The product leaves the line here
         self.outputInventory -= 1;
+        self.outputItems.pop_front();
         self.outputInProgress = false;
         return true;
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A machine with one even-odds fault and a noisy sensor, seeded from the given factory seed
+    fn seededMachine(id: &str, seed: u64) -> Machine
+    {
+        let fault = Fault {
+            faultChance: 0.5,
+            faultMessage: String::from("Test fault."),
+            faultTimeHighSec: 2.0,
+            faultTimeLowSec: 1.0,
+            timeBetweenFailures: None,
+            timeToRepair: None,
+            operatingTime: false,
+            skill: None,
+            precursor: None,
+        };
+        let mut machine = Machine::new(String::from(id), 1, 1, OPCState::PRODUCING, vec![fault], 1,
+            1000, 0, 1, 0, 1, true, 100.0, 10.0);
+        machine.seedRng(seed);
+        machine.startFailureClocks();
+        machine
+    }
+
+    fn faultRolls(machine: &mut Machine) -> Vec<bool>
+    {
+        (0..64).map(|_| machine.checkIfShouldFault()).collect()
+    }
+
+    fn sensorReadings(machine: &mut Machine) -> Vec<f64>
+    {
+        (0..64).map(|_| machine.sensor_Sim()).collect()
+    }
+
+    #[test]
+    fn sameSeedGivesTheSameRolls()
+    {
+        let mut first = seededMachine("machine-0", 42);
+        let mut second = seededMachine("machine-0", 42);
+        assert_eq!(faultRolls(&mut first), faultRolls(&mut second));
+        assert_eq!(sensorReadings(&mut first), sensorReadings(&mut second));
+    }
+
+    #[test]
+    fn differentSeedsGiveDifferentRolls()
+    {
+        assert_ne!(faultRolls(&mut seededMachine("machine-0", 1)), faultRolls(&mut seededMachine("machine-0", 2)));
+    }
+
+    #[test]
+    fn machinesDrawFromTheirOwnStreams()
+    {
+        assert_ne!(faultRolls(&mut seededMachine("machine-0", 42)), faultRolls(&mut seededMachine("machine-1", 42)));
+    }
+
+    #[test]
+    fn productMixConvergesOnItsWeights()
+    {
+        let mut machine = seededMachine("machine-0", 42);
+        machine.productMix = vec![
+            ProductShare { productType: String::from("a"), weight: 3.0 },
+            ProductShare { productType: String::from("b"), weight: 1.0 },
+        ];
+
+        let draws = 20000;
+        let shareA = (0..draws).filter(|_| machine.newProductType() == "a").count() as f64 / draws as f64;
+        assert!((shareA - 0.75).abs() < 0.01, "a made up {shareA} of the mix");
+    }
+
+    // A flow machine piped half a unit a second, able to make one unit a second from one unit of input
+    fn flowMachine(inputLevel: f64) -> Machine
+    {
+        let mut machine = seededMachine("machine-0", 42);
+        machine.continuousInput = true;
+        machine.continuousProcessing = true;
+        machine.inputTickSpeedUs = 2000000;
+        machine.processingTickSpeedUs = 1000000;
+        machine.inputInvCapacity = 5;
+        machine.inputLevel = inputLevel;
+        machine
+    }
+
+    fn assertClose(actual: f64, expected: f64)
+    {
+        assert!((actual - expected).abs() < 1e-9, "{actual} is not {expected}");
+    }
+
+    #[test]
+    fn flowDrainsStoredInputAtTheProcessingRate()
+    {
+        let mut machine = flowMachine(2.0);
+        machine.advanceFlow(1000000);
+
+        assertClose(machine.inputLevel, 1.5);
+        assertClose(machine.outputLevel, 1.0);
+        assertClose(machine.consumedFraction, 1.0);
+    }
+
+    #[test]
+    fn dryFlowRunsAtTheSupplyRate()
+    {
+        let mut machine = flowMachine(0.0);
+        machine.advanceFlow(1500000);
+
+        assertClose(machine.inputLevel, 0.0);
+        assertClose(machine.outputLevel, 0.75);
+        assertClose(machine.consumedFraction, 0.75);
+    }
+
+    #[test]
+    fn fullFlowOnlyTakesInWhatProcessingUses()
+    {
+        let mut machine = flowMachine(5.0);
+        machine.inputTickSpeedUs = 250000;
+        machine.advanceFlow(1000000);
+
+        assertClose(machine.inputLevel, 5.0);
+        assertClose(machine.outputLevel, 1.0);
+    }
+
+    // An inspection that sends defective products back on a rework lane up to twice
+    fn inspectionMachine() -> Machine
+    {
+        let mut machine = seededMachine("inspection", 42);
+        machine.faults.clear();
+        machine.inspection = true;
+        machine.processingTickSpeedUs = 0;
+        machine.outputInvCapacity = 1;
+        machine.reworkIDs = vec![String::from("rework")];
+        machine.maxReworks = 2;
+        machine
+    }
+
+    fn product(productType: &str, serial: &str, defective: bool) -> Product
+    {
+        Product { productType: String::from(productType), serial: String::from(serial), defective, reworkCount: 0, awaitingRework: false, rejected: false }
+    }
+
+    // Inspects one product, returning what came out, if anything
+    fn inspect(machine: &mut Machine, product: Product) -> Option<Product>
+    {
+        machine.inputItems.push_back(product);
+        machine.inputInventory += 1;
+        assert!(machine.inspectionProcessing(0));
+
+        machine.outputInventory = 0;
+        machine.outputItems.pop_front()
+    }
+
+    #[test]
+    fn defectsAreReworkedUpToTheLimitThenScrapped()
+    {
+        let mut machine = inspectionMachine();
+        let mut part = product("a", "part-0", true);
+
+        for reworks in 1..=2
+        {
+            // Reworked without its defect being fixed, so it comes back defective
+            part = inspect(&mut machine, part).expect("A part sent for rework went missing.");
+            assert!(part.awaitingRework);
+            assert_eq!(part.reworkCount, reworks);
+            assert_eq!(machine.reworkedCount, reworks as usize);
+            part.awaitingRework = false;
+        }
+
+        assert!(inspect(&mut machine, part).is_none(), "a part past the rework limit was not scrapped");
+        assert_eq!(machine.scrapCount, 1);
+        assert_eq!(machine.reworkedCount, 2);
+        assert_eq!(machine.goodCount, 0);
+    }
+
+    #[test]
+    fn firstPassYieldIsCountedPerProductType()
+    {
+        let mut machine = inspectionMachine();
+        for (serial, productType, defective) in [("0", "a", false), ("1", "a", true), ("2", "a", false), ("3", "a", false), ("4", "b", true), ("5", "b", false)]
+        {
+            inspect(&mut machine, product(productType, serial, defective));
+        }
+
+        // A reworked part passing does not count as a first pass
+        let mut reworked = product("b", "4", false);
+        reworked.reworkCount = 1;
+        inspect(&mut machine, reworked);
+
+        assert_eq!(machine.firstPass["a"].inspected, 4);
+        assert_eq!(machine.firstPass["a"].passed, 3);
+        assert_eq!(machine.firstPass["b"].inspected, 2);
+        assert_eq!(machine.firstPass["b"].passed, 1);
+        assert_eq!(machine.firstPassYield(), 4.0 / 6.0);
+        assert_eq!(machine.goodCount, 5);
+    }
+
+    fn wear(faultChanceGrowth: f64) -> Wear
+    {
+        Wear { clock: WearClock::Cycles(10), processingSlowdown: 0.0, faultChanceGrowth, sensorDrift: 0.0, maxWear: None, resetOnRepair: false }
+    }
+
+    #[test]
+    fn faultChanceGrowsWithWear()
+    {
+        let faultShare = |wearLevel: f64| {
+            let mut machine = seededMachine("machine-0", 42);
+            machine.faults[0].faultChance = 0.1;
+            machine.wear = Some(wear(1.0));
+            machine.wearLevel = wearLevel;
+            (0..20000).filter(|_| machine.checkIfShouldFault()).count() as f64 / 20000.0
+        };
+
+        assert!((faultShare(0.0) - 0.1).abs() < 0.01);
+        assert!((faultShare(2.0) - 0.3).abs() < 0.015);
+        assert!((faultShare(4.0) - 0.5).abs() < 0.015);
+    }
+
+    #[test]
+    fn faultChanceScalesDownAfterMaintenance()
+    {
+        let faultShare = |faultChanceScale: f64| {
+            let mut machine = seededMachine("machine-0", 42);
+            machine.faultChanceScale = faultChanceScale;
+            (0..20000).filter(|_| machine.checkIfShouldFault()).count() as f64 / 20000.0
+        };
+
+        assert_eq!(faultShare(0.0), 0.0);
+        assert!((faultShare(0.5) - 0.25).abs() < 0.015);
+        assert!((faultShare(1.0) - 0.5).abs() < 0.015);
+    }
+
+    // Share of products made defective with a sensorDefectChance of 0.2, after the machine is set up by prepare
+    fn defectShare(prepare: impl Fn(&mut Machine)) -> f64
+    {
+        let mut machine = seededMachine("machine-0", 42);
+        machine.sensorDefectChance = 0.2;
+        prepare(&mut machine);
+        (0..20000).filter(|_| machine.rollDefect()).count() as f64 / 20000.0
+    }
+
+    #[test]
+    fn defectRateRisesWithTheReportedReading()
+    {
+        // The generic sensor reads around 100, and a reading 5 away adds all of the chance
+        assert_eq!(defectShare(|machine| machine.sensorValue = 100.0), 0.0);
+        assert!((defectShare(|machine| machine.sensorValue = 102.5) - 0.1).abs() < 0.01);
+        assert!((defectShare(|machine| machine.sensorValue = 95.0) - 0.2).abs() < 0.015);
+
+        // Wear drift moves the reading the machine reports, and so the defect rate
+        let worn = defectShare(|machine| {
+            machine.variance = 0.0;
+            machine.wear = Some(Wear { sensorDrift: 2.5, ..wear(0.0) });
+            machine.wearLevel = 1.0;
+            machine.sampleSensors(0);
+        });
+        assert!((worn - 0.1).abs() < 0.01);
+    }
+
+    #[test]
+    fn defectsFollowTheNamedQualitySensor()
+    {
+        let named = |value: f64| defectShare(|machine| {
+            let sensor: crate::json::JSONSensor = serde_json::from_value(serde_json::json!({ "name": "temperature", "model": "uniform", "baseline": 20.0 }))
+                .expect("Test sensor does not parse.");
+            machine.sensors = vec![Sensor::new(&sensor)];
+            machine.sensors[0].value = value;
+            machine.qualitySensor = Some(String::from("temperature"));
+            machine.sensorTolerance = 10.0;
+            machine.sensorValue = 150.0; // the generic sensor is not followed
+        });
+
+        assert_eq!(named(20.0), 0.0);
+        assert!((named(25.0) - 0.1).abs() < 0.01);
+        assert!((named(30.0) - 0.2).abs() < 0.015);
+    }
+
+    #[test]
+    fn statesParseFromConfigAndDisplayNames()
+    {
+        assert_eq!(OPCState::parse("FAULTED"), Some(OPCState::FAULTED));
+        assert_eq!(OPCState::parse("plannedStop"), Some(OPCState::PLANNEDSTOP));
+        assert_eq!(OPCState::parse("STARVEDBLOCKED"), Some(OPCState::STARVEDBLOCKED));
+        for state in [OPCState::PRODUCING, OPCState::FAULTED, OPCState::BLOCKED, OPCState::STARVED,
+                OPCState::STARVEDBLOCKED, OPCState::PLANNEDSTOP, OPCState::MAINTENANCE]
+        {
+            assert_eq!(OPCState::parse(&state.to_string()), Some(state));
+        }
+
+        assert_eq!(OPCState::parse("broken"), None);
+        assert_eq!(OPCState::parse(""), None);
+    }
+
+    #[test]
+    fn sensorPollingDoesNotShiftFaultRolls()
+    {
+        let mut polled = seededMachine("machine-0", 42);
+        sensorReadings(&mut polled);
+        assert_eq!(faultRolls(&mut polled), faultRolls(&mut seededMachine("machine-0", 42)));
+    }
+}
@@ -9,11 +9,31 @@ use json::*;
 mod servers;
 use servers::*;
 
-use std::borrow::BorrowMut;
-use std::time::Instant;
+mod factory;
+use factory::*;
+
+mod batch;
+use batch::*;
+
+mod scheduler;
+use scheduler::*;
+
+mod snapshot;
+use snapshot::*;
+
+mod replications;
+
+mod experiment;
+use experiment::*;
+
+mod calendar;
+mod maintenance;
+mod sensor;
+
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
 use std::thread;
-use std::cell::{RefCell, RefMut};
+use std::cell::RefCell;
 use std::sync::Arc;
 use std::fs::File;
 
@@ -26,49 +46,117 @@ use tracing_subscriber::{
     filter::LevelFilter,
 };
 
-use in_container;
-
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use chrono::{Datelike, Timelike, Utc};
 
+// Longest the simulation thread sleeps between checks of the control panel signals, in microseconds
+const MAX_IDLE_SLEEP_US: u64 = 10000;
+
+// Namespace of the lines' nodes on the OPC server, each line adds its name to it
+const LINE_NAMESPACE: &str = "urn:line-server";
+
+// Every node a machine can have on the OPC server, keyed in nodeIDs as "{machineID}-{name}"
+const MACHINE_NODE_NAMES: [&str; 20] = ["folder", "state", "fault-msg", "waiting-for-technician", "produced-count", "consumed-count",
+        "good-count", "scrap-count", "reworked-count", "first-pass-yield", "state-change-count", "fault-count", "input-inventory", "output-inventory", "last-product-type", "last-serial",
+        "input-level", "output-level", "wear", "sensor"];
+
 fn main() -> Result<()>
 {
-    // Ensure the simulation state is set to running, and initialize the web server for the control panel
-    simStateManager(true, Some(SimulationState::STOP));    
+    // Batch mode runs a config headless and exits, without the OPC server or control panel
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--batch")
+    {
+        return batchMain(&args);
+    }
+    // Experiment mode runs every scenario of a parameter sweep headless and exits
+    if args.iter().any(|arg| arg == "--experiment")
+    {
+        return experimentMain(&args);
+    }
+
+    initLogging();
+
+    // Ensure the default line's state is set to stopped, and initialize the web server for the control panel
+    initLines();
     thread::spawn(|| {
         let _ = initWebServer();
     });
 
     // Set up the OPC UA server and keep track of the address space (Arc<RwLock<AddressSpace>>)
     let opcuaServer = initOPCServer();
-    let mut addressSpace = opcuaServer.address_space();
+    let addressSpace = opcuaServer.address_space();
     thread::spawn(|| {
         opcuaServer.run();
     });
 
-    // Loop forever, starting the simulation if it is stopped and the state is "RUNNING"
+    // Every line runs its simulation on its own thread, so lines can be started, paused and
+    // stopped independently of each other
+    let mut lineThreads = HashMap::<String, thread::JoinHandle<Result<()>>>::new();
+
+    // Loop forever, starting a line's simulation if it is stopped and its state is "RUNNING"
     // Exit if the signal is given by breaking the loop
     loop
     {
-        let state = simStateManager(false, None);
-        
-        if state == SimulationState::RUNNING
+        // Forget lines whose simulation has ended, so they can be started again. A line whose
+        // simulation failed or panicked is stopped, so it is not started again straight away.
+        let finishedLines: Vec<String> = lineThreads.iter().filter(|(_, thread)| thread.is_finished()).map(|(line, _)| line.clone()).collect();
+        for line in finishedLines
         {
-            simClockManager(true, false, None);
-            let _ = simulation(&mut addressSpace);
+            let thread = lineThreads.remove(&line).expect("Line thread ceased to exist.");
+            let failure = match thread.join()
+            {
+                Ok(Ok(())) => None,
+                Ok(Err(error)) => Some(error.to_string()),
+                Err(panic) => Some(match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>())
+                {
+                    (Some(message), _) => format!("Simulation panicked: {}", message),
+                    (_, Some(message)) => format!("Simulation panicked: {}", message),
+                    _ => String::from("Simulation panicked."),
+                }),
+            };
+            if let Some(failure) = failure
+            {
+                tracing::error!("Line {} stopped. {}", line, failure);
+                if simStateManager(&line, false, None) != SimulationState::EXIT
+                {
+                    simStateManager(&line, true, Some(SimulationState::STOP));
+                }
+            }
         }
-        else if state == SimulationState::EXIT
+
+        let lines = simLineManager(false, None);
+        if lines.iter().any(|line| simStateManager(line, false, None) == SimulationState::EXIT)
         {
             break;
         }
+
+        for line in lines
+        {
+            if simStateManager(&line, false, None) == SimulationState::RUNNING && !lineThreads.contains_key(&line)
+            {
+                simClockManager(&line, true, false, None);
+                let mut addressSpace = addressSpace.clone();
+                let lineName = line.clone();
+                lineThreads.insert(line, thread::spawn(move || simulation(&lineName, &mut addressSpace)));
+            }
+        }
+
+        thread::sleep(Duration::from_micros(MAX_IDLE_SLEEP_US));
+    }
+
+    // Let every line clean its nodes off the server before exiting
+    for (_, thread) in lineThreads
+    {
+        let _ = thread.join();
     }
 
     Ok(())
 }
 
-// Used to be main, this is the simulation logic that runs until the web server signals it to stop
-fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Result<()>
+// Logs to stdout and to a file in the data directory named after the time the program started.
+// Installed once, as every line logs through the same subscriber.
+fn initLogging()
 {
     // let filter = tracing_subscriber::filter::EnvFilter::builder()
     //     .with_default_directive(tracing_subscriber::filter::LevelFilter::DEBUG.into())
@@ -84,16 +172,7 @@ fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Res
     let fmtTime = Utc::now();
     let logFileName = format!("{}-{}-{}_{}-{}-{}-log.txt", fmtTime.month(), fmtTime.day(), fmtTime.year(), 
                         fmtTime.hour(), fmtTime.minute(), fmtTime.second());
-    let logFilePath: String;
-    if in_container::in_container()
-    {
-        logFilePath = format!("/home/data/{}", logFileName);
-    }
-    else
-    {
-        logFilePath = format!("./data/{}", logFileName);
-    }
-    let logFile = File::create(logFilePath);
+    let logFile = File::create(dataPath(&logFileName));
     let logFile = match logFile 
     {
         Ok(file) => file, 
@@ -111,34 +190,66 @@ fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Res
         .with(stdoutLogger)
         .with(fileLogger)
         .try_init();
+}
 
-    // Tuple of line data structures and settings
-    let factoryDataOption = factorySetup();
+// Used to be main, this is the simulation logic that runs until the web server signals it to stop.
+// A line that cannot be set up returns the error, and main stops the line.
+fn simulation(line: &str, addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> Result<()>
+{
+    // Lines share one logger, so each line's messages are tagged with its name
+    let _lineSpan = tracing::info_span!("line", name = line).entered();
 
-    if factoryDataOption.is_none(){
-        return Ok(());
+    // Every machine and conveyor on the line, along with the factory-level settings.
+    // Resumes from a snapshot instead of the config if one was chosen.
+    let snapshotName = simSnapshotManager(line, false, None);
+    let mut factory = if snapshotName.is_empty()
+    {
+        let data = loadFactoryData(&simConfigManager(line, false, None)).map_err(|error| anyhow!("Could not load config: {}", error))?;
+        factorySetup(data)
     }
+    else
+    {
+        loadSnapshot(&snapshotName).map_err(|error| anyhow!("Could not restore snapshot {}: {}", snapshotName, error))?
+    };
 
-    let factoryData = factoryDataOption.unwrap();
-
-    // HashMap<String, RefCell<Machine>> containing all machines
-    let mut machines = factoryData.0;
-    // Immutable reference vector containing every machine ID
-    let machineIDs = factoryData.1;
-    // HashMap<String, RefCell<ConveyorBelt>> containing all conveyors
-    let mut conveyors = factoryData.2;
-    // Immutable reference vector containing every conveyor ID
-    let conveyorIDs = factoryData.3;
+    // Lines are known by the name in their config, which could have changed since the line was started
+    if factory.name != line
+    {
+        return Err(anyhow!("Line {} was given a config named {}, set the config again to run it.", line, factory.name));
+    }
 
-    //Simulation speed
-    let simSpeed: f64 = factoryData.4;
-    // Server poll rate in microseconds
-    let pollRateUs = factoryData.5;
-    let mut pollDeltaTimeUs = 0; // microseconds passed since last poll 
+    //Simulation speed, can be changed by a config reload
+    let mut simSpeed: f64 = factory.simSpeed;
 
     // Set up the server with the new machine data, and get a Hashmap<String, NodeId> of all nodes
     // on the server
-    let nodeIDs = serverSetup(addressSpace, machines.clone(), "MyLine");
+    // Each line gets its own namespace, so lines with the same machine IDs do not clash
+    let namespace = lineNamespace(line);
+    let mut nodeIDs = serverSetup(addressSpace, &factory.machines, &factory.name, &namespace);
+
+    // Pushes every machine's current values to the OPC server
+    let pushToServer = |factory: &Factory, nodeIDs: &HashMap<String, NodeId>| {
+        // Simulated timestamps run at simSpeed, so a historian sees the line's own timeline
+        let sourceTimestamp = if factory.simulatedTimestamps
+        {
+            DateTime::from(factory.calendarTime().and_utc())
+        }
+        else
+        {
+            DateTime::now()
+        };
+
+        let mut addressSpace = addressSpace.write();
+        serverPoll(&mut addressSpace, &factory.machines, nodeIDs, &factory.machineIDs, &sourceTimestamp);
+    };
+
+    // Step and run-until requests left over from a previous run do not apply to this one
+    simStepManager(line, true, None);
+    simRunUntilManager(line, true, None);
+    simSaveSnapshotManager(line, true, None);
+    simReloadManager(line, true, None);
+    simElapsedManager(line, true, Some(factory.simTimeUs));
+    simMachinesManager(line, true, Some(factory.machineIDs.clone()));
 
     // Time at the instant of beginning the simulation, used to calculate
     // time passage based on the elapsed time from this moment in microseconds
@@ -152,14 +263,53 @@ fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Res
 
     // In the case the user sets a time limit, this will be used to stop the sim when the time has passed
     let mut executionTimer = 0; // Microseconds counter
-    let timerLimit = simTimerManager(false, None) as u128; // Time limit for the sim
+    let timerLimit = simTimerManager(line, false, None) as u128; // Time limit for the sim
     let timerExists = timerLimit != 0; // If this resolves to true, a timer was set
 
     // Loop until the signal is given to stop this simulation or exit the entire program
     // and perform the simulation logic
     let mut pauseHappened = false;
-    while simStateManager(false, None) != SimulationState::STOP && simStateManager(false, None) != SimulationState::EXIT
+    while simStateManager(line, false, None) != SimulationState::STOP && simStateManager(line, false, None) != SimulationState::EXIT
     {               
+        // Snapshots are taken between loop iterations, when no event is half handled
+        if let Some(snapshotName) = simSaveSnapshotManager(line, false, None)
+        {
+            if let Err(error) = saveSnapshot(&factory, &snapshotName)
+            {
+                tracing::error!("Could not save snapshot {}: {}", snapshotName, error);
+            }
+        }
+
+        // Config reloads are applied between loop iterations too, then the server is brought in line
+        // with the new machines, leaving the nodes of machines that are still there untouched
+        // A config that cannot be loaded leaves the line running as it was
+        let reloadData = simReloadManager(line, false, None).and_then(|configName| match loadFactoryData(&configName)
+        {
+            Ok(data) => Some(data),
+            Err(error) => {
+                tracing::error!("Could not reload config, carrying on with the old one: {}", error);
+                None
+            }
+        });
+        if let Some(data) = reloadData
+        {
+            let summary = factory.reload(data);
+            simSpeed = factory.simSpeed;
+            simMachinesManager(line, true, Some(factory.machineIDs.clone()));
+            {
+                let mut addressSpace = addressSpace.write();
+                for id in summary.removedMachines.iter()
+                {
+                    removeMachineNodes(&mut addressSpace, id, &mut nodeIDs);
+                }
+                for id in factory.machineIDs.iter()
+                {
+                    syncMachineNodes(&mut addressSpace, &namespace, &factory.machines.get(id).expect("Machine ceased to exist.").borrow(), &mut nodeIDs);
+                }
+            }
+            pushToServer(&factory, &nodeIDs);
+        }
+
         // Time at start of loop    
         iterTime = start.elapsed().as_micros();
 
@@ -167,13 +317,36 @@ fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Res
         deltaTime = ((iterTime - prevTime) as f64 * simSpeed) as u128;
 
         // Just log that a pause happened and skip all of the simulating if the pause signal is set
-        if simStateManager(false, None) == SimulationState::PAUSED 
+        if simStateManager(line, false, None) == SimulationState::PAUSED 
         {
             // Track time while paused for a total runtime tracker
-            simClockManager(false, true, Some(deltaTime));
+            simClockManager(line, false, true, Some(deltaTime));
             // Log loop start time, to calculate difference in time later
             prevTime = iterTime;
             pauseHappened = true; 
+
+            // Stepping is only done while paused, so the line can be inspected between steps
+            match simStepManager(line, false, None)
+            {
+                Some(StepRequest::Events(count)) => {
+                    factory.stepEvents(count, |factory, event| {
+                        if *event != EventKind::Poll
+                        {
+                            tracing::debug!("Stepped {:?} at {} us.", event, factory.simTimeUs);
+                        }
+                    });
+                    pushToServer(&factory, &nodeIDs);
+                },
+                Some(StepRequest::TimeMs(ms)) => {
+                    factory.runUntil(factory.simTimeUs + ms * 1000, |factory, event| {
+                        if *event == EventKind::Poll { pushToServer(factory, &nodeIDs); }
+                        false
+                    });
+                    pushToServer(&factory, &nodeIDs);
+                },
+                None => thread::sleep(Duration::from_micros(MAX_IDLE_SLEEP_US)),
+            }
+            simElapsedManager(line, true, Some(factory.simTimeUs));
             continue; 
         }
         
@@ -186,7 +359,7 @@ fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Res
         }   
 
         // Update runtime clocks with new deltaTime
-        simClockManager(false, true, Some(deltaTime));
+        simClockManager(line, false, true, Some(deltaTime));
 
         // If there is an execution time limit set, check it
         if timerExists
@@ -195,54 +368,52 @@ fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Res
             if executionTimer >= timerLimit
             {
                 tracing::debug!("Execution time exceeded, ending simulation.");
-                simStateManager(true, Some(SimulationState::STOP));
+                simStateManager(line, true, Some(SimulationState::STOP));
                 break;
             }
         }
 
-        // For every machine, update its state by checking if it needs to perform
-        // any actions, based on the amount of time that has passed.
-        // This works on a sort of "tick" system.
-        for id in machineIDs.iter()
-        {
-            machines.get_mut(id)
-                    .expect(format!("Machine {id} does not exist.").as_str())
-                    .borrow_mut()
-                    .get_mut()
-                    .update(&mut conveyors, deltaTime);
-        }
-        
-        // For every belt, update its state by checking if it needs to
-        // move anything, give anything, or take anything. This also works
-        // on a tick system.
-        for id in conveyorIDs.iter()
-        {
-            // Get reference to current conveyor
-            let mut conveyor = conveyors.get(id).expect(format!("Conveyor {id} does not exist.").as_str()).borrow_mut();
-            // Initialize input conveyor to None by default, as most belts will NOT take input from other belts
-            let mut inputConveyor: Option<RefMut<ConveyorBelt>> = None;
-            // If the current conveyor has some value for inputID (conveyor to take from),
-            // get that conveyor as a reference and make it an option
-            if conveyor.isInputIDSome
+        // Handle every event that has come due, pushing values to the OPC server on each poll,
+        // and stop at the exact event where the run-until condition is met, if there is one
+        let runCondition = simRunUntilManager(line, false, None);
+        let targetUs = factory.simTimeUs + deltaTime;
+        let conditionMet = factory.runUntil(targetUs, |factory, event| {
+            if *event == EventKind::Poll
             {
-                let inputID = conveyor.inputID.as_ref().unwrap();
-                inputConveyor = Some(conveyors.get(inputID).expect(format!("Conveyor {inputID} does not exist.").as_str()).borrow_mut());
+                pushToServer(factory, &nodeIDs);
             }
-            conveyor.update(inputConveyor, deltaTime);
-        }
 
+            match &runCondition
+            {
+                Some(condition) => factory.isConditionMet(condition),
+                None => false,
+            }
+        });
 
-        // Check if the server should poll for updates
-        pollDeltaTimeUs += deltaTime;
-        if pollDeltaTimeUs >= pollRateUs
+        if conditionMet
         {
-            pollDeltaTimeUs -= pollRateUs;
-            let mut addressSpace = addressSpace.write();
-            serverPoll(&mut addressSpace, &machines, &nodeIDs, &machineIDs);
+            tracing::info!("Run-until condition {:?} met at {} us, pausing.", runCondition.unwrap(), factory.simTimeUs);
+            simRunUntilManager(line, true, None);
+            simStateManager(line, true, Some(SimulationState::PAUSED));
+            pushToServer(&factory, &nodeIDs);
         }
+        simElapsedManager(line, true, Some(factory.simTimeUs));
 
         // Log loop start time, to calculate difference in time later
         prevTime = iterTime;
+
+        // Nothing can happen until the next event, so sleep until then instead of spinning.
+        // The sleep is capped so pause/stop signals and the control panel clocks stay responsive.
+        let mut sleepUs = MAX_IDLE_SLEEP_US;
+        if let Some(nextEventUs) = factory.events.peekTime()
+        {
+            if simSpeed > 0.0
+            {
+                let wallUs = (nextEventUs.saturating_sub(factory.simTimeUs) as f64 / simSpeed) as u64;
+                sleepUs = sleepUs.min(wallUs);
+            }
+        }
+        thread::sleep(Duration::from_micros(sleepUs));
     }
 
     // If we are outside the main loop, the simulation has ended, so we will wipe the data from the OPC server.
@@ -251,254 +422,350 @@ fn simulation(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>) -> std::io::Res
         addressSpace.write().delete(&nodeID, true);
     }
 
-    for id in machineIDs
-    {
-        let machine = machines.get(&id).expect("Machine ceased to exist.").borrow();
-        tracing::info!("\nMachine: {}\nConsumed: {}\nProduced: {}\nState Changes: {}\nFaults: {}", 
-                machine.id, machine.consumedCount, machine.producedCount, machine.stateChangeCount, machine.faultedCount);
-    }
+    factory.logStatistics();
     
     Ok(())
 }
 
-fn factorySetup() -> Option<(HashMap<String, RefCell<Machine>>, Vec<String>, 
-                        HashMap<String, RefCell<ConveyorBelt>>, Vec<String>, f64, u128)>
+// Namespace URI for a line's nodes, eg. urn:line-server:packaging
+fn lineNamespace(line: &str) -> String
+{
+    format!("{LINE_NAMESPACE}:{line}")
+}
+
+// Returns a tuple containing the new Server, as well as a HashMap of machine IDs to OPC NodeIDs
+// Set up the OPC server with tags, folders, etc for every machine, and give each machine
+// its variables/values to be updated later when the server polls
+fn serverSetup(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>, machinesHashMap: &HashMap<String, RefCell<Machine>>, lineName: &str, namespace: &str) -> HashMap<String, NodeId>
 {
-    let file_path = simConfigManager(false, None);
-    let json_data: String;
-    if in_container::in_container()
+    let mut nodeIDs = HashMap::<String, NodeId>::new();
+
     {
-        json_data = read_json_file(format!("/home/data/{}", file_path).as_str());
-    } else {
-        json_data = read_json_file(format!("./data/{}", file_path).as_str());
-    }
+        let mut addressSpace = addressSpace.write();
+        addressSpace.register_namespace(namespace).unwrap();
 
-    // If we get here, the web service already checked if the JSON has a valid structure
-    // using json schemas, so we can parse this without worrying about a panic
-    let data: JSONData = serde_json::from_str(&json_data).expect("Failed to parse JSON");
+        let folderID = addressSpace.add_folder(lineName, lineName, &NodeId::objects_folder_id()).unwrap();
+        nodeIDs.insert(String::from("root"), folderID.clone());
 
+        for machine in machinesHashMap.values()
+        {
+            syncMachineNodes(&mut addressSpace, namespace, &machine.borrow(), &mut nodeIDs);
+        }
+    }
 
-    tracing::info!("Factory Name: {}", data.factory.name);
-    tracing::info!("Description: {}", data.factory.description);
-    tracing::info!("Simulation Speed: {} ", data.factory.simSpeed);
-    tracing::info!("Poll Rate: {} milliseconds", data.factory.pollRateMs);
+    nodeIDs
+}
 
-    //Setting data to variables to be passed into the return
-    let factorySpeed = data.factory.simSpeed; 
-    let factoryPollRateUs = data.factory.pollRateMs * 1000; // milliseconds to microseconds
+// Gives a machine its folder and variables on the server, under the line's folder. Nodes the
+// machine already has are left alone, so a config reload can run this on every machine and
+// only the new machines and new sensors get nodes, while clients stay subscribed to the rest.
+fn syncMachineNodes(addressSpace: &mut AddressSpace, namespace: &str, machine: &Machine, nodeIDs: &mut HashMap<String, NodeId>)
+{
+    let ns = addressSpace.register_namespace(namespace).unwrap();
+    let machineID = machine.id.to_string();
 
-    let mut machines = HashMap::<String, RefCell<Machine>>::new();
-    let mut conveyors = HashMap::<String, RefCell<ConveyorBelt>>::new();
-    let mut machineIDs = Vec::<String>::new(); // Track all IDs, this makes iterating over the hashmap easier in the future
-    let mut conveyorIDs = Vec::<String>::new();
+    // Making folder for machine and its tags, child of line folder
+    let machineFolderID = match nodeIDs.get(&format!("{machineID}-folder"))
+    {
+        Some(folderID) => folderID.clone(),
+        None => {
+            let folderID = nodeIDs.get("root").expect("Line folder does not exist.").clone();
+            let machineName = format!("Machine-ID-{machineID}");
+            let machineFolderID = addressSpace.add_folder(machineName.clone(), machineName.clone(), &folderID).unwrap();
+            nodeIDs.insert(format!("{machineID}-folder"), machineFolderID.clone());
+            machineFolderID
+        }
+    };
 
-    for machine in data.factory.machines 
+    // Vector of this machine's variable nodes
+    let mut variables = Vec::<Variable>::new();
+
+    // Macro to add a new variable to the server, used in the form of:
+    // add_server_variable!(variable_name, machine_field, type), 
+    // eg. cost_amount, cost (for machine.cost), u64 (to cast the value to u64)
+    //
+    // This is done as a macro because it's impossible to vary the field of a 
+    // struct you add in a function, and this is also the "default case", some
+    // server variables deviate from this and are done manually.
+    macro_rules! add_server_variable
     {
-        let mut state = OPCState::PRODUCING;
+        ($var_name:expr, $($machine_field:ident).+, $type:ty) => {
+            {
+                let nodeName = $var_name;
+                if !nodeIDs.contains_key(&format!("{machineID}-{nodeName}"))
+                {
+                    let nodeID = NodeId::new(ns, format!("{machineID}-{nodeName}"));
+                    variables.push(
+                        Variable::new(&nodeID, 
+                        nodeName,
+                        nodeName,
+                        machine.$($machine_field).+ as $type));
+                    nodeIDs.insert(format!("{machineID}-{nodeName}"), nodeID);
+                }
+            }
+        }
+    }
+    
+    // State node initialization
+    // Done without macro for example of what macro does, and due to special case of converting
+    // state to string with .to_string()
+    nodeIDs.entry(format!("{machineID}-state")).or_insert_with(|| {
+        let stateVarName = "state";
+        let stateNodeID = NodeId::new(ns, format!("{machineID}-state"));
+        variables.push(
+            Variable::new(&stateNodeID,
+            stateVarName, 
+            stateVarName, 
+            machine.state.to_string()));
+        stateNodeID
+    });
 
-        match machine.state.to_lowercase().as_str()
+    // Fault message node initialization
+    // Done without macro due to the fault message being an option, unlike any other field used here
+    nodeIDs.entry(format!("{machineID}-fault-msg")).or_insert_with(|| {
+        let faultMsgVarName = "fault-message";
+        let faultMsgNodeID = NodeId::new(ns, format!("{machineID}-fault-msg"));
+        let mut faultMessage = String::from("");
+        if machine.currentFault.is_some()
         {
-            "producing" => state = OPCState::PRODUCING,
-            "faulted" => state = OPCState::FAULTED,
-            "blocked" => state = OPCState::BLOCKED,
-            "starved" => state = OPCState::STARVED,
-            _ => (),
+            faultMessage = machine.currentFault.clone().expect("Fault does not exist, somehow.").faultMessage;
         }
-        
-        let id = String::from(machine.id);
+        variables.push(
+            Variable::new(&faultMsgNodeID,
+            faultMsgVarName,
+            faultMsgVarName,
+            faultMessage));
+        faultMsgNodeID
+    });
 
-        let mut machineFaults = Vec::<Fault>::new();
-        for fault in machine.faults
+    // Waiting for technician node initialization
+    // Done without macro as it is worked out rather than read from a field, and only there when the line has technicians
+    let waitingNodeKey = format!("{machineID}-waiting-for-technician");
+    if machine.needsTechnician && !nodeIDs.contains_key(&waitingNodeKey)
+    {
+        let nodeName = "waiting-for-technician";
+        let nodeID = NodeId::new(ns, waitingNodeKey.clone());
+        variables.push(Variable::new(&nodeID, nodeName, nodeName, machine.waitingForTechnician()));
+        nodeIDs.insert(waitingNodeKey, nodeID);
+    }
+    else if !machine.needsTechnician
+    {
+        if let Some(nodeID) = nodeIDs.remove(&waitingNodeKey)
         {
-            machineFaults.push(Fault { faultChance: fault.faultChance, faultMessage: fault.faultMessage, 
-                    faultTimeHighSec: fault.faultTimeHighSec, faultTimeLowSec: fault.faultTimeLowSec });
+            // The technicians were taken out of the config by a reload
+            addressSpace.delete(&nodeID, true);
         }
-        
-        let mut newMachine = Machine::new(
-            id.clone(),
-            machine.cost,
-            machine.throughput,
-            state,
-            machineFaults,
-            data.factory.debounceRateInPolls,
-            machine.processingSpeedMs * 1000, // milliseconds to microseconds
-            machine.inputSpeedMs * 1000, // milliseconds to microseconds
-            machine.inputCapacity,
-            machine.outputSpeedMs * 1000, // milliseconds to microseconds
-            machine.outputCapacity,
-            machine.sensor,
-            machine.sensorBaseline,
-            machine.sensorVariance,
-        );
-        newMachine.inputIDs = machine.inputIDs;
-        newMachine.outputIDs = machine.outputIDs;
-
-        let mut inputBehavior: fn(&mut Machine, &mut HashMap<String, RefCell<ConveyorBelt>>, u128) -> bool = Machine::singleInput;
-        let mut processingBehavior: fn(&mut Machine, u128) -> bool = Machine::defaultProcessing;
-        let mut outputBehavior: fn(&mut Machine, &mut HashMap<String, RefCell<ConveyorBelt>>, u128) -> bool = Machine::singleOutput;
-        match machine.inputBehavior.to_lowercase().as_str()
-        {
-            "spawner" => inputBehavior = Machine::spawnerInput,
-            "single" => inputBehavior = Machine::singleInput,
-            // "flow" => inputBehavior = Machine::flowInput,
-            _ => (),
-        }
-        match machine.processingBehavior.to_lowercase().as_str()
-        {
-            "default" => processingBehavior = Machine::defaultProcessing,
-            // "flow" => processingBehavior = Machine::flowProcessing,
-            _ => (),
-        }
-        match machine.outputBehavior.to_lowercase().as_str()
-        {
-            "consumer" => outputBehavior = Machine::consumerOutput,
-            "default" => outputBehavior = Machine::singleOutput,
-            _ => (),
-        }
-
-        newMachine.inputBehavior = Some(inputBehavior);
-        newMachine.processingBehavior = Some(processingBehavior);
-        newMachine.outputBehavior = Some(outputBehavior);
-
-        machineIDs.push(id.clone());
-        machines.insert(id.clone(), RefCell::new(newMachine));
     }
 
-    for conveyor in data.factory.conveyors
+    add_server_variable!("produced-count", producedCount, u64);
+    add_server_variable!("consumed-count", consumedCount, u64);
+    add_server_variable!("good-count", goodCount, u64);
+    add_server_variable!("scrap-count", scrapCount, u64);
+    add_server_variable!("state-change-count", stateChangeCount, u64);
+    add_server_variable!("fault-count", faultedCount, u64);
+    add_server_variable!("input-inventory", inputInventory, u64);
+    add_server_variable!("output-inventory", outputInventory, u64);
+
+    // Last product nodes initialization
+    // Done without macro as the last product is an option, empty until the machine makes something
+    let (lastProductType, lastSerial) = lastProductValues(machine);
+    for (nodeName, value) in [("last-product-type", lastProductType), ("last-serial", lastSerial)]
     {
-        let id = String::from(conveyor.id);
-        conveyors.insert(id.clone(), RefCell::new(ConveyorBelt::new(id.clone(), conveyor.capacity, conveyor.beltSpeedMs * 1000, conveyor.inputID)));
-        conveyorIDs.push(id.clone());
+        nodeIDs.entry(format!("{machineID}-{nodeName}")).or_insert_with(|| {
+            let nodeID = NodeId::new(ns, format!("{machineID}-{nodeName}"));
+            variables.push(Variable::new(&nodeID, nodeName, nodeName, value));
+            nodeID
+        });
     }
 
-    return Some((machines, machineIDs, conveyors, conveyorIDs, factorySpeed, factoryPollRateUs));
-}
-
-// Returns a tuple containing the new Server, as well as a HashMap of machine IDs to OPC NodeIDs
-// Set up the OPC server with tags, folders, etc for every machine, and give each machine
-// its variables/values to be updated later when the server polls
-fn serverSetup(addressSpace: &mut Arc<opcuaRwLock<AddressSpace>>, machinesHashMap: HashMap<String, RefCell<Machine>>, lineName: &str) -> HashMap<String, NodeId>
-{
-    let machinesHashMap = machinesHashMap.values();
-    let mut machines = Vec::<Machine>::new();
-    for machine in machinesHashMap
+    // Flow machine nodes, the fractional input and output inventories
+    // Done without macro as the values are worked out rather than read from a field
+    for (nodeName, value) in [("input-level", machine.inputLevel), ("output-level", machine.outputInventory as f64 + machine.outputLevel)]
     {
-        machines.push(machine.borrow().clone());
+        let nodeKey = format!("{machineID}-{nodeName}");
+        if machine.continuousProcessing && !nodeIDs.contains_key(&nodeKey)
+        {
+            let nodeID = NodeId::new(ns, nodeKey.clone());
+            variables.push(Variable::new(&nodeID, nodeName, nodeName, value));
+            nodeIDs.insert(nodeKey, nodeID);
+        }
+        else if !machine.continuousProcessing
+        {
+            if let Some(nodeID) = nodeIDs.remove(&nodeKey)
+            {
+                // The machine stopped being a flow machine in a reload
+                addressSpace.delete(&nodeID, true);
+            }
+        }
     }
-    
-    let ns = {
-        let address_space = addressSpace.clone();
-        let mut address_space = address_space.write();
-        address_space.register_namespace("urn:line-server").unwrap()
-    };
-
-    let mut nodeIDs = HashMap::<String, NodeId>::new();
 
+    // Inspection nodes, the products sent back for rework and the first-pass yield
+    // Done without macro as they are only on inspection machines
+    for (nodeName, value) in [("reworked-count", Variant::from(machine.reworkedCount as u64)), ("first-pass-yield", Variant::from(machine.firstPassYield()))]
     {
-        let mut addressSpace = addressSpace.write();
+        let nodeKey = format!("{machineID}-{nodeName}");
+        if machine.inspection && !nodeIDs.contains_key(&nodeKey)
+        {
+            let nodeID = NodeId::new(ns, nodeKey.clone());
+            variables.push(Variable::new(&nodeID, nodeName, nodeName, value));
+            nodeIDs.insert(nodeKey, nodeID);
+        }
+        else if !machine.inspection
+        {
+            if let Some(nodeID) = nodeIDs.remove(&nodeKey)
+            {
+                // The machine stopped being an inspection in a reload
+                addressSpace.delete(&nodeID, true);
+            }
+        }
+    }
 
-        let folderID = addressSpace.add_folder(lineName, lineName, &NodeId::objects_folder_id()).unwrap();
-        nodeIDs.insert(String::from("root"), folderID.clone());
+    // Wear node, the units of wear built up
+    // Done without macro as it is only on machines that wear
+    let wearNodeKey = format!("{machineID}-wear");
+    if machine.wear.is_some() && !nodeIDs.contains_key(&wearNodeKey)
+    {
+        let nodeName = "wear";
+        let nodeID = NodeId::new(ns, wearNodeKey.clone());
+        variables.push(Variable::new(&nodeID, nodeName, nodeName, machine.wearLevel));
+        nodeIDs.insert(wearNodeKey, nodeID);
+    }
+    else if machine.wear.is_none()
+    {
+        if let Some(nodeID) = nodeIDs.remove(&wearNodeKey)
+        {
+            // Wear was taken out of the config by a reload
+            addressSpace.delete(&nodeID, true);
+        }
+    }
 
-        for i in 0 as usize..machines.len()
+    // Assembly lane nodes, the parts held in each bill of materials lane and whether it is short of parts
+    // Done without macro as there is a pair of nodes per lane
+    let mut laneNodeKeys = Vec::<String>::new();
+    for lane in machine.billOfMaterials.iter()
+    {
+        for nodeName in [format!("lane-{}-inventory", lane.inputID), format!("lane-{}-starved", lane.inputID)]
         {
-            let machineID = machines[i].id.to_string();
-            // Making folder for machine and its tags, child of line folder
-            let machineName = format!("Machine-ID-{machineID}");
-            let machineFolderID = addressSpace.add_folder(machineName.clone(), machineName.clone(), &folderID).unwrap();
-            
-            // Vector of this machine's variable nodes
-            let mut variables = Vec::<Variable>::new();
-
-            // Macro to add a new variable to the server, used in the form of:
-            // add_server_variable!(variable_name, machine_field, type), 
-            // eg. cost_amount, cost (for machine.cost), u64 (to cast the value to u64)
-            //
-            // This is done as a macro because it's impossible to vary the field of a 
-            // struct you add in a function, and this is also the "default case", some
-            // server variables deviate from this and are done manually.
-            macro_rules! add_server_variable
+            let nodeKey = format!("{machineID}-{nodeName}");
+            if !nodeIDs.contains_key(&nodeKey)
             {
-                ($var_name:expr, $($machine_field:ident).+, $type:ty) => {
-                    {
-                        let nodeName = $var_name;
-                        let nodeID = NodeId::new(ns, format!("{machineID}-{nodeName}"));
-                        variables.push(
-                            Variable::new(&nodeID, 
-                            nodeName,
-                            nodeName,
-                            machines[i].$($machine_field).+ as $type));
-                        nodeIDs.insert(format!("{machineID}-{nodeName}"), nodeID);
-                    }
+                let nodeID = NodeId::new(ns, nodeKey.clone());
+                let variable = if nodeName.ends_with("-inventory")
+                {
+                    Variable::new(&nodeID, nodeName.as_str(), nodeName.as_str(), lane.items.len() as u64)
                 }
+                else
+                {
+                    Variable::new(&nodeID, nodeName.as_str(), nodeName.as_str(), lane.items.len() < lane.quantity)
+                };
+                variables.push(variable);
+                nodeIDs.insert(nodeKey.clone(), nodeID);
             }
-            
-            // State node initialization
-            // Done without macro for example of what macro does, and due to special case of converting
-            // state to string with .to_string()
-            let stateVarName = "state";
-            let stateNodeID = NodeId::new(ns, format!("{machineID}-state"));
-            variables.push(
-                Variable::new(&stateNodeID,
-                stateVarName, 
-                stateVarName, 
-                machines[i].state.to_string()));
-            nodeIDs.insert(format!("{machineID}-state"), stateNodeID);
-
-            // Fault message node initialization
-            // Done without macro due to the fault message being an option, unlike any other field used here
-            let faultMsgVarName = "fault-message";
-            let faultMsgNodeID = NodeId::new(ns, format!("{machineID}-fault-msg"));
-            let mut faultMessage = String::from("");
-            if machines[i].currentFault.is_some()
-            {
-                faultMessage = machines[i].currentFault.clone().expect("Fault does not exist, somehow.").faultMessage;
-            }
-            variables.push(
-                Variable::new(&faultMsgNodeID,
-                faultMsgVarName,
-                faultMsgVarName,
-                faultMessage));
-            nodeIDs.insert(format!("{machineID}-fault-msg"), faultMsgNodeID);
-
-            add_server_variable!("produced-count", producedCount, u64);
-            add_server_variable!("consumed-count", consumedCount, u64);
-            add_server_variable!("state-change-count", stateChangeCount, u64);
-            add_server_variable!("fault-count", faultedCount, u64);
-            add_server_variable!("input-inventory", inputInventory, u64);
-            add_server_variable!("output-inventory", outputInventory, u64);
-            if machines[i].sensor == true
+            laneNodeKeys.push(nodeKey);
+        }
+    }
+
+    // Lanes taken out of the bill of materials by a reload
+    let staleLaneKeys: Vec<String> = nodeIDs.keys()
+        .filter(|key| key.starts_with(&format!("{machineID}-lane-")) && !laneNodeKeys.contains(key))
+        .cloned()
+        .collect();
+    for key in staleLaneKeys
+    {
+        let nodeID = nodeIDs.remove(&key).expect("NodeId ceased to exist.");
+        addressSpace.delete(&nodeID, true);
+    }
+
+    if machine.sensor
+    {
+        add_server_variable!("sensor", baseline, f64)
+    }
+    else if let Some(sensorNodeID) = nodeIDs.remove(&format!("{machineID}-sensor"))
+    {
+        // The sensor was turned off by a reload
+        addressSpace.delete(&sensorNodeID, true);
+    }
+
+    // Named sensor nodes, one per sensor with its units as the description
+    // Done without macro as there is a node per sensor
+    let mut sensorNodeKeys = Vec::<String>::new();
+    for sensor in machine.sensors.iter()
+    {
+        let nodeKey = format!("{machineID}-sensor-{}", sensor.name);
+        if !nodeIDs.contains_key(&nodeKey)
+        {
+            let nodeID = NodeId::new(ns, nodeKey.clone());
+            let mut variable = Variable::new(&nodeID, sensor.name.as_str(), sensor.name.as_str(), sensor.value);
+            if !sensor.units.is_empty()
             {
-                add_server_variable!("sensor", baseline, f64)
+                variable.set_description(LocalizedText::from(sensor.units.as_str()));
             }
+            variables.push(variable);
+            nodeIDs.insert(nodeKey.clone(), nodeID);
+        }
+        sensorNodeKeys.push(nodeKey);
+    }
+
+    // Sensors taken out of the config by a reload
+    let staleSensorKeys: Vec<String> = nodeIDs.keys()
+        .filter(|key| key.starts_with(&format!("{machineID}-sensor-")) && !sensorNodeKeys.contains(key))
+        .cloned()
+        .collect();
+    for key in staleSensorKeys
+    {
+        let nodeID = nodeIDs.remove(&key).expect("NodeId ceased to exist.");
+        addressSpace.delete(&nodeID, true);
+    }
+
+    let _ = addressSpace.add_variables(variables, &machineFolderID);
+}
+
+// Type and serial of the last product a machine made, empty strings if it has not made one
+fn lastProductValues(machine: &Machine) -> (String, String)
+{
+    match &machine.lastProduct
+    {
+        Some(product) => (product.productType.clone(), product.serial.clone()),
+        None => (String::new(), String::new()),
+    }
+}
 
-            let _ = addressSpace.add_variables(variables, &machineFolderID);
+// Takes a machine that is no longer on the line off the server
+fn removeMachineNodes(addressSpace: &mut AddressSpace, machineID: &str, nodeIDs: &mut HashMap<String, NodeId>)
+{
+    for nodeName in MACHINE_NODE_NAMES
+    {
+        if let Some(nodeID) = nodeIDs.remove(&format!("{machineID}-{nodeName}"))
+        {
+            addressSpace.delete(&nodeID, true);
         }
     }
 
-    return nodeIDs;
+    let laneAndSensorKeys: Vec<String> = nodeIDs.keys()
+        .filter(|key| key.starts_with(&format!("{machineID}-lane-")) || key.starts_with(&format!("{machineID}-sensor-")))
+        .cloned()
+        .collect();
+    for key in laneAndSensorKeys
+    {
+        let nodeID = nodeIDs.remove(&key).expect("NodeId ceased to exist.");
+        addressSpace.delete(&nodeID, true);
+    }
 }
 
 // Handles updating the values of each machine on the OPC server
-fn serverPoll(addressSpace: &mut AddressSpace, machines: &HashMap<String, RefCell<Machine>>, nodeIDs: &HashMap<String, NodeId>, ids: &Vec<String>)
+fn serverPoll(addressSpace: &mut AddressSpace, machines: &HashMap<String, RefCell<Machine>>, nodeIDs: &HashMap<String, NodeId>, ids: &[String],
+        sourceTimestamp: &DateTime)
 {
     let now = DateTime::now();
     // For every machine ID, get that machine and update all of its values on the OPC server
-    // as well as provide a current timestamp
-    // (maybe we should have this respect simSpeed, in case the user is running at multiplied timescale)
+    // with the given source timestamp, and the current time as the server timestamp
     for id in ids.iter()
     {
-        let mut machine = machines.get(id).expect("Machine ceased to exist.").borrow_mut();
+        let machine = machines.get(id).expect("Machine ceased to exist.").borrow();
         let machineID = machine.id.to_string();
 
-        // This checks for any machine state updates, and it is done here
-        // so that we don't have to make a second timer for state debouncing,
-        // and can instead measure the time it takes to change a state in
-        // server poll cycles
-        machine.updateState();
-
         let stateNodeID = nodeIDs.get(&format!("{machineID}-state")).expect("NodeId ceased to exist.");
-        addressSpace.set_variable_value(stateNodeID, machine.state.to_string(), &now, &now);
+        addressSpace.set_variable_value(stateNodeID, machine.state.to_string(), sourceTimestamp, &now);
 
 
         // Macro to update a variable on the server, used in the form of:
@@ -514,7 +781,7 @@ fn serverPoll(addressSpace: &mut AddressSpace, machines: &HashMap<String, RefCel
                 {
                     let nodeName = $var_name;
                     let nodeID = nodeIDs.get(&format!("{machineID}-{nodeName}")).expect("NodeId ceased to exist.");
-                    addressSpace.set_variable_value(nodeID, machine.$($machine_field).+ as $type, &now, &now);
+                    addressSpace.set_variable_value(nodeID, machine.$($machine_field).+ as $type, sourceTimestamp, &now);
                 }
             }
         }
@@ -525,23 +792,71 @@ fn serverPoll(addressSpace: &mut AddressSpace, machines: &HashMap<String, RefCel
         {
             faultMessage = machine.currentFault.clone().expect("Fault does not exist, somehow.").faultMessage;
         }
-        addressSpace.set_variable_value(faultMsgNodeID, faultMessage, &now, &now);
+        addressSpace.set_variable_value(faultMsgNodeID, faultMessage, sourceTimestamp, &now);
+
+        if machine.needsTechnician
+        {
+            let waitingNodeID = nodeIDs.get(&format!("{machineID}-waiting-for-technician")).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(waitingNodeID, machine.waitingForTechnician(), sourceTimestamp, &now);
+        }
 
         update_server_variable!("produced-count", producedCount, u64);
         update_server_variable!("consumed-count", consumedCount, u64);
+        update_server_variable!("good-count", goodCount, u64);
+        update_server_variable!("scrap-count", scrapCount, u64);
         update_server_variable!("state-change-count", stateChangeCount, u64);
         update_server_variable!("fault-count", faultedCount, u64);
         update_server_variable!("input-inventory", inputInventory, u64);
         update_server_variable!("output-inventory", outputInventory, u64);
 
-        if machine.sensor == true 
+        let (lastProductType, lastSerial) = lastProductValues(&machine);
+        for (nodeName, value) in [("last-product-type", lastProductType), ("last-serial", lastSerial)]
+        {
+            let nodeID = nodeIDs.get(&format!("{machineID}-{nodeName}")).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(nodeID, value, sourceTimestamp, &now);
+        }
+
+        if machine.continuousProcessing
+        {
+            let inputLevelNodeID = nodeIDs.get(&format!("{machineID}-input-level")).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(inputLevelNodeID, machine.inputLevel, sourceTimestamp, &now);
+            let outputLevelNodeID = nodeIDs.get(&format!("{machineID}-output-level")).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(outputLevelNodeID, machine.outputInventory as f64 + machine.outputLevel, sourceTimestamp, &now);
+        }
+
+        if machine.inspection
+        {
+            update_server_variable!("reworked-count", reworkedCount, u64);
+            let firstPassYieldNodeID = nodeIDs.get(&format!("{machineID}-first-pass-yield")).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(firstPassYieldNodeID, machine.firstPassYield(), sourceTimestamp, &now);
+        }
+
+        if machine.wear.is_some()
+        {
+            update_server_variable!("wear", wearLevel, f64);
+        }
+
+        for lane in machine.billOfMaterials.iter()
+        {
+            let inventoryNodeID = nodeIDs.get(&format!("{machineID}-lane-{}-inventory", lane.inputID)).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(inventoryNodeID, lane.items.len() as u64, sourceTimestamp, &now);
+            let starvedNodeID = nodeIDs.get(&format!("{machineID}-lane-{}-starved", lane.inputID)).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(starvedNodeID, lane.items.len() < lane.quantity, sourceTimestamp, &now);
+        }
+
+        if machine.sensor
         {
-            // Currently not stored in machine, should probably change later
-            
             //println!("Machine ID: {}", machine.id);   //here for debugging
-            let sensorVal = machine::Machine::sensor_Sim(machine.baseline, machine.variance);
+            // Read when the factory polled, so defects follow the same readings clients see
             let sensorNodeID = nodeIDs.get(&format!("{machineID}-sensor")).expect("NodeId ceased to exist.");
-            addressSpace.set_variable_value(sensorNodeID, sensorVal as f64, &now, &now);
+            addressSpace.set_variable_value(sensorNodeID, machine.sensorValue, sourceTimestamp, &now);
+        }
+
+        // Named sensors were read when the factory polled, so only their stored readings are sent
+        for sensor in machine.sensors.iter()
+        {
+            let sensorNodeID = nodeIDs.get(&format!("{machineID}-sensor-{}", sensor.name)).expect("NodeId ceased to exist.");
+            addressSpace.set_variable_value(sensorNodeID, sensor.value, sourceTimestamp, &now);
         }
     }
 }
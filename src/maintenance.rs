@@ -0,0 +1,141 @@
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Serialize, Deserialize};
+
+use crate::calendar::*;
+use crate::json::*;
+
+// A group of maintenance technicians who share the same skills
+pub struct TechnicianGroup
+{
+    pub name: String,
+    pub count: usize,
+    pub skills: Option<Vec<String>>, // None can repair any fault
+}
+impl TechnicianGroup
+{
+    pub fn new(group: &JSONTechnicianGroup) -> TechnicianGroup
+    {
+        TechnicianGroup { name: group.name.clone(), count: group.count, skills: group.skills.clone() }
+    }
+
+    // Faults without a skill can be repaired by anyone, the rest need a group with that skill
+    pub fn canRepair(&self, skill: &Option<String>) -> bool
+    {
+        match (skill, &self.skills)
+        {
+            (None, _) | (_, None) => true,
+            (Some(skill), Some(skills)) => skills.contains(skill),
+        }
+    }
+}
+
+// What makes preventive maintenance due
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MaintenanceTrigger
+{
+    Cycles(u64),
+    OperatingUs(u128),
+    Calendar, // The factory marks it due at the times of day it is scheduled for
+}
+
+// One preventive maintenance definition of a machine, with how far it is towards being due
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MaintenancePlan
+{
+    pub name: String,
+    pub trigger: MaintenanceTrigger,
+    pub durationUs: u128,
+    pub restoration: f64, // 0.0 through 1.0 chance each time-based fault starts over from a fresh draw, and share rolled faults drop by
+    pub recoveryCycles: Option<u64>, // cycles rolled faults take to climb back, None for the cycles since it was last done
+    pub skill: Option<String>, // skill a technician needs to do it, None for any technician
+    pub cyclesSince: u64, // processing cycles since it was last done
+    pub operatingSinceUs: u128, // time spent processing since it was last done
+    pub calendarDue: bool, // one of its scheduled times has passed since it was last done
+}
+impl MaintenancePlan
+{
+    pub fn new(maintenance: &JSONMaintenance, defaultName: &str) -> MaintenancePlan
+    {
+        // The schema makes sure there is exactly one trigger
+        let trigger = match (maintenance.everyCycles, maintenance.everyOperatingHours)
+        {
+            (Some(cycles), _) => MaintenanceTrigger::Cycles(cycles),
+            (None, Some(hours)) => MaintenanceTrigger::OperatingUs((hours * 3600.0 * 1000000.0) as u128), // hours to microseconds
+            (None, None) => MaintenanceTrigger::Calendar,
+        };
+
+        MaintenancePlan {
+            name: maintenance.name.clone().unwrap_or(String::from(defaultName)),
+            trigger,
+            durationUs: (maintenance.durationSec * 1000000.0) as u128, // seconds to microseconds
+            restoration: maintenance.restoration.unwrap_or(1.0),
+            recoveryCycles: maintenance.recoveryCycles,
+            skill: maintenance.skill.clone(),
+            cyclesSince: 0,
+            operatingSinceUs: 0,
+            calendarDue: false,
+        }
+    }
+
+    pub fn isDue(&self) -> bool
+    {
+        match self.trigger
+        {
+            MaintenanceTrigger::Cycles(cycles) => self.cyclesSince >= cycles,
+            MaintenanceTrigger::OperatingUs(operatingUs) => self.operatingSinceUs >= operatingUs,
+            MaintenanceTrigger::Calendar => self.calendarDue,
+        }
+    }
+
+    // Processing time left before it is due, None if it is not counted in processing time
+    pub fn operatingLeftUs(&self) -> Option<u128>
+    {
+        match self.trigger
+        {
+            MaintenanceTrigger::OperatingUs(operatingUs) => Some(operatingUs.saturating_sub(self.operatingSinceUs)),
+            _ => None,
+        }
+    }
+
+    pub fn restart(&mut self)
+    {
+        self.cyclesSince = 0;
+        self.operatingSinceUs = 0;
+        self.calendarDue = false;
+    }
+}
+
+// A time of day that makes one machine's calendar maintenance due, on some days of the week
+pub struct MaintenanceTime
+{
+    pub machineID: String,
+    pub plan: usize, // position of the plan in the machine's maintenancePlans
+    days: Vec<Weekday>,
+    time: NaiveTime,
+}
+impl MaintenanceTime
+{
+    pub fn new(machineID: &str, plan: usize, time: &JSONMaintenanceTime) -> MaintenanceTime
+    {
+        MaintenanceTime {
+            machineID: String::from(machineID),
+            plan,
+            days: parseDays(&time.days),
+            time: NaiveTime::parse_from_str(&time.time, TIME_FORMAT).expect("Maintenance time is not a valid time."),
+        }
+    }
+
+    pub fn isAt(&self, time: NaiveDateTime) -> bool
+    {
+        self.days.contains(&time.weekday()) && time.time() == self.time
+    }
+
+    // The first time after this one that it comes round again
+    pub fn next(&self, time: NaiveDateTime) -> Option<NaiveDateTime>
+    {
+        (0..=7).map(|offset| time.date() + Duration::days(offset))
+            .filter(|date| self.days.contains(&date.weekday()))
+            .map(|date| date.and_time(self.time))
+            .find(|next| *next > time)
+    }
+}
@@ -0,0 +1,289 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use serde::Serialize;
+
+use crate::factory::*;
+use crate::json::*;
+
+// Two-sided 95% Student's t critical values for 1 to 30 degrees of freedom
+const T_CRITICAL_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+fn tCritical95(degreesOfFreedom: usize) -> f64
+{
+    if degreesOfFreedom == 0
+    {
+        return f64::NAN;
+    }
+
+    // Past 30 the t distribution is close enough to normal
+    *T_CRITICAL_95.get(degreesOfFreedom - 1).unwrap_or(&1.960)
+}
+
+// Mean, sample standard deviation and 95% confidence interval of the mean for one figure
+#[derive(Serialize)]
+pub struct Summary
+{
+    pub mean: f64,
+    pub stdDev: f64,
+    pub ci95Low: f64,
+    pub ci95High: f64,
+}
+
+pub fn summarize(values: &[f64]) -> Summary
+{
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+
+    // A single run has no spread to estimate, so its interval is just the value
+    let mut stdDev = 0.0;
+    let mut halfWidth = 0.0;
+    if values.len() > 1
+    {
+        let squares: f64 = values.iter().map(|value| (value - mean).powi(2)).sum();
+        stdDev = (squares / (n - 1.0)).sqrt();
+        halfWidth = tCritical95(values.len() - 1) * stdDev / n.sqrt();
+    }
+
+    Summary { mean, stdDev, ci95Low: mean - halfWidth, ci95High: mean + halfWidth }
+}
+
+// Every figure summarized for one machine across all replications
+#[derive(Serialize)]
+pub struct MachineSummary
+{
+    pub id: String,
+    pub producedCount: Summary,
+    pub consumedCount: Summary,
+    pub faultedCount: Summary,
+    pub maintenanceCount: Summary,
+    pub stateChangeCount: Summary,
+    pub producingSec: Summary,
+    pub faultedSec: Summary,
+    pub blockedSec: Summary,
+    pub starvedSec: Summary,
+    pub starvedBlockedSec: Summary,
+    pub plannedStopSec: Summary,
+    pub maintenanceSec: Summary,
+    pub waitingForTechnicianSec: Summary,
+    pub availability: Summary,
+    pub quality: Summary,
+}
+
+// Line-level figures summarized across all replications. Time in state is summed over every
+// machine, so it is in machine-seconds.
+#[derive(Serialize)]
+pub struct LineSummary
+{
+    pub producedCount: Summary,
+    pub goodCount: Summary,
+    pub faultedCount: Summary,
+    pub producingSec: Summary,
+    pub faultedSec: Summary,
+    pub blockedSec: Summary,
+    pub starvedSec: Summary,
+    pub starvedBlockedSec: Summary,
+    pub plannedStopSec: Summary,
+    pub maintenanceSec: Summary,
+    pub waitingForTechnicianSec: Summary,
+}
+
+#[derive(Serialize)]
+pub struct ReplicationReport
+{
+    pub factory: String,
+    pub replications: usize,
+    pub seeds: Vec<u64>, // Any single replication can be rerun in batch mode with its seed
+    pub simulatedTimeSec: f64,
+    pub warmupSec: f64,
+    pub measuredTimeSec: f64,
+    pub line: LineSummary,
+    pub machines: Vec<MachineSummary>,
+}
+
+// Runs count copies of the config for horizonUs each, replication i seeded with baseSeed + i
+pub fn runReplications(data: &JSONData, horizonUs: u128, count: usize, baseSeed: u64) -> Vec<RunStatistics>
+{
+    let mut runs = Vec::<JSONData>::new();
+    for replication in 0..count
+    {
+        let mut replicationData = data.clone();
+        replicationData.factory.seed = Some(baseSeed.wrapping_add(replication as u64));
+        runs.push(replicationData);
+    }
+
+    runParallel(&runs, horizonUs)
+}
+
+// Runs every config for horizonUs, returning their statistics in the same order.
+// Runs are spread over one thread per core, and every run builds its own factory, so the
+// results only depend on the configs and not on how the threads were scheduled.
+pub fn runParallel(runs: &[JSONData], horizonUs: u128) -> Vec<RunStatistics>
+{
+    let count = runs.len();
+    let threadCount = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(count);
+    let nextRun = AtomicUsize::new(0);
+
+    let mut results: Vec<(usize, RunStatistics)> = thread::scope(|scope| {
+        let mut workers = Vec::new();
+        for _ in 0..threadCount
+        {
+            workers.push(scope.spawn(|| {
+                let mut finished = Vec::<(usize, RunStatistics)>::new();
+                loop
+                {
+                    let run = nextRun.fetch_add(1, Ordering::Relaxed);
+                    if run >= count
+                    {
+                        return finished;
+                    }
+
+                    let mut factory = factorySetup(runs[run].clone());
+                    factory.runUntil(horizonUs, |_, _| false);
+                    finished.push((run, factory.statistics()));
+                }
+            }));
+        }
+
+        workers.into_iter()
+            .flat_map(|worker| worker.join().expect("Simulation thread panicked."))
+            .collect()
+    });
+
+    results.sort_by_key(|(run, _)| *run);
+    results.into_iter().map(|(_, statistics)| statistics).collect()
+}
+
+pub fn summarizeReplications(runs: &[RunStatistics]) -> ReplicationReport
+{
+    let first = &runs[0];
+
+    // Pulls one figure out of every run and summarizes it
+    let across = |figure: &dyn Fn(&RunStatistics) -> f64| -> Summary {
+        summarize(&runs.iter().map(figure).collect::<Vec<f64>>())
+    };
+
+    let mut machines = Vec::<MachineSummary>::new();
+    for (i, machine) in first.machines.iter().enumerate()
+    {
+        machines.push(MachineSummary {
+            id: machine.id.clone(),
+            producedCount: across(&|run| run.machines[i].producedCount as f64),
+            consumedCount: across(&|run| run.machines[i].consumedCount as f64),
+            faultedCount: across(&|run| run.machines[i].faultedCount as f64),
+            maintenanceCount: across(&|run| run.machines[i].maintenanceCount as f64),
+            stateChangeCount: across(&|run| run.machines[i].stateChangeCount as f64),
+            producingSec: across(&|run| run.machines[i].producingSec),
+            faultedSec: across(&|run| run.machines[i].faultedSec),
+            blockedSec: across(&|run| run.machines[i].blockedSec),
+            starvedSec: across(&|run| run.machines[i].starvedSec),
+            starvedBlockedSec: across(&|run| run.machines[i].starvedBlockedSec),
+            plannedStopSec: across(&|run| run.machines[i].plannedStopSec),
+            maintenanceSec: across(&|run| run.machines[i].maintenanceSec),
+            waitingForTechnicianSec: across(&|run| run.machines[i].waitingForTechnicianSec),
+            availability: across(&|run| run.machines[i].availability),
+            quality: across(&|run| run.machines[i].quality),
+        });
+    }
+
+    let line = LineSummary {
+        producedCount: across(&|run| run.lineProducedCount as f64),
+        goodCount: across(&|run| run.lineGoodCount as f64),
+        faultedCount: across(&|run| run.lineFaultedCount as f64),
+        producingSec: across(&|run| run.machines.iter().map(|machine| machine.producingSec).sum()),
+        faultedSec: across(&|run| run.machines.iter().map(|machine| machine.faultedSec).sum()),
+        blockedSec: across(&|run| run.machines.iter().map(|machine| machine.blockedSec).sum()),
+        starvedSec: across(&|run| run.machines.iter().map(|machine| machine.starvedSec).sum()),
+        starvedBlockedSec: across(&|run| run.machines.iter().map(|machine| machine.starvedBlockedSec).sum()),
+        plannedStopSec: across(&|run| run.machines.iter().map(|machine| machine.plannedStopSec).sum()),
+        maintenanceSec: across(&|run| run.machines.iter().map(|machine| machine.maintenanceSec).sum()),
+        waitingForTechnicianSec: across(&|run| run.machines.iter().map(|machine| machine.waitingForTechnicianSec).sum()),
+    };
+
+    ReplicationReport {
+        factory: first.factory.clone(),
+        replications: runs.len(),
+        seeds: runs.iter().map(|run| run.seed).collect(),
+        simulatedTimeSec: first.simulatedTimeSec,
+        warmupSec: first.warmupSec,
+        measuredTimeSec: first.measuredTimeSec,
+        line,
+        machines,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn assertClose(actual: f64, expected: f64)
+    {
+        assert!((actual - expected).abs() < 1e-9, "{actual} is not {expected}");
+    }
+
+    #[test]
+    fn summaryUsesTheSampleStandardDeviationAndStudentsT()
+    {
+        let summary = summarize(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        let stdDev = (32.0_f64 / 7.0).sqrt();
+        let halfWidth = 2.365 * stdDev / 8.0_f64.sqrt(); // 7 degrees of freedom
+
+        assertClose(summary.mean, 5.0);
+        assertClose(summary.stdDev, stdDev);
+        assertClose(summary.ci95Low, 5.0 - halfWidth);
+        assertClose(summary.ci95High, 5.0 + halfWidth);
+    }
+
+    #[test]
+    fn singleRunHasNoSpread()
+    {
+        let summary = summarize(&[3.5]);
+
+        assertClose(summary.mean, 3.5);
+        assertClose(summary.stdDev, 0.0);
+        assertClose(summary.ci95Low, 3.5);
+        assertClose(summary.ci95High, 3.5);
+    }
+
+    #[test]
+    fn identicalRunsHaveAnEmptyInterval()
+    {
+        let summary = summarize(&[4.0; 5]);
+
+        assertClose(summary.stdDev, 0.0);
+        assertClose(summary.ci95Low, 4.0);
+        assertClose(summary.ci95High, 4.0);
+    }
+
+    #[test]
+    fn tCriticalValueFallsBackToNormalPastTheTable()
+    {
+        assertClose(tCritical95(1), 12.706);
+        assertClose(tCritical95(30), 2.042);
+        assertClose(tCritical95(31), 1.960);
+        assert!(tCritical95(0).is_nan());
+    }
+
+    #[test]
+    fn parallelRunsMatchRunningEachSeedAlone()
+    {
+        let data: JSONData = serde_json::from_str(include_str!("../data/factory.json")).expect("Example config does not parse.");
+        let horizonUs = 10 * 60 * 1000000;
+        let runs = runReplications(&data, horizonUs, 3, 100);
+
+        for (replication, run) in runs.iter().enumerate()
+        {
+            let mut alone = data.clone();
+            alone.factory.seed = Some(100 + replication as u64);
+            let mut factory = factorySetup(alone);
+            factory.runUntil(horizonUs, |_, _| false);
+
+            assert_eq!(serde_json::to_string(run).unwrap(), serde_json::to_string(&factory.statistics()).unwrap());
+        }
+    }
+}
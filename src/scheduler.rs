@@ -0,0 +1,210 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+// Everything that can be scheduled to happen at a future simulated time
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EventKind
+{
+    Machine(String), // A machine's input, processing, output or fault repair completes
+    Conveyor(String), // An item on a conveyor finishes moving a space
+    Poll, // Machine states are checked and the server is updated
+    WarmupEnd, // Statistics are reset, the line keeps running as it was
+    Calendar, // A shift, break or planned stop starts or ends, or calendar maintenance comes due
+}
+
+impl EventKind
+{
+    // Lower runs first when events share an instant. Polls go after everything else, so
+    // they always see the line after all work due at that instant has been done.
+    fn priority(&self) -> u8
+    {
+        match self
+        {
+            EventKind::Poll | EventKind::WarmupEnd => 1,
+            _ => 0,
+        }
+    }
+}
+
+// Ordered by time first, then priority, then by the order events were scheduled in,
+// so that events at the same instant are otherwise handled first-come first-served
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ScheduledEvent
+{
+    timeUs: u128,
+    priority: u8,
+    sequence: u64,
+    kind: EventKind,
+}
+
+// Priority queue of future events. Each kind of event has at most one live entry,
+// rescheduling replaces the old time, and replaced entries are skipped when they
+// reach the front of the queue rather than being searched for and removed.
+pub struct EventQueue
+{
+    heap: BinaryHeap<Reverse<ScheduledEvent>>,
+    scheduled: HashMap<EventKind, u128>, // The live time for every pending event
+    nextSequence: u64,
+}
+impl EventQueue
+{
+    pub fn new() -> EventQueue
+    {
+        EventQueue { heap: BinaryHeap::new(), scheduled: HashMap::new(), nextSequence: 0 }
+    }
+
+    // Schedules an event at timeUs, replacing any earlier schedule of the same kind
+    pub fn schedule(&mut self, kind: EventKind, timeUs: u128)
+    {
+        if self.scheduled.get(&kind) == Some(&timeUs)
+        {
+            return;
+        }
+
+        self.scheduled.insert(kind.clone(), timeUs);
+        self.heap.push(Reverse(ScheduledEvent { timeUs, priority: kind.priority(), sequence: self.nextSequence, kind }));
+        self.nextSequence += 1;
+    }
+
+    // Live time of an event, if it is scheduled
+    pub fn scheduledTime(&self, kind: &EventKind) -> Option<u128>
+    {
+        self.scheduled.get(kind).copied()
+    }
+
+    pub fn cancel(&mut self, kind: &EventKind)
+    {
+        self.scheduled.remove(kind);
+    }
+
+    // Time of the earliest live event, if there is one
+    pub fn peekTime(&mut self) -> Option<u128>
+    {
+        self.discardStale();
+        self.heap.peek().map(|event| event.0.timeUs)
+    }
+
+    // Removes and returns the earliest live event
+    pub fn pop(&mut self) -> Option<(u128, EventKind)>
+    {
+        self.discardStale();
+        let event = self.heap.pop()?.0;
+        self.scheduled.remove(&event.kind);
+        Some((event.timeUs, event.kind))
+    }
+
+    // Drops entries from the front of the queue that were rescheduled or cancelled
+    fn discardStale(&mut self)
+    {
+        while let Some(event) = self.heap.peek()
+        {
+            if self.scheduled.get(&event.0.kind) == Some(&event.0.timeUs)
+            {
+                return;
+            }
+            self.heap.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn machine(id: &str) -> EventKind
+    {
+        EventKind::Machine(String::from(id))
+    }
+
+    #[test]
+    fn popsInTimeOrder()
+    {
+        let mut events = EventQueue::new();
+        events.schedule(machine("b"), 20);
+        events.schedule(machine("a"), 10);
+        events.schedule(EventKind::Conveyor(String::from("c")), 15);
+
+        assert_eq!(events.pop(), Some((10, machine("a"))));
+        assert_eq!(events.pop(), Some((15, EventKind::Conveyor(String::from("c")))));
+        assert_eq!(events.pop(), Some((20, machine("b"))));
+        assert_eq!(events.pop(), None);
+    }
+
+    #[test]
+    fn reschedulingReplacesTheOldTime()
+    {
+        let mut events = EventQueue::new();
+        events.schedule(machine("a"), 10);
+        events.schedule(machine("a"), 30);
+        events.schedule(machine("b"), 20);
+
+        // The stale entry at 10 is skipped, not delivered
+        assert_eq!(events.peekTime(), Some(20));
+        assert_eq!(events.pop(), Some((20, machine("b"))));
+        assert_eq!(events.pop(), Some((30, machine("a"))));
+        assert_eq!(events.pop(), None);
+    }
+
+    #[test]
+    fn reschedulingBackToAnOldTimeDeliversOnce()
+    {
+        let mut events = EventQueue::new();
+        events.schedule(machine("a"), 10);
+        events.schedule(machine("a"), 30);
+        events.schedule(machine("a"), 10);
+
+        assert_eq!(events.pop(), Some((10, machine("a"))));
+        assert_eq!(events.pop(), None);
+    }
+
+    #[test]
+    fn schedulingTheSameTimeTwiceDeliversOnce()
+    {
+        let mut events = EventQueue::new();
+        events.schedule(machine("a"), 10);
+        events.schedule(machine("a"), 10);
+
+        assert_eq!(events.pop(), Some((10, machine("a"))));
+        assert_eq!(events.pop(), None);
+    }
+
+    #[test]
+    fn cancelledEventsAreSkipped()
+    {
+        let mut events = EventQueue::new();
+        events.schedule(machine("a"), 10);
+        events.schedule(machine("b"), 20);
+        events.cancel(&machine("a"));
+
+        assert_eq!(events.scheduledTime(&machine("a")), None);
+        assert_eq!(events.pop(), Some((20, machine("b"))));
+        assert_eq!(events.pop(), None);
+    }
+
+    #[test]
+    fn pollsRunAfterWorkAtTheSameInstant()
+    {
+        let mut events = EventQueue::new();
+        events.schedule(EventKind::Poll, 10);
+        events.schedule(EventKind::WarmupEnd, 10);
+        events.schedule(machine("a"), 10);
+        events.schedule(EventKind::Calendar, 10);
+
+        assert_eq!(events.pop(), Some((10, machine("a"))));
+        assert_eq!(events.pop(), Some((10, EventKind::Calendar)));
+        assert_eq!(events.pop(), Some((10, EventKind::Poll)));
+        assert_eq!(events.pop(), Some((10, EventKind::WarmupEnd)));
+    }
+
+    #[test]
+    fn sameInstantIsFirstComeFirstServed()
+    {
+        let mut events = EventQueue::new();
+        events.schedule(machine("b"), 10);
+        events.schedule(machine("a"), 10);
+
+        assert_eq!(events.pop(), Some((10, machine("b"))));
+        assert_eq!(events.pop(), Some((10, machine("a"))));
+    }
+}
@@ -0,0 +1,337 @@
+use std::f64::consts::PI;
+
+use rand::{Rng, SeedableRng};
+use rand::distributions::Distribution;
+use rand_chacha::ChaCha8Rng;
+use rand_distr::Normal;
+use serde::{Serialize, Deserialize};
+
+use crate::json::*;
+use crate::machine::OPCState;
+
+// How a sensor's readings move around its baseline
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SignalModel
+{
+    Uniform { variance: f64 }, // anywhere within variance / 2 of the baseline
+    Gaussian { stdDev: f64 },
+    RandomWalk { stepStdDev: f64, reversion: f64 }, // each reading moves on from the last, pulled back towards the baseline by reversion
+    Sine { amplitude: f64, periodUs: u128, stdDev: f64 },
+    Step { steps: Vec<(u128, f64)>, repeatUs: Option<u128>, stdDev: f64 }, // the baseline changes to each value at its time
+}
+
+// What a fault on its way looks like in a sensor. Each grows from nothing at the start of the lead time
+// to its full size at the fault.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PrecursorPattern
+{
+    Drift, // readings move steadily away, by magnitude
+    Noise, // extra noise, with a standard deviation of magnitude
+    Spikes { chance: f64 }, // readings jump by magnitude, as often as chance
+}
+
+// Warning a fault gives in one of the machine's sensors, for a while before it fails the machine
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Precursor
+{
+    pub sensor: String,
+    pub leadTimeUs: u128,
+    pub pattern: PrecursorPattern,
+    pub magnitude: f64,
+}
+impl Precursor
+{
+    pub fn new(precursor: &JSONPrecursor) -> Precursor
+    {
+        let pattern = match precursor.pattern.to_lowercase().as_str()
+        {
+            "noise" => PrecursorPattern::Noise,
+            "spikes" => PrecursorPattern::Spikes { chance: precursor.spikeChance.unwrap_or(0.2) },
+            _ => PrecursorPattern::Drift,
+        };
+
+        Precursor {
+            sensor: precursor.sensor.clone(),
+            leadTimeUs: ((precursor.leadTimeSec * 1000000.0) as u128).max(1), // seconds to microseconds
+            pattern,
+            magnitude: precursor.magnitude,
+        }
+    }
+
+    // How far through the lead time a fault timeLeftUs away is, from 0.0 to 1.0, None before it starts
+    pub fn progress(&self, timeLeftUs: u128) -> Option<f64>
+    {
+        if timeLeftUs >= self.leadTimeUs
+        {
+            return None;
+        }
+
+        Some(1.0 - timeLeftUs as f64 / self.leadTimeUs as f64)
+    }
+
+    // Change to one reading, progress of the way through the lead time
+    fn offset(&self, progress: f64, rng: &mut ChaCha8Rng) -> f64
+    {
+        match self.pattern
+        {
+            PrecursorPattern::Drift => self.magnitude * progress,
+            PrecursorPattern::Noise => noise(rng, self.magnitude.abs() * progress),
+            PrecursorPattern::Spikes { chance } => if rng.gen::<f64>() < chance * progress { self.magnitude } else { 0.0 },
+        }
+    }
+}
+
+// Where a sensor settles while its machine is in one state, and how fast. Targets follow the state the
+// machine is in, so they cannot warn of a fault ahead of time, that is what a fault's Precursor is for.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateTarget
+{
+    pub state: OPCState,
+    pub target: f64,
+    pub timeConstantUs: u128, // time to get about two thirds of the way to the target, 0 to get there at once
+    pub spike: f64, // jump as the machine goes into the state, which then dies away towards the target
+}
+
+// One named sensor on a machine, read every poll
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sensor
+{
+    pub name: String,
+    pub units: String,
+    pub model: SignalModel,
+    pub baseline: f64,
+    pub min: Option<f64>, // readings are kept within min and max
+    pub max: Option<f64>,
+    pub wearDrift: f64, // amount the reading moves per unit of machine wear
+    pub stateTargets: Vec<StateTarget>, // states without one settle back to the baseline
+    pub timeConstantUs: u128, // how fast the level settles back to the baseline, and towards targets without their own
+    pub level: f64, // where the readings are centred, moving towards the target of the machine's state
+    pub lastState: Option<OPCState>, // state the level was last moved in
+    pub value: f64, // last reading
+    pub walkOffset: f64, // distance a random walk has wandered from the baseline
+    pub rng: ChaCha8Rng, // own stream, so adding a sensor does not change any other sensor's readings
+}
+impl Sensor
+{
+    pub fn new(sensor: &JSONSensor) -> Sensor
+    {
+        // The schema makes sure each model has the fields it needs
+        let stdDev = sensor.stdDev.unwrap_or(0.0);
+        let model = match sensor.model.to_lowercase().as_str()
+        {
+            "gaussian" => SignalModel::Gaussian { stdDev },
+            "randomwalk" => SignalModel::RandomWalk { stepStdDev: sensor.stepStdDev.unwrap(), reversion: sensor.reversion.unwrap_or(0.0) },
+            "sine" => SignalModel::Sine { amplitude: sensor.amplitude.unwrap(),
+                    periodUs: (sensor.periodSec.unwrap() * 1000000.0) as u128, stdDev }, // seconds to microseconds
+            "step" => {
+                let mut steps: Vec<(u128, f64)> = sensor.steps.iter().flatten()
+                    .map(|step| ((step.atSec * 1000000.0) as u128, step.value)) // seconds to microseconds
+                    .collect();
+                steps.sort_by_key(|step| step.0);
+                SignalModel::Step { steps, repeatUs: sensor.repeatSec.map(|seconds| (seconds * 1000000.0) as u128), stdDev }
+            },
+            _ => SignalModel::Uniform { variance: sensor.variance.unwrap_or(0.0) },
+        };
+
+        let timeConstantUs = (sensor.timeConstantSec.unwrap_or(0.0) * 1000000.0) as u128; // seconds to microseconds
+        let mut stateTargets = Vec::<StateTarget>::new();
+        for target in sensor.stateTargets.iter().flatten()
+        {
+            stateTargets.push(StateTarget {
+                state: OPCState::parse(&target.state).unwrap(), // the schema only lets through state names
+                target: target.target.unwrap_or(sensor.baseline),
+                timeConstantUs: target.timeConstantSec.map(|seconds| (seconds * 1000000.0) as u128).unwrap_or(timeConstantUs),
+                spike: target.spike.unwrap_or(0.0),
+            });
+        }
+
+        Sensor {
+            name: sensor.name.clone(),
+            units: sensor.units.clone().unwrap_or_default(),
+            model,
+            baseline: sensor.baseline,
+            min: sensor.min,
+            max: sensor.max,
+            wearDrift: sensor.wearDrift.unwrap_or(0.0),
+            stateTargets,
+            timeConstantUs,
+            level: sensor.baseline,
+            lastState: None,
+            value: sensor.baseline,
+            walkOffset: 0.0,
+            rng: ChaCha8Rng::seed_from_u64(0),
+        }
+    }
+
+    // Moves the level towards the target of the state the machine has been in for deltaTime. The
+    // level follows a first-order lag, so it covers the same share of the way in the same time
+    // however the time is split up. Going into a state with a spike jumps the level first.
+    pub fn advance(&mut self, state: OPCState, deltaTime: u128)
+    {
+        let target = self.stateTargets.iter().find(|target| target.state == state);
+        if self.lastState != Some(state)
+        {
+            if let (Some(target), Some(_)) = (target, self.lastState)
+            {
+                self.level += target.spike;
+            }
+            self.lastState = Some(state);
+        }
+
+        let (targetLevel, timeConstantUs) = match target
+        {
+            Some(target) => (target.target, target.timeConstantUs),
+            None => (self.baseline, self.timeConstantUs),
+        };
+        if timeConstantUs == 0
+        {
+            self.level = targetLevel;
+            return;
+        }
+
+        self.level = targetLevel + (self.level - targetLevel) * (-(deltaTime as f64) / timeConstantUs as f64).exp();
+    }
+
+    // Takes a reading at timeUs into the run, stores it in value and returns it. Precursors are
+    // those of faults on their way, with how far through their lead time each is.
+    pub fn sample(&mut self, timeUs: u128, wearLevel: f64, precursors: &[(&Precursor, f64)]) -> f64
+    {
+        let mut reading = match &self.model
+        {
+            SignalModel::Uniform { variance } => {
+                let halfRange = variance / 2.0;
+                self.baseline + if halfRange > 0.0 { self.rng.gen_range(-halfRange..=halfRange) } else { 0.0 }
+            },
+            SignalModel::Gaussian { stdDev } => self.baseline + noise(&mut self.rng, *stdDev),
+            SignalModel::RandomWalk { stepStdDev, reversion } => {
+                self.walkOffset += noise(&mut self.rng, *stepStdDev) - reversion * self.walkOffset;
+                self.baseline + self.walkOffset
+            },
+            SignalModel::Sine { amplitude, periodUs, stdDev } => {
+                let phase = (timeUs % (*periodUs).max(1)) as f64 / (*periodUs).max(1) as f64;
+                self.baseline + amplitude * (2.0 * PI * phase).sin() + noise(&mut self.rng, *stdDev)
+            },
+            SignalModel::Step { steps, repeatUs, stdDev } => {
+                let stepTimeUs = match repeatUs
+                {
+                    Some(repeatUs) => timeUs % (*repeatUs).max(1),
+                    None => timeUs,
+                };
+                let level = steps.iter().rev().find(|step| step.0 <= stepTimeUs).map(|step| step.1).unwrap_or(self.baseline);
+                level + noise(&mut self.rng, *stdDev)
+            },
+        };
+        // The model moves the reading around the baseline, and the state moves the baseline
+        reading += self.level - self.baseline;
+        reading += self.wearDrift * wearLevel;
+        for (precursor, progress) in precursors
+        {
+            reading += precursor.offset(*progress, &mut self.rng);
+        }
+
+        let clamped = reading.clamp(self.min.unwrap_or(f64::NEG_INFINITY), self.max.unwrap_or(f64::INFINITY));
+        // A random walk stops at a limit rather than wandering further out past it
+        if let SignalModel::RandomWalk { .. } = self.model
+        {
+            self.walkOffset += clamped - reading;
+        }
+
+        self.value = clamped;
+        clamped
+    }
+}
+
+// Normally distributed noise around 0, none for a standard deviation of 0
+fn noise(rng: &mut ChaCha8Rng, stdDev: f64) -> f64
+{
+    if stdDev <= 0.0
+    {
+        return 0.0;
+    }
+
+    Normal::new(0.0, stdDev).expect("Sensor standard deviation must be positive.").sample(rng)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use serde_json::json;
+
+    // A sensor at 20 that heads to 80 while the machine is faulted, jumping by 10 on the way in
+    fn heatingSensor() -> Sensor
+    {
+        let sensor: JSONSensor = serde_json::from_value(json!({
+            "name": "temperature", "model": "uniform", "baseline": 20.0, "timeConstantSec": 10.0,
+            "stateTargets": [{ "state": "FAULTED", "target": 80.0, "spike": 10.0 }]
+        })).unwrap();
+        Sensor::new(&sensor)
+    }
+
+    fn driftPrecursor() -> Precursor
+    {
+        let precursor: JSONPrecursor = serde_json::from_value(json!({
+            "sensor": "temperature", "leadTimeSec": 5.0, "pattern": "drift", "magnitude": 10.0
+        })).unwrap();
+        Precursor::new(&precursor)
+    }
+
+    #[test]
+    fn precursorShowsForExactlyItsLeadTime()
+    {
+        let precursor = driftPrecursor();
+        assert_eq!(precursor.progress(6000000), None);
+        assert_eq!(precursor.progress(5000000), None);
+        assert_eq!(precursor.progress(4999999), Some(1.0 - 4999999.0 / 5000000.0));
+        assert_eq!(precursor.progress(2500000), Some(0.5));
+        assert_eq!(precursor.progress(0), Some(1.0));
+    }
+
+    #[test]
+    fn driftGrowsToItsMagnitudeAtTheFault()
+    {
+        let precursor = driftPrecursor();
+        let mut sensor = heatingSensor();
+        assert_eq!(sensor.sample(0, 0.0, &[]), 20.0);
+        assert_eq!(sensor.sample(0, 0.0, &[(&precursor, 0.5)]), 25.0);
+        assert_eq!(sensor.sample(0, 0.0, &[(&precursor, 1.0)]), 30.0);
+    }
+
+    #[test]
+    fn levelCoversTheSameShareOfTheWayHoweverTheTimeIsSplit()
+    {
+        let mut once = heatingSensor();
+        once.advance(OPCState::FAULTED, 10000000);
+
+        let mut inSteps = heatingSensor();
+        for _ in 0..1000
+        {
+            inSteps.advance(OPCState::FAULTED, 10000);
+        }
+
+        // One time constant takes the level about 63% of the way from 20 to 80
+        let expected = 80.0 - 60.0 * (-1.0f64).exp();
+        assert!((once.level - expected).abs() < 1e-9, "{}", once.level);
+        assert!((inSteps.level - expected).abs() < 1e-9, "{}", inSteps.level);
+    }
+
+    #[test]
+    fn spikeOnlyFiresOnAStateChange()
+    {
+        let mut sensor = heatingSensor();
+        // The first state seen is not a change
+        sensor.advance(OPCState::FAULTED, 0);
+        assert_eq!(sensor.level, 20.0);
+
+        sensor.advance(OPCState::PRODUCING, 0);
+        assert_eq!(sensor.level, 20.0);
+        sensor.advance(OPCState::FAULTED, 0);
+        assert_eq!(sensor.level, 30.0);
+        sensor.advance(OPCState::FAULTED, 0);
+        assert_eq!(sensor.level, 30.0);
+
+        // Going back out of the state does not spike
+        sensor.advance(OPCState::PRODUCING, 0);
+        assert_eq!(sensor.level, 30.0);
+    }
+}
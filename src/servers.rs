@@ -4,6 +4,7 @@ use opcua::server::prelude::*;
 
 use std::path::PathBuf;
 use std::sync::RwLock;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::metadata;
 
 use actix_web::{get, post, App, HttpResponse, HttpRequest, HttpServer, Responder, web, Result as ActixResult};
@@ -13,7 +14,12 @@ use serde::{Serialize, Deserialize};
 
 use crate::json;
 use json::*;
-use jsonschema::JSONSchema;
+use crate::snapshot::parseSnapshot;
+use crate::factory::loadFactoryData;
+use crate::machine::OPCState;
+
+// Config the control panel runs when it has not been given one
+const DEFAULT_CONFIG: &str = "factory.json";
 
 pub fn initOPCServer() -> Server
 {
@@ -58,6 +64,22 @@ pub enum SimulationState
     EXIT,    // Fully exit the program
 }
 
+// A request to move a paused simulation forward, from the control panel
+#[derive(Clone, PartialEq, Debug)]
+pub enum StepRequest
+{
+    Events(u64), // Handle this many machine/conveyor events
+    TimeMs(u128), // Move forward this many simulated milliseconds
+}
+
+// A condition that pauses the simulation as soon as it becomes true
+#[derive(Clone, PartialEq, Debug)]
+pub enum RunCondition
+{
+    MachineState { machineID: String, state: OPCState }, // Machine enters a state
+    ProducedCount { machineID: String, count: usize }, // Machine's produced count reaches a value
+}
+
 ///////////////////////////////////////////////////////////////////////////////////////////////////
 // Start of the cursed lands //////////////////////////////////////////////////////////////////////
 ///////////////////////////////////////////////////////////////////////////////////////////////////
@@ -66,50 +88,81 @@ pub enum SimulationState
 // so we use static memory to keep track of a "master state" for the simulation, which is 
 // either get or set depending on function arguments. The same is true for every funciton in this
 // section, these are all middlemen between the server and the simulation for various values.
-// 
+//
+// Several lines can run at once, so every value is kept per line, keyed by the name in the line's config.
+// Requests that do not name a line go to the default line, see simDefaultLineManager.
+
+// Every line the control panel has been told about, in name order
+// false and None for getter, true and Some(line) to add a line
+pub fn simLineManager(updateLines: bool, newLine: Option<String>) -> Vec<String>
+{
+    static LINES: RwLock<BTreeSet<String>> = RwLock::new(BTreeSet::new());
+
+    if let (true, Some(newLine)) = (updateLines, newLine)
+    {
+        LINES.write().unwrap().insert(newLine);
+    }
+
+    return LINES.read().ok().unwrap().iter().cloned().collect();
+}
+
+// The line requests go to when they do not name one, the line of the config last set
+// false and None for getter, true and Some(line) for setter
+pub fn simDefaultLineManager(updateLine: bool, newLine: Option<String>) -> String
+{
+    static DEFAULT_LINE: RwLock<String> = RwLock::new(String::new());
+
+    if let (true, Some(newLine)) = (updateLine, newLine)
+    {
+        *DEFAULT_LINE.write().unwrap() = newLine;
+    }
+
+    return DEFAULT_LINE.read().ok().unwrap().clone();
+}
+
 // false and None for getter, true and Some(SimulationState::StateHere) for setter 
-pub fn simStateManager(updateState: bool, newState: Option<SimulationState>) -> SimulationState
+pub fn simStateManager(line: &str, updateState: bool, newState: Option<SimulationState>) -> SimulationState
 {
-    static STATE: RwLock<SimulationState> = RwLock::new(SimulationState::STOP);
+    static STATE: RwLock<BTreeMap<String, SimulationState>> = RwLock::new(BTreeMap::new());
 
-    if updateState && newState.is_some()
+    if let (true, Some(newState)) = (updateState, newState)
     {
-        *STATE.write().unwrap() = newState.unwrap();
+        STATE.write().unwrap().insert(line.to_string(), newState);
     }
 
-    let state = *STATE.read().ok().unwrap();
+    let state = *STATE.read().ok().unwrap().get(line).unwrap_or(&SimulationState::STOP);
     return state.clone();
 }
 
-pub fn simConfigManager(updateConfig: bool, newConfig: Option<String>) -> String
+pub fn simConfigManager(line: &str, updateConfig: bool, newConfig: Option<String>) -> String
 {
-    static CONFIG: RwLock<String> = RwLock::new(String::new());
+    static CONFIG: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
 
     if updateConfig && newConfig.is_some()
     {
         let newConfig = newConfig.unwrap();
-        *CONFIG.write().unwrap() = newConfig;
+        CONFIG.write().unwrap().insert(line.to_string(), newConfig);
     }
 
-    if CONFIG.read().ok().unwrap().len() == 0
+    if CONFIG.read().ok().unwrap().get(line).map_or(0, |config| config.len()) == 0
     {
-        *CONFIG.write().unwrap() = String::from("factory.json");
+        CONFIG.write().unwrap().insert(line.to_string(), String::from(DEFAULT_CONFIG));
     }
 
-    return CONFIG.read().ok().unwrap().clone();
+    return CONFIG.read().ok().unwrap().get(line).unwrap().clone();
 }
 
-pub fn simClockManager(zeroTimes: bool, updateTimes: bool, deltaTime: Option<u128>) -> (u128, u128)
+pub fn simClockManager(line: &str, zeroTimes: bool, updateTimes: bool, deltaTime: Option<u128>) -> (u128, u128)
 {
-    static ACTIVETIME: RwLock<u128> = RwLock::new(0);
-    static RUNTIME: RwLock<u128> = RwLock::new(0);
+    static ACTIVETIME: RwLock<BTreeMap<String, u128>> = RwLock::new(BTreeMap::new());
+    static RUNTIME: RwLock<BTreeMap<String, u128>> = RwLock::new(BTreeMap::new());
 
-    let state = simStateManager(false, None);
+    let state = simStateManager(line, false, None);
 
     if zeroTimes
     {
-        *RUNTIME.write().unwrap() = 0;
-        *ACTIVETIME.write().unwrap() = 0;
+        RUNTIME.write().unwrap().insert(line.to_string(), 0);
+        ACTIVETIME.write().unwrap().insert(line.to_string(), 0);
 
         return (0, 0);
     }
@@ -117,29 +170,149 @@ pub fn simClockManager(zeroTimes: bool, updateTimes: bool, deltaTime: Option<u12
     if updateTimes && deltaTime.is_some()
     {
         let deltaTime = deltaTime.unwrap();
-        *RUNTIME.write().unwrap() += deltaTime;
+        *RUNTIME.write().unwrap().entry(line.to_string()).or_insert(0) += deltaTime;
 
         if state == SimulationState::RUNNING
         {
-            *ACTIVETIME.write().unwrap() += deltaTime;
+            *ACTIVETIME.write().unwrap().entry(line.to_string()).or_insert(0) += deltaTime;
         }
     }
 
-    let activetime = *ACTIVETIME.read().ok().unwrap();
-    let runtime = *RUNTIME.read().ok().unwrap();
+    let activetime = *ACTIVETIME.read().ok().unwrap().get(line).unwrap_or(&0);
+    let runtime = *RUNTIME.read().ok().unwrap().get(line).unwrap_or(&0);
     return (activetime, runtime);
 }
 
-pub fn simTimerManager(updateTimer: bool, newTimer: Option<i128>) -> i128
+pub fn simTimerManager(line: &str, updateTimer: bool, newTimer: Option<i128>) -> i128
+{
+    static TIME_LIMIT: RwLock<BTreeMap<String, i128>> = RwLock::new(BTreeMap::new());
+
+    if let (true, Some(newTimer)) = (updateTimer, newTimer)
+    {
+        TIME_LIMIT.write().unwrap().insert(line.to_string(), newTimer);
+    }
+
+    return *TIME_LIMIT.read().ok().unwrap().get(line).unwrap_or(&0)
+}
+
+// Simulated time of the running simulation in microseconds, set by the simulation thread
+// false and None for getter, true and Some(time) for setter
+pub fn simElapsedManager(line: &str, updateTime: bool, newTime: Option<u128>) -> u128
 {
-    static TIME_LIMIT: RwLock<i128> = RwLock::new(0);
+    static SIM_TIME: RwLock<BTreeMap<String, u128>> = RwLock::new(BTreeMap::new());
 
-    if updateTimer && newTimer.is_some()
+    if let (true, Some(newTime)) = (updateTime, newTime)
     {
-        *TIME_LIMIT.write().unwrap() = newTimer.unwrap();
+        SIM_TIME.write().unwrap().insert(line.to_string(), newTime);
     }
 
-    return TIME_LIMIT.read().ok().unwrap().clone()
+    return *SIM_TIME.read().ok().unwrap().get(line).unwrap_or(&0);
+}
+
+// Machine IDs of the running simulation, set by the simulation thread when it starts and on reloads
+// false and None for getter, true and Some(ids) for setter
+pub fn simMachinesManager(line: &str, updateMachines: bool, newMachines: Option<Vec<String>>) -> Vec<String>
+{
+    static MACHINES: RwLock<BTreeMap<String, Vec<String>>> = RwLock::new(BTreeMap::new());
+
+    if let (true, Some(newMachines)) = (updateMachines, newMachines)
+    {
+        MACHINES.write().unwrap().insert(line.to_string(), newMachines);
+    }
+
+    return MACHINES.read().ok().unwrap().get(line).cloned().unwrap_or_default();
+}
+
+// Pending step for a paused simulation. Unlike the other managers, the getter takes the request,
+// so that each step is only performed once.
+// false and None to take, true and Some(step) to request, true and None to cancel
+pub fn simStepManager(line: &str, updateStep: bool, newStep: Option<StepRequest>) -> Option<StepRequest>
+{
+    static STEP: RwLock<BTreeMap<String, StepRequest>> = RwLock::new(BTreeMap::new());
+
+    if updateStep
+    {
+        match newStep
+        {
+            Some(step) => STEP.write().unwrap().insert(line.to_string(), step),
+            None => STEP.write().unwrap().remove(line),
+        };
+        return None;
+    }
+
+    return STEP.write().unwrap().remove(line);
+}
+
+// Condition to pause the simulation on, checked after every event
+// false and None for getter, true and Some(condition) for setter, true and None to clear
+pub fn simRunUntilManager(line: &str, updateCondition: bool, newCondition: Option<RunCondition>) -> Option<RunCondition>
+{
+    static CONDITION: RwLock<BTreeMap<String, RunCondition>> = RwLock::new(BTreeMap::new());
+
+    if updateCondition
+    {
+        match newCondition
+        {
+            Some(condition) => CONDITION.write().unwrap().insert(line.to_string(), condition),
+            None => CONDITION.write().unwrap().remove(line),
+        };
+    }
+
+    return CONDITION.read().ok().unwrap().get(line).cloned();
+}
+
+// Snapshot to resume from when the simulation is next started, empty to start from the config
+// false and None for getter, true and Some(name) for setter, true and None to clear
+pub fn simSnapshotManager(line: &str, updateSnapshot: bool, newSnapshot: Option<String>) -> String
+{
+    static SNAPSHOT: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+    if updateSnapshot
+    {
+        SNAPSHOT.write().unwrap().insert(line.to_string(), newSnapshot.unwrap_or_default());
+    }
+
+    return SNAPSHOT.read().ok().unwrap().get(line).cloned().unwrap_or_default();
+}
+
+// Pending request to save a snapshot of the running simulation. The getter takes the request,
+// so that each snapshot is only written once.
+// false and None to take, true and Some(name) to request
+pub fn simSaveSnapshotManager(line: &str, updateSave: bool, newSave: Option<String>) -> Option<String>
+{
+    static SAVE: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+    if updateSave
+    {
+        match newSave
+        {
+            Some(name) => SAVE.write().unwrap().insert(line.to_string(), name),
+            None => SAVE.write().unwrap().remove(line),
+        };
+        return None;
+    }
+
+    return SAVE.write().unwrap().remove(line);
+}
+
+// Pending request to apply a config to the running simulation. The getter takes the request,
+// so that each reload is only applied once.
+// false and None to take, true and Some(config) to request
+pub fn simReloadManager(line: &str, updateReload: bool, newReload: Option<String>) -> Option<String>
+{
+    static RELOAD: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+    if updateReload
+    {
+        match newReload
+        {
+            Some(config) => RELOAD.write().unwrap().insert(line.to_string(), config),
+            None => RELOAD.write().unwrap().remove(line),
+        };
+        return None;
+    }
+
+    return RELOAD.write().unwrap().remove(line);
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////
@@ -161,17 +334,53 @@ struct MessageResponse
     message: String
 }
 
+// For requests that only need to know which line they are for, eg. ?line=packaging
+#[derive(Deserialize)]
+struct LineQuery
+{
+    line: Option<String>
+}
+
+// The line a request is for, the default line if it does not name one
+fn lineName(line: &Option<String>) -> String
+{
+    match line.as_deref()
+    {
+        Some(line) if !line.is_empty() => line.to_string(),
+        _ => simDefaultLineManager(false, None),
+    }
+}
+
+// Reads a config from the data directory, with the message to show if it cannot be run
+fn checkConfig(config: &str) -> Result<JSONData, String>
+{
+    loadFactoryData(config).map_err(|error| error.to_string())
+}
+
+// Registers the default config's line, so the control panel has a line to start before any config is set.
+// The line is nameless if the default config cannot be read, and starting it reports why.
+pub fn initLines()
+{
+    let line = checkConfig(DEFAULT_CONFIG).map(|data| data.factory.name).unwrap_or_default();
+
+    simStateManager(&line, true, Some(SimulationState::STOP));
+    simLineManager(true, Some(line.clone()));
+    simDefaultLineManager(true, Some(line));
+}
+
 // Stop or start the simulation but not the program
 #[post("/toggleSim")]
-async fn toggleSim() -> ActixResult<impl Responder>
+async fn toggleSim(info: web::Query<LineQuery>) -> ActixResult<impl Responder>
 {
-    let state = simStateManager(false, None);
+    let line = lineName(&info.line);
+
+    let state = simStateManager(&line, false, None);
 
     if state == SimulationState::STOP
     {
         //////////////////////
         // Timer validation //
-        if simTimerManager(false, None) < 0
+        if simTimerManager(&line, false, None) < 0
         {
             return Ok(web::Json(MessageResponse {message: String::from("Time cannot be negative.")}));
         }
@@ -179,52 +388,49 @@ async fn toggleSim() -> ActixResult<impl Responder>
         
         ////////////////////////////
         // Config file validation //
-        let mut configPath = simConfigManager(false, None);
-
-        if in_container::in_container()
-        {
-            configPath = format!("/home/data/{}", configPath);
-        }
-        else
+        let config = simConfigManager(&line, false, None);
+        let data = match checkConfig(&config)
         {
-            configPath = format!("./data/{}", configPath);
-        }
+            Ok(data) => data,
+            Err(message) => return Ok(web::Json(MessageResponse {message})),
+        };
 
-        if let Err(_e) = metadata(configPath.clone())
+        // The config may have been edited since it was set, and the line has to keep its name
+        if data.factory.name != line
         {
-            return Ok(web::Json(MessageResponse {message: String::from("File does not exist.")}));
+            return Ok(web::Json(MessageResponse {message: format!("{} is now named \"{}\", set it again to run it.", config, data.factory.name)}));
         }
-        
-        let jsonData = read_json_file(configPath.as_str());
-
-        let data_as_value: serde_json::Value = serde_json::from_str(&jsonData).expect("Failed to parse JSON");
 
-        // JSON file validation using JSON schema
-        let schema_string = read_json_file("./data/schema.json");
-        let schema_data = serde_json::from_str(&schema_string).expect("Failed to parse Schema");
-
-        let compiled_schema = JSONSchema::compile(&schema_data).expect("Could not compile schema");
-
-        let result = compiled_schema.validate(&data_as_value);
-        if let Err(errors) = result {
-            for error in errors {
-                println!("Validation error: {}", error);
-                println!("Instance path: {}", error.instance_path);
+        // A snapshot carries its own config, which has to be of the same line
+        let snapshot = simSnapshotManager(&line, false, None);
+        if !snapshot.is_empty()
+        {
+            if let Err(_e) = metadata(dataPath(&snapshot))
+            {
+                return Ok(web::Json(MessageResponse {message: String::from("Snapshot does not exist.")}));
             }
 
-            return Ok(web::Json(MessageResponse {message: String::from("JSON file has invalid structure.")}));
+            match parseSnapshot(&read_json_file(dataPath(&snapshot).as_str()), &snapshot)
+            {
+                Ok(saved) if saved.config.factory.name != line =>
+                    return Ok(web::Json(MessageResponse {message: format!("{} is of line \"{}\".", snapshot, saved.config.factory.name)})),
+                Err(error) => return Ok(web::Json(MessageResponse {message: error.to_string()})),
+                _ => {}
+            }
         }
 
         // If we get here, the JSON is correct and we can continue to turn the simulation on//
         //////////////////////////////////////////////////////////////////////////////////////
+
+        simLineManager(true, Some(line.clone()));
     }
 
     match state
     {
-        SimulationState::RUNNING => simStateManager(true, Some(SimulationState::STOP)),
-        SimulationState::STOP => simStateManager(true, Some(SimulationState::RUNNING)),
+        SimulationState::RUNNING => simStateManager(&line, true, Some(SimulationState::STOP)),
+        SimulationState::STOP => simStateManager(&line, true, Some(SimulationState::RUNNING)),
         SimulationState::EXIT => SimulationState::EXIT,
-        _ => simStateManager(true, Some(SimulationState::STOP))
+        _ => simStateManager(&line, true, Some(SimulationState::STOP))
     };
 
     Ok(web::Json(MessageResponse {message: String::from("success")}))
@@ -237,10 +443,12 @@ struct StateQuery
 }
 
 #[get("/simState")]
-async fn getSimState() -> ActixResult<impl Responder>
+async fn getSimState(info: web::Query<LineQuery>) -> ActixResult<impl Responder>
 {
+    let line = lineName(&info.line);
+
     let mut stateJSON = StateQuery{ state: String::from("running") };
-    let state = simStateManager(false, None);
+    let state = simStateManager(&line, false, None);
     match state
     {
         SimulationState::RUNNING => stateJSON.state = String::from("running"),
@@ -252,23 +460,28 @@ async fn getSimState() -> ActixResult<impl Responder>
     Ok(web::Json(stateJSON))
 }
 
-// Exit the program entirely
+// Exit the program entirely, stopping every line
 #[post("/exitSim")]
 async fn exitSim() -> impl Responder
 {
-    simStateManager(true, Some(SimulationState::EXIT));
+    for line in simLineManager(false, None)
+    {
+        simStateManager(&line, true, Some(SimulationState::EXIT));
+    }
 
     HttpResponse::Ok()
 }
 
 // Pause or unpause the simulation without killing it fully
 #[post("/suspendSim")]
-async fn suspendSim() -> impl Responder
+async fn suspendSim(info: web::Query<LineQuery>) -> impl Responder
 {
-    match simStateManager(false, None)
+    let line = lineName(&info.line);
+
+    match simStateManager(&line, false, None)
     {
-        SimulationState::RUNNING => simStateManager(true, Some(SimulationState::PAUSED)),
-        SimulationState::PAUSED => simStateManager(true, Some(SimulationState::RUNNING)),
+        SimulationState::RUNNING => simStateManager(&line, true, Some(SimulationState::PAUSED)),
+        SimulationState::PAUSED => simStateManager(&line, true, Some(SimulationState::RUNNING)),
         SimulationState::EXIT => SimulationState::EXIT,
         SimulationState::STOP => SimulationState::STOP
     };
@@ -282,28 +495,61 @@ struct ConfigQuery
     config: String
 }
 
+#[derive(Serialize)]
+struct ConfigResponse
+{
+    message: String,
+    line: String
+}
+
+// Set the config of the line named in it, which becomes the default line. Answers with the line's
+// name, for the requests that follow. A name can only be taken by another config while its line is stopped.
 #[post("/setConfig")]
-async fn setSimConfig(info: web::Query<ConfigQuery>) -> impl Responder
+async fn setSimConfig(info: web::Query<ConfigQuery>) -> ActixResult<impl Responder>
 {
-    simConfigManager(true, Some(info.config.clone()));
+    let config = info.config.trim().to_string();
+    let line = match checkConfig(&config)
+    {
+        Ok(data) => data.factory.name,
+        Err(message) => return Ok(web::Json(ConfigResponse {message, line: String::new()})),
+    };
 
-    HttpResponse::Ok()
+    if line.trim().is_empty()
+    {
+        return Ok(web::Json(ConfigResponse {message: String::from("The factory needs a name to run as a line."), line}));
+    }
+
+    let inUseBy = simConfigManager(&line, false, None);
+    if simLineManager(false, None).contains(&line) && inUseBy != config && simStateManager(&line, false, None) != SimulationState::STOP
+    {
+        return Ok(web::Json(ConfigResponse {message: format!("Line \"{}\" is already running from {}.", line, inUseBy), line}));
+    }
+
+    simConfigManager(&line, true, Some(config));
+    simLineManager(true, Some(line.clone()));
+    simDefaultLineManager(true, Some(line.clone()));
+
+    Ok(web::Json(ConfigResponse {message: String::from("success"), line}))
 }
 
 #[derive(Serialize)]
 struct TimeResponse
 {
     activeTime: u128,
-    runningTime: u128
+    runningTime: u128,
+    simulatedTime: u128
 }
 
 #[get("/getTime")]
-async fn getSimTime() -> ActixResult<impl Responder>
+async fn getSimTime(info: web::Query<LineQuery>) -> ActixResult<impl Responder>
 {
-    let rawTimes = simClockManager(false, false, None);
+    let line = lineName(&info.line);
+
+    let rawTimes = simClockManager(&line, false, false, None);
     let timesObj = TimeResponse {
         activeTime: rawTimes.0,
-        runningTime: rawTimes.1
+        runningTime: rawTimes.1,
+        simulatedTime: simElapsedManager(&line, false, None)
     };
 
     Ok(web::Json(timesObj))
@@ -318,15 +564,17 @@ struct RemainingTimeResponse
 }
 
 #[get("/getTimeLimit")]
-async fn getSimTimeLimit() -> ActixResult<impl Responder>
+async fn getSimTimeLimit(info: web::Query<LineQuery>) -> ActixResult<impl Responder>
 {
-    let timeLimit = simTimerManager(false, None) as u128;
+    let line = lineName(&info.line);
+
+    let timeLimit = simTimerManager(&line, false, None) as u128;
     if timeLimit <= 0
     {
         return Ok(web::Json(RemainingTimeResponse { timeLimit: timeLimit as u128, timeLeft: 0, percent: 100.0 }));
     }
 
-    let mut timePassed = simClockManager(false, false, None).0; // Time unpaused, does not track paused time, .0 gets this
+    let mut timePassed = simClockManager(&line, false, false, None).0; // Time unpaused, does not track paused time, .0 gets this
     if timePassed > timeLimit
     {
         timePassed = timeLimit;
@@ -345,17 +593,235 @@ async fn getSimTimeLimit() -> ActixResult<impl Responder>
 #[derive(Deserialize)]
 struct TimerQuery
 {
-    timer: i64
+    timer: i64,
+    line: Option<String>
 }
 
 #[post("/setTimer")]
 async fn setSimTimer(info: web::Query<TimerQuery>) -> impl Responder
 {
+    let line = lineName(&info.line);
+
     // Converts received time into microseconds, web service expects minutes
-    simTimerManager(true, Some(info.timer.clone() as i128 * 1000000 * 60));
+    simTimerManager(&line, true, Some(info.timer as i128 * 1000000 * 60));
+    HttpResponse::Ok()
+}
+
+#[derive(Deserialize)]
+struct StepQuery
+{
+    events: Option<u64>,
+    ms: Option<u64>,
+    line: Option<String>
+}
+
+// Step a paused simulation by a number of events (?events=N) or simulated milliseconds (?ms=N),
+// one event if neither is given
+#[post("/stepSim")]
+async fn stepSim(info: web::Query<StepQuery>) -> ActixResult<impl Responder>
+{
+    let line = lineName(&info.line);
+
+    if simStateManager(&line, false, None) != SimulationState::PAUSED
+    {
+        return Ok(web::Json(MessageResponse {message: String::from("Simulation must be paused to step.")}));
+    }
+
+    let step = match (info.events, info.ms)
+    {
+        (Some(_), Some(_)) => return Ok(web::Json(MessageResponse {message: String::from("Step by events or by time, not both.")})),
+        (_, Some(ms)) => StepRequest::TimeMs(ms as u128),
+        (Some(events), _) => StepRequest::Events(events),
+        (None, None) => StepRequest::Events(1),
+    };
+    simStepManager(&line, true, Some(step));
+
+    Ok(web::Json(MessageResponse {message: String::from("success")}))
+}
+
+#[derive(Deserialize)]
+struct RunUntilQuery
+{
+    machine: String,
+    state: Option<String>,
+    produced: Option<usize>,
+    line: Option<String>
+}
+
+// The condition a run-until request asks for, or why it cannot be run to on a line with these machines
+fn parseRunCondition(info: &RunUntilQuery, machineIDs: &[String]) -> Result<RunCondition, String>
+{
+    if !machineIDs.contains(&info.machine)
+    {
+        return Err(format!("There is no machine {} on this line.", info.machine));
+    }
+
+    match (&info.state, info.produced)
+    {
+        (Some(_), Some(_)) => Err(String::from("Run until a state or a produced count, not both.")),
+        (Some(state), _) => match OPCState::parse(state)
+        {
+            Some(state) => Ok(RunCondition::MachineState { machineID: info.machine.clone(), state }),
+            None => Err(format!("{} is not a machine state.", state)),
+        },
+        (_, Some(count)) => Ok(RunCondition::ProducedCount { machineID: info.machine.clone(), count }),
+        (None, None) => Err(String::from("A state or produced count is required.")),
+    }
+}
+
+// Run until a machine enters a state (?machine=ID&state=faulted) or reaches a produced
+// count (?machine=ID&produced=N), then pause. Resumes the simulation if it is paused.
+// An unknown machine or state is a bad request.
+#[post("/runUntil")]
+async fn runUntil(info: web::Query<RunUntilQuery>) -> HttpResponse
+{
+    let line = lineName(&info.line);
+
+    let state = simStateManager(&line, false, None);
+    if state != SimulationState::RUNNING && state != SimulationState::PAUSED
+    {
+        return HttpResponse::Ok().json(MessageResponse {message: String::from("Simulation is not running.")});
+    }
+
+    let condition = match parseRunCondition(&info, &simMachinesManager(&line, false, None))
+    {
+        Ok(condition) => condition,
+        Err(message) => return HttpResponse::BadRequest().json(MessageResponse {message}),
+    };
+    simRunUntilManager(&line, true, Some(condition));
+    simStateManager(&line, true, Some(SimulationState::RUNNING));
+
+    HttpResponse::Ok().json(MessageResponse {message: String::from("success")})
+}
+
+#[derive(Deserialize)]
+struct SnapshotQuery
+{
+    snapshot: String,
+    line: Option<String>
+}
+
+// Resume from a snapshot (?snapshot=file.json) the next time the simulation is started,
+// or start from the config again if the name is empty
+#[post("/setSnapshot")]
+async fn setSimSnapshot(info: web::Query<SnapshotQuery>) -> impl Responder
+{
+    let line = lineName(&info.line);
+
+    simSnapshotManager(&line, true, Some(info.snapshot.trim().to_string()));
+
     HttpResponse::Ok()
 }
 
+#[derive(Deserialize)]
+struct SaveSnapshotQuery
+{
+    name: String,
+    line: Option<String>
+}
+
+// Save the running or paused simulation to a snapshot file (?name=file.json) in the data directory
+#[post("/saveSnapshot")]
+async fn saveSimSnapshot(info: web::Query<SaveSnapshotQuery>) -> ActixResult<impl Responder>
+{
+    let line = lineName(&info.line);
+
+    let state = simStateManager(&line, false, None);
+    if state != SimulationState::RUNNING && state != SimulationState::PAUSED
+    {
+        return Ok(web::Json(MessageResponse {message: String::from("Simulation is not running.")}));
+    }
+
+    let name = info.name.trim();
+    if name.is_empty()
+    {
+        return Ok(web::Json(MessageResponse {message: String::from("A snapshot name is required.")}));
+    }
+
+    simSaveSnapshotManager(&line, true, Some(name.to_string()));
+
+    Ok(web::Json(MessageResponse {message: String::from("success")}))
+}
+
+#[derive(Deserialize)]
+struct ReloadQuery
+{
+    config: Option<String>,
+    line: Option<String>
+}
+
+// Apply a config to the running or paused simulation without stopping it, keeping the OPC nodes
+// and counters of machines that are still in it. Reloads the config in use if none is given
+// (?config=file.json), so it can be edited in place.
+#[post("/reloadConfig")]
+async fn reloadSimConfig(info: web::Query<ReloadQuery>) -> ActixResult<impl Responder>
+{
+    let line = lineName(&info.line);
+
+    let state = simStateManager(&line, false, None);
+    if state != SimulationState::RUNNING && state != SimulationState::PAUSED
+    {
+        return Ok(web::Json(MessageResponse {message: String::from("Simulation is not running.")}));
+    }
+
+    let config = match &info.config
+    {
+        Some(config) if !config.trim().is_empty() => config.trim().to_string(),
+        _ => simConfigManager(&line, false, None),
+    };
+
+    let data = match checkConfig(&config)
+    {
+        Ok(data) => data,
+        Err(message) => return Ok(web::Json(MessageResponse {message})),
+    };
+
+    // Lines are known by name, so a reload cannot rename one
+    if data.factory.name != line
+    {
+        return Ok(web::Json(MessageResponse {message: format!("{} is named \"{}\", not \"{}\".", config, data.factory.name, line)}));
+    }
+
+    simConfigManager(&line, true, Some(config.clone()));
+    simReloadManager(&line, true, Some(config));
+
+    Ok(web::Json(MessageResponse {message: String::from("success")}))
+}
+
+#[derive(Serialize)]
+struct LineResponse
+{
+    line: String,
+    config: String,
+    state: String,
+    simulatedTime: u128
+}
+
+// Every line the control panel knows about, with its config, state and simulated time
+#[get("/lines")]
+async fn getLines() -> ActixResult<impl Responder>
+{
+    let mut lines = Vec::<LineResponse>::new();
+    for line in simLineManager(false, None)
+    {
+        let state = match simStateManager(&line, false, None)
+        {
+            SimulationState::RUNNING => "running",
+            SimulationState::PAUSED => "paused",
+            SimulationState::STOP => "stop",
+            SimulationState::EXIT => "error",
+        };
+        lines.push(LineResponse {
+            config: simConfigManager(&line, false, None),
+            state: String::from(state),
+            simulatedTime: simElapsedManager(&line, false, None),
+            line,
+        });
+    }
+
+    Ok(web::Json(lines))
+}
+
 async fn getLogo(_req: HttpRequest) -> ActixResult<NamedFile>
 {
     Ok(NamedFile::open("./data/static/eosys.png")?)
@@ -379,9 +845,41 @@ pub async fn initWebServer() -> std::io::Result<()>
             .service(getSimTimeLimit)
             .service(setSimTimer)
             .service(getSimState)
+            .service(stepSim)
+            .service(runUntil)
+            .service(setSimSnapshot)
+            .service(saveSimSnapshot)
+            .service(reloadSimConfig)
+            .service(getLines)
         })
         .disable_signals()
         .bind((local_ip().expect("IP could not be found."), port))?
         .run()
         .await
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn query(machine: &str, state: Option<&str>, produced: Option<usize>) -> RunUntilQuery
+    {
+        RunUntilQuery { machine: String::from(machine), state: state.map(String::from), produced, line: None }
+    }
+
+    #[test]
+    fn runConditionsNameAMachineOnTheLineAndAState()
+    {
+        let machineIDs = vec![String::from("machine-0")];
+        assert_eq!(parseRunCondition(&query("machine-0", Some("planned stop"), None), &machineIDs),
+                Ok(RunCondition::MachineState { machineID: String::from("machine-0"), state: OPCState::PLANNEDSTOP }));
+        assert_eq!(parseRunCondition(&query("machine-0", None, Some(10)), &machineIDs),
+                Ok(RunCondition::ProducedCount { machineID: String::from("machine-0"), count: 10 }));
+
+        assert!(parseRunCondition(&query("machine-9", Some("faulted"), None), &machineIDs).is_err());
+        assert!(parseRunCondition(&query("machine-0", Some("broken"), None), &machineIDs).is_err());
+        assert!(parseRunCondition(&query("machine-0", Some("faulted"), Some(10)), &machineIDs).is_err());
+        assert!(parseRunCondition(&query("machine-0", None, None), &machineIDs).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::fs::{File, metadata};
+
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Deserialize};
+
+use crate::factory::*;
+use crate::json::*;
+use crate::machine::*;
+use crate::scheduler::*;
+
+// Bumped whenever the saved state changes shape, eg. a field is added to Machine, so an older
+// snapshot is turned away with a clear message instead of failing partway through parsing
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+// Only the version, read first so the rest is parsed only if it has the shape expected
+#[derive(Deserialize)]
+struct SnapshotVersion
+{
+    #[serde(default)]
+    version: u32, // Snapshots from before versioning have none
+}
+
+// The full state of a running line at one instant. The config is stored alongside the state,
+// so a snapshot can be restored even after the config file it came from has been edited.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot
+{
+    pub version: u32,
+    pub config: JSONData,
+    pub seed: u64,
+    pub simTimeUs: u128,
+    pub statsStartUs: u128,
+    pub nextPollUs: u128,
+    pub machines: Vec<Machine>,
+    pub conveyors: Vec<ConveyorBelt>,
+}
+
+// Captures the state of the line at the current instant
+pub fn takeSnapshot(factory: &Factory) -> Snapshot
+{
+    let mut machines = Vec::<Machine>::new();
+    for id in factory.machineIDs.iter()
+    {
+        machines.push(factory.machines.get(id).expect("Machine ceased to exist.").borrow().clone());
+    }
+
+    let mut conveyors = Vec::<ConveyorBelt>::new();
+    for id in factory.conveyorIDs.iter()
+    {
+        conveyors.push(factory.conveyors.get(id).expect("Conveyor ceased to exist.").borrow().clone());
+    }
+
+    Snapshot {
+        version: SNAPSHOT_VERSION,
+        config: factory.config.clone(),
+        seed: factory.seed,
+        simTimeUs: factory.simTimeUs,
+        statsStartUs: factory.statsStartUs,
+        nextPollUs: factory.events.scheduledTime(&EventKind::Poll).unwrap_or(factory.simTimeUs + factory.pollRateUs),
+        machines,
+        conveyors,
+    }
+}
+
+// Writes the state of the line to a file in the data directory
+pub fn saveSnapshot(factory: &Factory, fileName: &str) -> Result<()>
+{
+    serde_json::to_writer(File::create(dataPath(fileName))?, &takeSnapshot(factory))?;
+    tracing::info!("Saved snapshot at {} us to {}.", factory.simTimeUs, fileName);
+
+    Ok(())
+}
+
+// Rebuilds a line from a snapshot file in the data directory, ready to continue
+// from the exact instant the snapshot was taken
+pub fn loadSnapshot(fileName: &str) -> Result<Factory>
+{
+    let snapshotPath = dataPath(fileName);
+    if let Err(_e) = metadata(&snapshotPath)
+    {
+        return Err(anyhow!("{} does not exist.", snapshotPath));
+    }
+
+    let snapshot = parseSnapshot(&read_json_file(&snapshotPath), fileName)?;
+    let factory = restoreSnapshot(snapshot, fileName)?;
+
+    tracing::info!("Restored snapshot {} at {} us.", fileName, factory.simTimeUs);
+
+    Ok(factory)
+}
+
+// Reads a snapshot saved by saveSnapshot, named in errors by fileName
+pub fn parseSnapshot(json: &str, fileName: &str) -> Result<Snapshot>
+{
+    let version = serde_json::from_str::<SnapshotVersion>(json)?.version;
+    if version != SNAPSHOT_VERSION
+    {
+        return Err(anyhow!("{} is snapshot version {}, this build reads version {}. Save it again from a run of this build.",
+                fileName, version, SNAPSHOT_VERSION));
+    }
+
+    Ok(serde_json::from_str(json)?)
+}
+
+// Rebuilds a line from a snapshot, named in errors by fileName
+pub fn restoreSnapshot(snapshot: Snapshot, fileName: &str) -> Result<Factory>
+{
+    // Build the line from the stored config first, which sets up the behaviors that
+    // could not be saved, then lay the saved state over the top of it
+    let mut config = snapshot.config;
+    config.factory.seed = Some(snapshot.seed);
+    checkFactoryData(&config).map_err(|error| anyhow!("{}: {}", fileName, error))?;
+    let mut factory = factorySetup(config);
+
+    if snapshot.machines.len() != factory.machineIDs.len() || snapshot.conveyors.len() != factory.conveyorIDs.len()
+    {
+        return Err(anyhow!("{} does not match the config stored inside it.", fileName));
+    }
+
+    for mut savedMachine in snapshot.machines
+    {
+        let mut machine = factory.machines.get(&savedMachine.id)
+                .ok_or_else(|| anyhow!("Machine {} is not in the stored config.", savedMachine.id))?
+                .borrow_mut();
+        savedMachine.inputBehavior = machine.inputBehavior;
+        savedMachine.processingBehavior = machine.processingBehavior;
+        savedMachine.outputBehavior = machine.outputBehavior;
+        *machine = savedMachine;
+    }
+
+    for savedConveyor in snapshot.conveyors
+    {
+        let mut conveyor = factory.conveyors.get(&savedConveyor.id)
+                .ok_or_else(|| anyhow!("Conveyor {} is not in the stored config.", savedConveyor.id))?
+                .borrow_mut();
+        *conveyor = savedConveyor;
+    }
+
+    // The snapshot was taken between events, so settling only reschedules what was in progress
+    factory.simTimeUs = snapshot.simTimeUs;
+    factory.statsStartUs = snapshot.statsStartUs;
+    factory.restartEvents(snapshot.nextPollUs);
+
+    Ok(factory)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn exampleConfig() -> JSONData
+    {
+        let mut data: JSONData = serde_json::from_str(include_str!("../data/factory.json")).expect("Example config does not parse.");
+        data.factory.seed = Some(7);
+        data
+    }
+
+    fn statistics(factory: &Factory) -> String
+    {
+        serde_json::to_string(&factory.statistics()).expect("Statistics do not serialize.")
+    }
+
+    #[test]
+    fn resumingASnapshotMatchesAnUnbrokenRun()
+    {
+        let minuteUs = 60 * 1000000;
+        let mut unbroken = factorySetup(exampleConfig());
+        unbroken.runUntil(30 * minuteUs, |_, _| false);
+
+        // Stop part way, through JSON like a snapshot file, then carry on to the same end
+        let mut first = factorySetup(exampleConfig());
+        first.runUntil(13 * minuteUs, |_, _| false);
+        let saved = serde_json::to_string(&takeSnapshot(&first)).expect("Snapshot does not serialize.");
+        let snapshot = parseSnapshot(&saved, "test").expect("Snapshot does not parse.");
+        let mut resumed = restoreSnapshot(snapshot, "test").expect("Snapshot does not restore.");
+        resumed.runUntil(30 * minuteUs, |_, _| false);
+
+        assert_eq!(statistics(&unbroken), statistics(&resumed));
+    }
+
+    #[test]
+    fn unversionedSnapshotIsRejected()
+    {
+        let mut saved = serde_json::to_value(takeSnapshot(&factorySetup(exampleConfig()))).expect("Snapshot does not serialize.");
+        saved.as_object_mut().expect("Snapshot is not an object.").remove("version");
+
+        assert!(parseSnapshot(&saved.to_string(), "test").is_err());
+    }
+
+    #[test]
+    fn snapshotOfADifferentLineIsRejected()
+    {
+        let factory = factorySetup(exampleConfig());
+        let mut snapshot = takeSnapshot(&factory);
+        snapshot.machines.pop();
+
+        assert!(restoreSnapshot(snapshot, "test").is_err());
+    }
+
+    #[test]
+    fn snapshotWithAnImpossibleConfigIsRejected()
+    {
+        let mut snapshot = takeSnapshot(&factorySetup(exampleConfig()));
+        snapshot.config.factory.startDateTime = Some(String::from("2026-02-30T06:00:00"));
+
+        assert!(restoreSnapshot(snapshot, "test").is_err());
+    }
+}